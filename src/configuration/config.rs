@@ -1,9 +1,11 @@
 use std::mem;
 use std::net::{IpAddr, SocketAddr};
 use std::sync::atomic::Ordering;
+use std::sync::mpsc::SyncSender;
 use std::sync::{Arc, Mutex, MutexGuard};
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, Instant, SystemTime};
 
+use rand::seq::SliceRandom;
 use x25519_dalek::{PublicKey, StaticSecret};
 
 use super::udp::Owner;
@@ -20,19 +22,53 @@ use super::*;
 pub struct PeerState {
     pub rx_bytes: u64,
     pub tx_bytes: u64,
+    pub rx_packets: u64, // transport messages, keepalives included
+    pub tx_packets: u64, // transport messages, keepalives included
     pub last_handshake_time: Option<(u64, u64)>,
     pub public_key: PublicKey,
     pub allowed_ips: Vec<(IpAddr, u32)>,
     pub endpoint: Option<SocketAddr>,
     pub persistent_keepalive_interval: u64,
     pub preshared_key: [u8; 32], // 0^32 is the "default value" (though treated like any other psk)
+    pub session_initiator: Option<bool>, // was the handshake of the current session initiated by us
+    pub session_age: Option<Duration>, // age of the current session
+    pub handshake_failed: bool, // no handshake completed within REKEY_ATTEMPT_TIME of the first attempt
+    pub outbound_queue_depth: usize, // number of transport messages queued for transmission
+    pub outbound_drops: u64,    // number of transport messages dropped due to a full queue
+    pub replays: u64,           // number of transport messages rejected as replays
 }
 
+impl From<PeerState> for PeerConfig {
+    fn from(state: PeerState) -> PeerConfig {
+        PeerConfig {
+            public_key: state.public_key,
+            preshared_key: state.preshared_key,
+            endpoint: state.endpoint,
+            persistent_keepalive_interval: state.persistent_keepalive_interval,
+            allowed_ips: state.allowed_ips,
+        }
+    }
+}
+
+/// Describes the overall health of the device (e.g. for liveness probes)
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct HealthSummary {
+    pub peers: usize,             // number of configured peers
+    pub connected_peers: usize,   // peers with a session which can be used for sending
+    pub recent_handshakes: usize, // peers with a handshake within the last HEALTHY_HANDSHAKE_AGE
+    pub rx_rate: u64,             // received bytes per second (requires the stats history)
+    pub tx_rate: u64,             // transmitted bytes per second (requires the stats history)
+}
+
+// sessions established by older handshakes have expired (REJECT_AFTER_TIME)
+const HEALTHY_HANDSHAKE_AGE: Duration = Duration::from_secs(180);
+
 pub struct WireGuardConfig<T: tun::Tun, B: udp::PlatformUDP>(Arc<Mutex<Inner<T, B>>>);
 
 struct Inner<T: tun::Tun, B: udp::PlatformUDP> {
     wireguard: WireGuard<T, B>,
     port: u16,
+    port_range: Option<(u16, u16)>,
     bind: Option<B::Owner>,
     fwmark: Option<u32>,
 }
@@ -48,6 +84,7 @@ impl<T: tun::Tun, B: udp::PlatformUDP> WireGuardConfig<T, B> {
         WireGuardConfig(Arc::new(Mutex::new(Inner {
             wireguard: wg,
             port: 0,
+            port_range: None,
             bind: None,
             fwmark: None,
         })))
@@ -80,6 +117,13 @@ pub trait Configuration {
     /// The private if set, otherwise None.
     fn get_private_key(&self) -> Option<StaticSecret>;
 
+    /// Returns the public key of the device
+    ///
+    /// # Returns
+    ///
+    /// The public key corresponding to the private key if set, otherwise None.
+    fn get_public_key(&self) -> Option<PublicKey>;
+
     /// Returns the protocol version of the device
     ///
     /// # Returns
@@ -89,6 +133,50 @@ pub trait Configuration {
 
     fn set_listen_port(&self, port: u16) -> Result<(), ConfigError>;
 
+    /// Select the Noise construction (and hence the AEAD) used for new handshakes
+    ///
+    /// # Arguments
+    ///
+    /// - `name`: The name of the construction, e.g. "Noise_IKpsk2_25519_AESGCM_BLAKE2s"
+    ///
+    /// # Returns
+    ///
+    /// An error if the construction is not supported
+    fn set_construction(&self, name: &str) -> Result<(), ConfigError>;
+
+    /// Returns the name of the Noise construction used for new handshakes
+    fn get_construction(&self) -> &'static str;
+
+    /// Select the strategy used to allocate the (receiver) indexes of new handshakes
+    ///
+    /// # Arguments
+    ///
+    /// - `strategy`: Random (the default) or Sequential (predictable, for debugging)
+    fn set_index_strategy(&self, strategy: IndexStrategy);
+
+    /// Set the size of the replay window of sessions established from now on
+    ///
+    /// # Arguments
+    ///
+    /// - `bitlen`: The number of bits in the window (a power of two between 128 and 65536),
+    ///    larger windows tolerate more reordering at the cost of memory per session.
+    ///
+    /// # Returns
+    ///
+    /// An error if the size is not supported
+    fn set_replay_window(&self, bitlen: usize) -> Result<(), ConfigError>;
+
+    /// Listen on a random port within a range (instead of a fixed listen port)
+    ///
+    /// # Arguments
+    ///
+    /// - `range`: The (inclusive) range of ports, None reverts to the listen port
+    ///
+    /// # Returns
+    ///
+    /// An error if the device is up and no port in the range could be bound.
+    fn set_listen_port_range(&self, range: Option<(u16, u16)>) -> Result<(), ConfigError>;
+
     /// Set the firewall mark (or similar, depending on platform)
     ///
     /// # Arguments
@@ -104,6 +192,43 @@ pub trait Configuration {
     /// Removes all peers from the device
     fn replace_peers(&self);
 
+    /// Replace the set of peers by the configuration (similar to "wg syncconf")
+    ///
+    /// # Arguments
+    ///
+    /// - `peers`: The configuration of every peer
+    ///
+    /// Peers absent from the configuration are removed,
+    /// existing peers retain their sessions unless their preshared key changed.
+    fn apply_config(&self, peers: &[PeerConfig]);
+
+    /// Export the sessions of every peer, for hand-off to another instance (e.g. during an upgrade)
+    ///
+    /// The exported sessions are no longer used by this instance for sending.
+    ///
+    /// # Returns
+    ///
+    /// The serialized sessions (zeroed on drop)
+    fn export_sessions(&self) -> Sessions;
+
+    /// Import the sessions exported by another instance
+    ///
+    /// # Arguments
+    ///
+    /// - `sessions`: The serialized sessions
+    ///
+    /// # Returns
+    ///
+    /// The number of peers for which a session was imported
+    fn import_sessions(&self, sessions: &[u8]) -> usize;
+
+    /// Check that the private key and the configured peers are consistent
+    ///
+    /// # Returns
+    ///
+    /// Every inconsistency found, see the validate function
+    fn validate(&self) -> Result<(), Vec<ConfigError>>;
+
     /// Remove the peer from the
     ///
     /// # Arguments
@@ -140,6 +265,22 @@ pub trait Configuration {
     /// An error if no such peer exists
     fn set_preshared_key(&self, peer: &PublicKey, psk: [u8; 32]);
 
+    /// Replace the psk of the peer while still accepting the previous psk
+    ///
+    /// # Arguments
+    ///
+    /// - `peer`: The public key of the peer
+    /// - `psk`: The new psk
+    /// - `window`: How long handshakes using the previous psk are accepted
+    fn rotate_preshared_key(&self, peer: &PublicKey, psk: [u8; 32], window: Duration);
+
+    /// Set (or unset) the source of preshared keys
+    ///
+    /// # Arguments
+    ///
+    /// - `provider`: Polled for the latest psk of a peer before every handshake
+    fn set_psk_provider(&self, provider: Option<Arc<dyn PskProvider>>);
+
     /// Update the endpoint of the
     ///
     /// # Arguments
@@ -148,6 +289,19 @@ pub trait Configuration {
     /// - `psk`
     fn set_endpoint(&self, peer: &PublicKey, addr: SocketAddr);
 
+    /// Set the endpoint of the peer (e.g. learned out-of-band)
+    /// and initiate a handshake with the new endpoint if the peer has no session
+    ///
+    /// # Arguments
+    ///
+    /// - `peer`: The public key of the peer
+    /// - `addr`: The new endpoint
+    ///
+    /// # Returns
+    ///
+    /// False if the peer does not exist
+    fn connect(&self, peer: &PublicKey, addr: SocketAddr) -> bool;
+
     /// Update the endpoint of the
     ///
     /// # Arguments
@@ -156,6 +310,22 @@ pub trait Configuration {
     /// - `psk`
     fn set_persistent_keepalive_interval(&self, peer: &PublicKey, secs: u64);
 
+    /// Pace the transmission of transport messages to the peer (smoothing bursts)
+    ///
+    /// # Arguments
+    ///
+    /// - `peer`: The public key of the peer
+    /// - `rate`: The maximum number of messages per second, None (or zero) disables pacing
+    fn set_pacing(&self, peer: &PublicKey, rate: Option<u32>);
+
+    /// Restrict the IP protocols carried by the peer (in both directions)
+    ///
+    /// # Arguments
+    ///
+    /// - `peer`: The public key of the peer
+    /// - `protocols`: The allowed IP protocol numbers (e.g. 6 for TCP), None allows every protocol
+    fn set_allowed_protocols(&self, peer: &PublicKey, protocols: Option<Vec<u8>>);
+
     /// Remove all allowed IPs from the peer
     ///
     /// # Arguments
@@ -180,6 +350,40 @@ pub trait Configuration {
     /// An error if the peer does not exist
     fn add_allowed_ip(&self, peer: &PublicKey, ip: IpAddr, masklen: u32);
 
+    /// Replace the allowed subnets of the peer
+    ///
+    /// The update is atomic: traffic is routed by either the old or the new set.
+    ///
+    /// # Arguments
+    ///
+    /// - `peer`: The public key of the peer
+    /// - `ips`: The new set of subnets (ip, masklen)
+    fn set_allowed_ips(&self, peer: &PublicKey, ips: &[(IpAddr, u32)]);
+
+    /// Enable (or disable) responding to packets without a matching peer
+    /// with an ICMP (or ICMPv6) "destination unreachable" error,
+    /// causing local applications to fail fast rather than time out.
+    fn set_icmp_unreachable(&self, enabled: bool);
+
+    /// Only accept handshake initiations from the source IP of a configured peer endpoint
+    ///
+    /// Intended for fixed-topology deployments where the endpoint of every peer is known:
+    /// while enabled, peers without an endpoint cannot initiate handshakes.
+    fn set_endpoint_filter(&self, enabled: bool);
+
+    /// Enable (or disable) sampling of the byte counters of every peer,
+    /// the retained samples determine the traffic rates reported by get_health.
+    fn set_stats_history(&self, enabled: bool);
+
+    /// Register an observer receiving a copy of every decrypted packet
+    ///
+    /// # Arguments
+    ///
+    /// - `sink`: The sending half of the channel to the observer,
+    ///    packets are dropped for the observer while the channel is full.
+    ///    The observer is removed once the receiving half is dropped.
+    fn add_observer(&self, sink: SyncSender<Vec<u8>>);
+
     fn get_listen_port(&self) -> Option<u16>;
 
     /// Returns the state of all peers
@@ -189,7 +393,153 @@ pub trait Configuration {
     /// A list of structures describing the state of each peer
     fn get_peers(&self) -> Vec<PeerState>;
 
+    /// Returns the state of a single peer
+    ///
+    /// # Arguments
+    ///
+    /// - `peer`: The public key of the peer
+    ///
+    /// # Returns
+    ///
+    /// The structure describing the state of the peer, or None if the peer does not exist
+    fn get_peer(&self, peer: &PublicKey) -> Option<PeerState>;
+
+    /// Check if the tunnel to the peer is "up"
+    ///
+    /// # Returns
+    ///
+    /// True if the peer exists and has a confirmed, non-expired session
+    fn is_peer_connected(&self, peer: &PublicKey) -> bool;
+
+    /// Returns the time until the current session with the peer expires
+    ///
+    /// # Returns
+    ///
+    /// None if the peer does not exist or has no current session
+    fn get_session_remaining(&self, peer: &PublicKey) -> Option<Duration>;
+
+    /// Returns the number of messages of each type received and sent by the device
+    ///
+    /// # Returns
+    ///
+    /// A pair of (received, sent) counters,
+    /// indexed by message type - 1 (initiation, response, cookie reply, transport)
+    fn get_message_counters(&self) -> ([u64; 4], [u64; 4]);
+
+    /// Returns the number of handshake messages dropped due to an invalid mac1
+    /// (e.g. messages not intended for our public key)
+    fn get_invalid_mac1_drops(&self) -> u64;
+
+    /// Returns the number of handshake messages dropped due to a full handshake queue
+    fn get_handshake_queue_drops(&self) -> u64;
+
+    /// Returns the number of encrypted transport messages awaiting transmission over UDP
+    /// (the depth of the queue of every peer is reported by get_peers)
+    fn get_egress_queue_depth(&self) -> usize;
+
+    /// Returns a summary of the state of all peers
+    ///
+    /// # Returns
+    ///
+    /// The number of (connected) peers and the aggregate traffic rates,
+    /// the rates are computed from the stats history (zero when disabled).
+    fn get_health(&self) -> HealthSummary;
+
     fn get_fwmark(&self) -> Option<u32>;
+
+    /// Returns the (receiver) ids allocated by the device
+    ///
+    /// # Returns
+    ///
+    /// A list of (id, public key of owning peer, key-wheel slot) triples
+    #[cfg(feature = "debug_uapi")]
+    fn get_ids(&self) -> Vec<(u32, PublicKey, &'static str)>;
+}
+
+// the subnet (network address) containing the ip
+fn subnet(ip: &IpAddr, masklen: u32) -> IpAddr {
+    match ip {
+        IpAddr::V4(ip) => {
+            let mask = (!0u32).checked_shl(32u32.saturating_sub(masklen));
+            IpAddr::V4((u32::from(*ip) & mask.unwrap_or(0)).into())
+        }
+        IpAddr::V6(ip) => {
+            let mask = (!0u128).checked_shl(128u32.saturating_sub(masklen));
+            IpAddr::V6((u128::from(*ip) & mask.unwrap_or(0)).into())
+        }
+    }
+}
+
+/// Check that the private key and peers form a consistent configuration:
+///
+/// - A private key is set.
+/// - The public key of every peer is distinct from our own and from every other peer.
+/// - No subnet is allowed for multiple peers (only one peer can receive its traffic).
+/// - Every peer with a persistent keepalive has an endpoint.
+///
+/// # Returns
+///
+/// A list of every inconsistency found (empty if the configuration is consistent)
+pub fn validate(sk: Option<&StaticSecret>, peers: &[PeerConfig]) -> Vec<ConfigError> {
+    let mut errors = vec![];
+    let pk = sk.map(PublicKey::from);
+    if pk.is_none() {
+        errors.push(ConfigError::MissingPrivateKey);
+    }
+
+    let mut keys: Vec<[u8; 32]> = Vec::with_capacity(peers.len());
+    let mut subnets: Vec<(IpAddr, u32)> = vec![];
+    for peer in peers {
+        // check public key
+        if pk.map(|pk| pk.as_bytes() == peer.public_key.as_bytes()) == Some(true) {
+            errors.push(ConfigError::PeerIsSelf);
+        }
+        if keys.contains(peer.public_key.as_bytes()) {
+            errors.push(ConfigError::DuplicatePeer);
+        }
+        keys.push(*peer.public_key.as_bytes());
+
+        // check allowed ips
+        for (ip, masklen) in peer.allowed_ips.iter() {
+            let net = (subnet(ip, *masklen), *masklen);
+            if subnets.contains(&net) {
+                errors.push(ConfigError::ConflictingAllowedIp);
+            } else {
+                subnets.push(net);
+            }
+        }
+
+        // check keepalive
+        if peer.persistent_keepalive_interval > 0 && peer.endpoint.is_none() {
+            errors.push(ConfigError::KeepaliveWithoutEndpoint);
+        }
+    }
+    errors
+}
+
+/* Bind to a random port within the (inclusive) range,
+ * every port is tried (in random order) before giving up.
+ */
+fn bind_range<B: udp::PlatformUDP>(
+    min: u16,
+    max: u16,
+) -> Result<(Vec<B::Reader>, B::Writer, B::Owner), ConfigError> {
+    // port 0 would bind to an arbitrary port outside the range
+    if min == 0 || min > max {
+        return Err(ConfigError::InvalidPortNumber);
+    }
+
+    let mut ports: Vec<u16> = (min..=max).collect();
+    ports.shuffle(&mut rand::thread_rng());
+    for port in ports {
+        match B::bind(port) {
+            Ok(r) => return Ok(r),
+            Err(e) => log::debug!("failed to bind port {}: {}", port, e),
+        }
+    }
+
+    log::info!("no port available in range {}-{}", min, max);
+    Err(ConfigError::FailedToBind)
 }
 
 fn start_listener<T: tun::Tun, B: udp::PlatformUDP>(
@@ -198,11 +548,14 @@ fn start_listener<T: tun::Tun, B: udp::PlatformUDP>(
     cfg.bind = None;
 
     // create new listener
-    let (mut readers, writer, mut owner) = match B::bind(cfg.port) {
-        Ok(r) => r,
-        Err(_) => {
-            return Err(ConfigError::FailedToBind);
-        }
+    let (mut readers, writer, mut owner) = match cfg.port_range {
+        Some((min, max)) => bind_range::<B>(min, max)?,
+        None => match B::bind(cfg.port) {
+            Ok(r) => r,
+            Err(_) => {
+                return Err(ConfigError::FailedToBind);
+            }
+        },
     };
 
     // set fwmark
@@ -240,6 +593,27 @@ impl<T: tun::Tun, B: udp::PlatformUDP> Configuration for WireGuardConfig<T, B> {
         self.lock().fwmark
     }
 
+    fn get_message_counters(&self) -> ([u64; 4], [u64; 4]) {
+        self.lock().wireguard.message_counters()
+    }
+
+    fn get_invalid_mac1_drops(&self) -> u64 {
+        self.lock().wireguard.invalid_mac1_drops()
+    }
+
+    fn get_handshake_queue_drops(&self) -> u64 {
+        self.lock().wireguard.handshake_queue_drops()
+    }
+
+    fn get_egress_queue_depth(&self) -> usize {
+        self.lock().wireguard.egress_queue_depth()
+    }
+
+    #[cfg(feature = "debug_uapi")]
+    fn get_ids(&self) -> Vec<(u32, PublicKey, &'static str)> {
+        self.lock().wireguard.list_ids()
+    }
+
     fn set_private_key(&self, sk: Option<StaticSecret>) {
         log::info!("configuration, set private key");
         self.lock().wireguard.set_key(sk)
@@ -249,6 +623,10 @@ impl<T: tun::Tun, B: udp::PlatformUDP> Configuration for WireGuardConfig<T, B> {
         self.lock().wireguard.get_sk()
     }
 
+    fn get_public_key(&self) -> Option<PublicKey> {
+        self.lock().wireguard.get_pk()
+    }
+
     fn get_protocol_version(&self) -> usize {
         1
     }
@@ -267,6 +645,53 @@ impl<T: tun::Tun, B: udp::PlatformUDP> Configuration for WireGuardConfig<T, B> {
         let bound: bool = {
             let old = mem::replace(&mut cfg.bind, None);
             cfg.port = port;
+            cfg.port_range = None;
+            old.is_some()
+        };
+
+        // restart listener if bound
+        if bound {
+            start_listener(cfg)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn set_construction(&self, name: &str) -> Result<(), ConfigError> {
+        log::info!("configuration, set construction: {}", name);
+        if self.lock().wireguard.set_construction(name) {
+            Ok(())
+        } else {
+            Err(ConfigError::UnsupportedValue)
+        }
+    }
+
+    fn get_construction(&self) -> &'static str {
+        self.lock().wireguard.get_construction()
+    }
+
+    fn set_index_strategy(&self, strategy: IndexStrategy) {
+        log::info!("configuration, set index strategy: {:?}", strategy);
+        self.lock().wireguard.set_index_strategy(strategy);
+    }
+
+    fn set_replay_window(&self, bitlen: usize) -> Result<(), ConfigError> {
+        log::info!("configuration, set replay window: {}", bitlen);
+        if self.lock().wireguard.set_replay_window(bitlen) {
+            Ok(())
+        } else {
+            Err(ConfigError::UnsupportedValue)
+        }
+    }
+
+    fn set_listen_port_range(&self, range: Option<(u16, u16)>) -> Result<(), ConfigError> {
+        log::trace!("Config, Set listen port range: {:?}", range);
+
+        // update range and take old bind
+        let mut cfg = self.lock();
+        let bound: bool = {
+            let old = mem::replace(&mut cfg.bind, None);
+            cfg.port_range = range;
             old.is_some()
         };
 
@@ -296,6 +721,28 @@ impl<T: tun::Tun, B: udp::PlatformUDP> Configuration for WireGuardConfig<T, B> {
         self.lock().wireguard.clear_peers();
     }
 
+    fn apply_config(&self, peers: &[PeerConfig]) {
+        self.lock().wireguard.apply_config(peers);
+    }
+
+    fn export_sessions(&self) -> Sessions {
+        self.lock().wireguard.serialize_sessions()
+    }
+
+    fn import_sessions(&self, sessions: &[u8]) -> usize {
+        self.lock().wireguard.import_sessions(sessions)
+    }
+
+    fn validate(&self) -> Result<(), Vec<ConfigError>> {
+        let peers: Vec<PeerConfig> = self.get_peers().into_iter().map(PeerConfig::from).collect();
+        let errors = validate(self.get_private_key().as_ref(), &peers[..]);
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
     fn remove_peer(&self, peer: &PublicKey) {
         self.lock().wireguard.remove_peer(peer);
     }
@@ -308,10 +755,20 @@ impl<T: tun::Tun, B: udp::PlatformUDP> Configuration for WireGuardConfig<T, B> {
         self.lock().wireguard.set_psk(*peer, psk);
     }
 
+    fn rotate_preshared_key(&self, peer: &PublicKey, psk: [u8; 32], window: Duration) {
+        self.lock().wireguard.rotate_psk(*peer, psk, window);
+    }
+
+    fn set_psk_provider(&self, provider: Option<Arc<dyn PskProvider>>) {
+        self.lock().wireguard.set_psk_provider(provider);
+    }
+
     fn set_endpoint(&self, peer: &PublicKey, addr: SocketAddr) {
-        if let Some(peer) = self.lock().wireguard.lookup_peer(peer) {
-            peer.router.set_endpoint(B::Endpoint::from_address(addr));
-        }
+        self.lock().wireguard.set_endpoint(peer, addr, false);
+    }
+
+    fn connect(&self, peer: &PublicKey, addr: SocketAddr) -> bool {
+        self.lock().wireguard.set_endpoint(peer, addr, true)
     }
 
     fn set_persistent_keepalive_interval(&self, peer: &PublicKey, secs: u64) {
@@ -320,6 +777,16 @@ impl<T: tun::Tun, B: udp::PlatformUDP> Configuration for WireGuardConfig<T, B> {
         }
     }
 
+    fn set_pacing(&self, peer: &PublicKey, rate: Option<u32>) {
+        self.lock().wireguard.set_pacing(peer, rate);
+    }
+
+    fn set_allowed_protocols(&self, peer: &PublicKey, protocols: Option<Vec<u8>>) {
+        if let Some(peer) = self.lock().wireguard.lookup_peer(peer) {
+            peer.router.set_allowed_protocols(protocols);
+        }
+    }
+
     fn replace_allowed_ips(&self, peer: &PublicKey) {
         if let Some(peer) = self.lock().wireguard.lookup_peer(peer) {
             peer.router.remove_allowed_ips();
@@ -332,34 +799,378 @@ impl<T: tun::Tun, B: udp::PlatformUDP> Configuration for WireGuardConfig<T, B> {
         }
     }
 
+    fn set_allowed_ips(&self, peer: &PublicKey, ips: &[(IpAddr, u32)]) {
+        if let Some(peer) = self.lock().wireguard.lookup_peer(peer) {
+            peer.router.set_allowed_ips(ips);
+        }
+    }
+
+    fn set_icmp_unreachable(&self, enabled: bool) {
+        self.lock().wireguard.set_icmp_unreachable(enabled);
+    }
+
+    fn set_endpoint_filter(&self, enabled: bool) {
+        self.lock().wireguard.set_endpoint_filter(enabled);
+    }
+
+    fn set_stats_history(&self, enabled: bool) {
+        self.lock().wireguard.set_stats_history(enabled);
+    }
+
+    fn add_observer(&self, sink: SyncSender<Vec<u8>>) {
+        self.lock().wireguard.add_observer(sink);
+    }
+
+    fn get_health(&self) -> HealthSummary {
+        let cfg = self.lock();
+        let now = SystemTime::now();
+        let mut health: HealthSummary = Default::default();
+
+        for p in cfg.wireguard.list_peers() {
+            health.peers += 1;
+
+            if p.router.has_session() {
+                health.connected_peers += 1;
+            }
+
+            let recent = (*p.walltime_last_handshake.lock())
+                .and_then(|t| now.duration_since(t).ok())
+                .map(|age| age < HEALTHY_HANDSHAKE_AGE)
+                .unwrap_or(false);
+            if recent {
+                health.recent_handshakes += 1;
+            }
+
+            // average rates over the retained samples
+            let history = cfg.wireguard.history(&p.pk);
+            if let (Some(first), Some(last)) = (history.first(), history.last()) {
+                let secs = last.0.duration_since(first.0).as_secs_f64();
+                if secs > 0.0 {
+                    health.rx_rate += (last.1.saturating_sub(first.1) as f64 / secs) as u64;
+                    health.tx_rate += (last.2.saturating_sub(first.2) as f64 / secs) as u64;
+                }
+            }
+        }
+        health
+    }
+
     fn get_peers(&self) -> Vec<PeerState> {
         let cfg = self.lock();
-        let peers = cfg.wireguard.list_peers();
-        let mut state = Vec::with_capacity(peers.len());
-
-        for p in peers {
-            // convert the system time to (secs, nano) since epoch
-            let last_handshake_time = (*p.walltime_last_handshake.lock()).and_then(|t| {
-                let duration = t
-                    .duration_since(SystemTime::UNIX_EPOCH)
-                    .unwrap_or(Duration::from_secs(0));
-                Some((duration.as_secs(), duration.subsec_nanos() as u64))
-            });
-
-            if let Some(psk) = cfg.wireguard.get_psk(&p.pk) {
-                // extract state into PeerState
-                state.push(PeerState {
-                    preshared_key: psk,
-                    endpoint: p.router.get_endpoint(),
-                    rx_bytes: p.rx_bytes.load(Ordering::Relaxed),
-                    tx_bytes: p.tx_bytes.load(Ordering::Relaxed),
-                    persistent_keepalive_interval: p.get_keepalive_interval(),
-                    allowed_ips: p.router.list_allowed_ips(),
-                    last_handshake_time,
-                    public_key: p.pk,
-                })
+        cfg.wireguard
+            .list_peers()
+            .iter()
+            .filter_map(|p| peer_state(&cfg.wireguard, p))
+            .collect()
+    }
+
+    fn get_peer(&self, peer: &PublicKey) -> Option<PeerState> {
+        let cfg = self.lock();
+        let p = cfg.wireguard.lookup_peer(peer)?;
+        peer_state(&cfg.wireguard, &p)
+    }
+
+    fn is_peer_connected(&self, peer: &PublicKey) -> bool {
+        self.lock().wireguard.is_peer_connected(peer)
+    }
+
+    fn get_session_remaining(&self, peer: &PublicKey) -> Option<Duration> {
+        self.lock().wireguard.session_remaining(peer)
+    }
+}
+
+// extract a snapshot of the state of the peer
+fn peer_state<T: tun::Tun, B: udp::PlatformUDP>(
+    wg: &WireGuard<T, B>,
+    p: &Peer<T, B>,
+) -> Option<PeerState> {
+    // convert the system time to (secs, nano) since epoch
+    let last_handshake_time = (*p.walltime_last_handshake.lock()).and_then(|t| {
+        let duration = t
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or(Duration::from_secs(0));
+        Some((duration.as_secs(), duration.subsec_nanos() as u64))
+    });
+
+    let psk = wg.get_psk(&p.pk)?;
+    Some(PeerState {
+        preshared_key: psk,
+        endpoint: p.router.get_endpoint(),
+        rx_bytes: p.rx_bytes.load(Ordering::Relaxed),
+        tx_bytes: p.tx_bytes.load(Ordering::Relaxed),
+        rx_packets: p.rx_packets.load(Ordering::Relaxed),
+        tx_packets: p.tx_packets.load(Ordering::Relaxed),
+        persistent_keepalive_interval: p.get_keepalive_interval(),
+        allowed_ips: p.router.list_allowed_ips(),
+        last_handshake_time,
+        session_initiator: p.router.session_initiator(),
+        session_age: p.router.session_age(Instant::now()),
+        handshake_failed: p.handshake_failed(),
+        outbound_queue_depth: p.router.outbound_queue_depth(),
+        outbound_drops: p.router.outbound_drops(),
+        replays: p.router.replays(),
+        public_key: p.pk,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer(sk: u8, endpoint: Option<&str>, keepalive: u64, ips: &[(&str, u32)]) -> PeerConfig {
+        PeerConfig {
+            public_key: PublicKey::from(&StaticSecret::from([sk; 32])),
+            preshared_key: [0u8; 32],
+            endpoint: endpoint.map(|e| e.parse().unwrap()),
+            persistent_keepalive_interval: keepalive,
+            allowed_ips: ips
+                .iter()
+                .map(|(ip, m)| (ip.parse().unwrap(), *m))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        let sk = StaticSecret::from([0x1; 32]);
+
+        // a consistent configuration (overlapping, but distinct, subnets are fine)
+        let peers = [
+            peer(0x2, Some("127.0.0.1:51820"), 25, &[("0.0.0.0", 0)]),
+            peer(0x3, None, 0, &[("10.0.0.0", 24), ("fd00::", 64)]),
+        ];
+        assert!(validate(Some(&sk), &peers).is_empty());
+
+        // missing private key
+        let errors = validate(None, &peers);
+        assert_eq!(errors.len(), 1, "errors: {:?}", errors);
+        assert!(match errors[0] {
+            ConfigError::MissingPrivateKey => true,
+            _ => false,
+        });
+
+        // peer matching our own public key
+        let errors = validate(Some(&sk), &[peer(0x1, None, 0, &[])]);
+        assert_eq!(errors.len(), 1, "errors: {:?}", errors);
+        assert!(match errors[0] {
+            ConfigError::PeerIsSelf => true,
+            _ => false,
+        });
+
+        // duplicate peer
+        let errors = validate(
+            Some(&sk),
+            &[peer(0x2, None, 0, &[]), peer(0x2, None, 0, &[])],
+        );
+        assert_eq!(errors.len(), 1, "errors: {:?}", errors);
+        assert!(match errors[0] {
+            ConfigError::DuplicatePeer => true,
+            _ => false,
+        });
+
+        // the same subnet (after masking) allowed for two peers
+        let errors = validate(
+            Some(&sk),
+            &[
+                peer(0x2, None, 0, &[("10.0.0.0", 24)]),
+                peer(0x3, None, 0, &[("10.0.0.1", 24)]),
+            ],
+        );
+        assert_eq!(errors.len(), 1, "errors: {:?}", errors);
+        assert!(match errors[0] {
+            ConfigError::ConflictingAllowedIp => true,
+            _ => false,
+        });
+
+        // persistent keepalive without an endpoint
+        let errors = validate(Some(&sk), &[peer(0x2, None, 25, &[])]);
+        assert_eq!(errors.len(), 1, "errors: {:?}", errors);
+        assert!(match errors[0] {
+            ConfigError::KeepaliveWithoutEndpoint => true,
+            _ => false,
+        });
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_bind_range() {
+        use crate::platform::plt;
+
+        // the chosen port lies within the range
+        let (_, _, owner) = bind_range::<plt::UDP>(41820, 41830).unwrap();
+        let port = owner.get_port();
+        assert!(
+            port >= 41820 && port <= 41830,
+            "port {} outside range",
+            port
+        );
+
+        // empty ranges (or including port 0) are rejected
+        assert!(bind_range::<plt::UDP>(41830, 41820).is_err());
+        assert!(bind_range::<plt::UDP>(0, 10).is_err());
+    }
+
+    #[test]
+    fn test_get_health() {
+        use crate::platform::dummy;
+        use crate::wireguard::dummy_keypair;
+
+        let (_fake, _reader, tun_writer, _) = dummy::TunTest::create(false);
+        let wg: WireGuard<dummy::TunTest, dummy::PairBind> = WireGuard::new(tun_writer);
+
+        let pk1 = PublicKey::from(&StaticSecret::from([0x1; 32]));
+        let pk2 = PublicKey::from(&StaticSecret::from([0x2; 32]));
+        let pk3 = PublicKey::from(&StaticSecret::from([0x3; 32]));
+        wg.add_peer(pk1);
+        wg.add_peer(pk2);
+        wg.add_peer(pk3);
+
+        // a connected peer (with traffic)
+        let peer1 = wg.lookup_peer(&pk1).unwrap();
+        peer1.router.add_keypair(dummy_keypair(true));
+        *peer1.walltime_last_handshake.lock() = Some(SystemTime::now());
+
+        let start = Instant::now();
+        peer1.sample_history(start);
+        peer1.rx_bytes.fetch_add(1000, Ordering::Relaxed);
+        peer1.tx_bytes.fetch_add(2000, Ordering::Relaxed);
+        peer1.sample_history(start + Duration::from_secs(10));
+
+        // a peer whose session expired long ago
+        let peer2 = wg.lookup_peer(&pk2).unwrap();
+        *peer2.walltime_last_handshake.lock() = Some(SystemTime::now() - Duration::from_secs(600));
+
+        // the third peer never completed a handshake
+
+        let cfg = WireGuardConfig::new(wg);
+        assert_eq!(
+            cfg.get_health(),
+            HealthSummary {
+                peers: 3,
+                connected_peers: 1,
+                recent_handshakes: 1,
+                rx_rate: 100,
+                tx_rate: 200,
             }
+        );
+    }
+
+    #[test]
+    fn test_get_peer() {
+        use crate::platform::dummy;
+
+        let (_fake, _reader, tun_writer, _) = dummy::TunTest::create(false);
+        let wg: WireGuard<dummy::TunTest, dummy::PairBind> = WireGuard::new(tun_writer);
+
+        let pk1 = PublicKey::from(&StaticSecret::from([0x1; 32]));
+        let pk2 = PublicKey::from(&StaticSecret::from([0x2; 32]));
+        wg.add_peer(pk1);
+
+        let peer1 = wg.lookup_peer(&pk1).unwrap();
+        peer1.rx_bytes.fetch_add(1000, Ordering::Relaxed);
+        peer1.tx_bytes.fetch_add(2000, Ordering::Relaxed);
+
+        let cfg = WireGuardConfig::new(wg);
+        let state = cfg.get_peer(&pk1).expect("no state for existing peer");
+        assert_eq!(state.public_key.as_bytes(), pk1.as_bytes());
+        assert_eq!(state.rx_bytes, 1000);
+        assert_eq!(state.tx_bytes, 2000);
+        assert_eq!(state.last_handshake_time, None);
+        assert_eq!(state.endpoint, None);
+
+        // unknown peers have no state
+        assert!(cfg.get_peer(&pk2).is_none());
+    }
+
+    #[test]
+    fn test_outbound_queue_depth() {
+        use crate::platform::dummy;
+        use crate::wireguard::dummy_keypair;
+
+        let (_fake, _reader, tun_writer, _) = dummy::TunTest::create(false);
+        let wg: WireGuard<dummy::TunTest, dummy::PairBind> = WireGuard::new(tun_writer);
+        wg.up(1500);
+
+        // the other end is never read: transmission blocks once the pair bind is full
+        let ((_bind_reader1, bind_writer1), (_bind_reader2, _bind_writer2)) =
+            dummy::PairBind::pair();
+        wg.set_writer(bind_writer1);
+
+        let pk = PublicKey::from(&StaticSecret::from([0x1; 32]));
+        wg.add_peer(pk);
+        let peer = wg.lookup_peer(&pk).unwrap();
+        peer.router.set_endpoint(dummy::UnitEndpoint::new());
+
+        // fill the pair bind (the keepalive confirming the key-pair included),
+        // such that transmission blocks on the last keepalive
+        peer.router.add_keypair(dummy_keypair(true));
+        for _ in 0..128 {
+            peer.router.send_keepalive();
         }
-        state
+        let start = Instant::now();
+        while peer.tx_packets.load(Ordering::Relaxed) < 128 || wg.egress_queue_depth() != 1 {
+            assert!(
+                start.elapsed() < Duration::from_secs(1),
+                "transmission did not block"
+            );
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        // subsequent keepalives remain queued for the peer
+        for _ in 0..10 {
+            peer.router.send_keepalive();
+        }
+
+        let cfg = WireGuardConfig::new(wg);
+        let state = cfg.get_peer(&pk).unwrap();
+        assert_eq!(state.outbound_queue_depth, 10);
+        assert_eq!(cfg.get_egress_queue_depth(), 1);
+    }
+
+    #[test]
+    fn test_get_public_key() {
+        use crate::platform::dummy;
+
+        let (_fake, _reader, tun_writer, _) = dummy::TunTest::create(false);
+        let wg: WireGuard<dummy::TunTest, dummy::PairBind> = WireGuard::new(tun_writer);
+        let cfg = WireGuardConfig::new(wg);
+        assert!(cfg.get_public_key().is_none());
+
+        // test vector from RFC 7748 (section 6.1)
+        let mut sk = [0u8; 32];
+        sk.copy_from_slice(
+            &hex::decode("77076d0a7318a57d3c16c17251b26645df4c2f87ebc0992ab177fba51db92c2a")
+                .unwrap(),
+        );
+        cfg.set_private_key(Some(StaticSecret::from(sk)));
+        assert_eq!(
+            cfg.get_public_key().map(|pk| hex::encode(pk.as_bytes())),
+            Some("8520f0098930a754748b7ddcb43ef75a0dbf3a0d26381af4eba4a98eaa9b4e6a".to_string())
+        );
+
+        cfg.set_private_key(None);
+        assert!(cfg.get_public_key().is_none());
+    }
+
+    #[test]
+    fn test_set_construction() {
+        use crate::platform::dummy;
+
+        let (_fake, _reader, tun_writer, _) = dummy::TunTest::create(false);
+        let wg: WireGuard<dummy::TunTest, dummy::PairBind> = WireGuard::new(tun_writer);
+        let cfg = WireGuardConfig::new(wg);
+        assert_eq!(
+            cfg.get_construction(),
+            "Noise_IKpsk2_25519_ChaChaPoly_BLAKE2s"
+        );
+
+        cfg.set_construction("Noise_IKpsk2_25519_AESGCM_BLAKE2s")
+            .unwrap();
+        assert_eq!(cfg.get_construction(), "Noise_IKpsk2_25519_AESGCM_BLAKE2s");
+
+        // unsupported constructions are rejected (retaining the previous construction)
+        assert!(cfg
+            .set_construction("Noise_IKpsk2_25519_AESGCM_SHA256")
+            .is_err());
+        assert_eq!(cfg.get_construction(), "Noise_IKpsk2_25519_AESGCM_BLAKE2s");
     }
 }
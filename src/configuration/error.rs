@@ -19,6 +19,11 @@ pub enum ConfigError {
     IOError,
     UnsupportedValue,
     UnsupportedProtocolVersion,
+    DuplicatePeer,
+    PeerIsSelf,
+    MissingPrivateKey,
+    ConflictingAllowedIp,
+    KeepaliveWithoutEndpoint,
 }
 
 impl fmt::Display for ConfigError {
@@ -54,6 +59,13 @@ impl ConfigError {
             ConfigError::InvalidAllowedIp => EINVAL,
             ConfigError::InvalidOperation => EINVAL,
             ConfigError::UnsupportedValue => EINVAL,
+            ConfigError::DuplicatePeer => EINVAL,
+            ConfigError::PeerIsSelf => EINVAL,
+
+            // inconsistent configuration
+            ConfigError::MissingPrivateKey => EINVAL,
+            ConfigError::ConflictingAllowedIp => EINVAL,
+            ConfigError::KeepaliveWithoutEndpoint => EINVAL,
 
             // other protocol errors
             ConfigError::LineTooLong => EPROTO,
@@ -2,9 +2,8 @@ mod config;
 mod error;
 pub mod uapi;
 
-use super::platform::Endpoint;
 use super::platform::{tun, udp};
-use super::wireguard::WireGuard;
+use super::wireguard::{IndexStrategy, Peer, PeerConfig, PskProvider, Sessions, WireGuard};
 
 pub use error::ConfigError;
 
@@ -1,6 +1,9 @@
 use log;
 use std::io;
 
+#[cfg(feature = "debug_uapi")]
+use x25519_dalek::PublicKey;
+
 use super::Configuration;
 
 pub fn serialize<C: Configuration, W: io::Write>(writer: &mut W, config: &C) -> io::Result<()> {
@@ -55,3 +58,64 @@ pub fn serialize<C: Configuration, W: io::Write>(writer: &mut W, config: &C) ->
 
     Ok(())
 }
+
+/// Serialize the allocated (receiver) ids, for debugging
+///
+/// # Arguments
+///
+/// - `ids`: (id, public key of owning peer, key-wheel slot) triples
+#[cfg(feature = "debug_uapi")]
+pub fn serialize_ids<W: io::Write>(
+    writer: &mut W,
+    ids: &[(u32, PublicKey, &'static str)],
+) -> io::Result<()> {
+    let mut write = |key: &'static str, value: String| {
+        debug_assert!(value.is_ascii());
+        log::trace!("UAPI: return : {}={}", key, value);
+        writer.write(key.as_ref())?;
+        writer.write(b"=")?;
+        writer.write(value.as_ref())?;
+        writer.write(b"\n")
+    };
+
+    for (id, pk, slot) in ids {
+        write("id", id.to_string())?;
+        write("public_key", hex::encode(pk.as_bytes()))?;
+        write("slot", slot.to_string())?;
+    }
+
+    Ok(())
+}
+
+#[cfg(all(test, feature = "debug_uapi"))]
+mod tests {
+    use super::*;
+    use x25519_dalek::StaticSecret;
+
+    #[test]
+    fn test_serialize_ids() {
+        let pk1 = PublicKey::from(&StaticSecret::from([0x1; 32]));
+        let pk2 = PublicKey::from(&StaticSecret::from([0x2; 32]));
+        let ids = vec![
+            (0x1000, pk1, "current"),
+            (0x2000, pk1, "previous"),
+            (0x3000, pk2, "handshake"),
+        ];
+
+        let mut buf = vec![];
+        serialize_ids(&mut buf, &ids[..]).unwrap();
+        let out = String::from_utf8(buf).unwrap();
+
+        // every id is described by three lines
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 3 * ids.len());
+        for (n, (id, pk, slot)) in ids.iter().enumerate() {
+            assert_eq!(lines[3 * n], format!("id={}", id));
+            assert_eq!(
+                lines[3 * n + 1],
+                format!("public_key={}", hex::encode(pk.as_bytes()))
+            );
+            assert_eq!(lines[3 * n + 2], format!("slot={}", slot));
+        }
+    }
+}
@@ -7,10 +7,15 @@ use std::io::{Read, Write};
 use super::{ConfigError, Configuration};
 
 use get::serialize;
-use set::LineParser;
+#[cfg(feature = "debug_uapi")]
+use get::serialize_ids;
+use set::{validate, LineParser};
 
 const MAX_LINE_LENGTH: usize = 256;
 
+// bound on the number of lines of a set transcript (which is buffered for validation)
+const MAX_SET_LINES: usize = 1 << 16;
+
 pub fn handle<S: Read + Write, C: Configuration>(stream: &mut S, config: &C) {
     fn operation<S: Read + Write, C: Configuration>(
         stream: &mut S,
@@ -49,15 +54,43 @@ pub fn handle<S: Read + Write, C: Configuration>(stream: &mut S, config: &C) {
                 log::debug!("UAPI, Get operation");
                 serialize(stream, config).map_err(|_| ConfigError::IOError)
             }
+            #[cfg(feature = "debug_uapi")]
+            "debug=1" => {
+                log::debug!("UAPI, Debug operation");
+                serialize_ids(stream, &config.get_ids()[..]).map_err(|_| ConfigError::IOError)
+            }
             "set=1" => {
                 log::debug!("UAPI, Set operation");
-                let mut parser = LineParser::new(config);
+
+                // read the full transcript
+                let mut lines = vec![];
                 loop {
                     let ln = readline(stream)?;
                     if ln == "" {
                         break;
                     }
-                    let (k, v) = keypair(ln.as_str())?;
+                    if lines.len() >= MAX_SET_LINES {
+                        return Err(ConfigError::IOError);
+                    }
+                    lines.push(ln);
+                }
+                let mut pairs = Vec::with_capacity(lines.len());
+                for ln in &lines {
+                    pairs.push(keypair(ln.as_str())?);
+                }
+
+                // report every error before applying any changes
+                let errors = validate(config, &pairs[..]);
+                for e in &errors {
+                    log::info!("UAPI, Set operation, invalid configuration: {:?}", e);
+                }
+                if let Some(e) = errors.into_iter().next() {
+                    return Err(e);
+                }
+
+                // apply the transcript
+                let mut parser = LineParser::new(config);
+                for (k, v) in pairs {
                     parser.parse_line(k, v)?;
                 }
                 parser.parse_line("", "")
@@ -81,3 +114,105 @@ pub fn handle<S: Read + Write, C: Configuration>(stream: &mut S, config: &C) {
     );
     let _ = stream.write("\n\n".as_ref());
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::{self, Cursor};
+
+    use x25519_dalek::{PublicKey, StaticSecret};
+
+    use super::super::WireGuardConfig;
+    use crate::platform::dummy;
+    use crate::wireguard::WireGuard;
+
+    // a UAPI connection: reads the request and records the response
+    struct Connection {
+        request: Cursor<Vec<u8>>,
+        response: Vec<u8>,
+    }
+
+    impl Read for Connection {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.request.read(buf)
+        }
+    }
+
+    impl Write for Connection {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.response.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn request<C: Configuration>(config: &C, request: &str) -> String {
+        let mut conn = Connection {
+            request: Cursor::new(request.as_bytes().to_vec()),
+            response: vec![],
+        };
+        handle(&mut conn, config);
+        String::from_utf8(conn.response).unwrap()
+    }
+
+    #[test]
+    fn test_set_get() {
+        let (_fake, _reader, tun_writer, _) = dummy::TunTest::create(false);
+        let wg: WireGuard<dummy::TunTest, dummy::PairBind> = WireGuard::new(tun_writer);
+        let config = WireGuardConfig::new(wg);
+
+        let sk = hex::encode([0x48u8; 32]); // invariant under clamping
+        let pk = hex::encode(PublicKey::from(&StaticSecret::from([0x2; 32])).as_bytes());
+
+        // apply a configuration
+        let set = format!(
+            "set=1\nprivate_key={}\npublic_key={}\nendpoint=127.0.0.1:51820\n\
+             persistent_keepalive_interval=25\nallowed_ip=10.0.0.0/24\nallowed_ip=fd00::/64\n\n",
+            sk, pk
+        );
+        assert_eq!(request(&config, &set), "errno=0\n\n");
+
+        // read it back
+        let get = request(&config, "get=1\n\n");
+        let lines: Vec<&str> = get.lines().collect();
+        for expected in &[
+            format!("private_key={}", sk),
+            format!("public_key={}", pk),
+            "persistent_keepalive_interval=25".to_owned(),
+            "allowed_ip=10.0.0.0/24".to_owned(),
+            "allowed_ip=fd00::/64".to_owned(),
+            "rx_bytes=0".to_owned(),
+            "tx_bytes=0".to_owned(),
+        ] {
+            assert!(lines.contains(&expected.as_str()), "missing {}", expected);
+        }
+        assert!(get.ends_with("errno=0\n\n"));
+
+        // the peer can be removed again
+        let remove = format!("set=1\npublic_key={}\nremove=true\n\n", pk);
+        assert_eq!(request(&config, &remove), "errno=0\n\n");
+        assert!(!request(&config, "get=1\n\n").contains("public_key="));
+
+        // inconsistent configurations are refused (before any change is applied)
+        let keepalive = format!(
+            "set=1\npublic_key={}\npersistent_keepalive_interval=25\n\n",
+            pk
+        );
+        assert_ne!(request(&config, &keepalive), "errno=0\n\n");
+        assert!(!request(&config, "get=1\n\n").contains("public_key="));
+
+        // transcripts exceeding the bound on the number of lines are refused
+        let mut long = "set=1\n".to_owned();
+        for _ in 0..=MAX_SET_LINES {
+            long.push_str("fwmark=1\n");
+        }
+        long.push_str("\n");
+        assert_ne!(request(&config, &long), "errno=0\n\n");
+
+        // invalid operations are reported through errno
+        assert_ne!(request(&config, "frobnicate=1\n\n"), "errno=0\n\n");
+    }
+}
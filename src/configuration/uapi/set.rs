@@ -3,6 +3,8 @@ use std::net::{IpAddr, SocketAddr};
 use subtle::ConstantTimeEq;
 use x25519_dalek::{PublicKey, StaticSecret};
 
+use super::super::config::validate as validate_config;
+use super::super::PeerConfig;
 use super::{ConfigError, Configuration};
 
 enum ParserState {
@@ -22,6 +24,177 @@ struct ParsedPeer {
     endpoint: Option<SocketAddr>,
 }
 
+// a peer configured by the transcript (see validate)
+struct Stanza {
+    peer: PeerConfig,
+    existing: bool,
+    update_only: bool,
+    remove: bool,
+}
+
+/// Validate a complete set transcript before it is applied,
+/// such that every problem is reported at once rather than one-at-a-time.
+///
+/// The syntax of every line is checked,
+/// then the configuration resulting from applying the transcript
+/// is checked for consistency (see configuration::validate).
+///
+/// # Arguments
+///
+/// - `config`: The current configuration of the device
+/// - `lines`: The (key, value) pairs of the transcript
+///
+/// # Returns
+///
+/// Every error found in the transcript
+/// (the syntax errors, in order of occurrence, followed by the inconsistencies)
+pub fn validate<C: Configuration>(config: &C, lines: &[(&str, &str)]) -> Vec<ConfigError> {
+    let mut errors = vec![];
+    let mut sk = config.get_private_key();
+
+    // the current peers (until configured by the transcript) and the peers of the transcript
+    let mut current: Vec<PeerConfig> = config
+        .get_peers()
+        .into_iter()
+        .map(PeerConfig::from)
+        .collect();
+    let mut stanzas: Vec<Stanza> = vec![];
+
+    for (key, value) in lines {
+        let res = if *key == "public_key" {
+            match <[u8; 32]>::from_hex(value) {
+                Ok(pk) => {
+                    // a peer configured repeatedly is reported as a duplicate
+                    let existing = current.iter().position(|p| p.public_key.as_bytes() == &pk);
+                    stanzas.push(Stanza {
+                        existing: existing.is_some(),
+                        peer: match existing {
+                            Some(i) => current.remove(i),
+                            None => PeerConfig {
+                                public_key: PublicKey::from(pk),
+                                preshared_key: [0u8; 32],
+                                endpoint: None,
+                                persistent_keepalive_interval: 0,
+                                allowed_ips: vec![],
+                            },
+                        },
+                        update_only: false,
+                        remove: false,
+                    });
+                    Ok(())
+                }
+                Err(_) => Err(ConfigError::InvalidHexValue),
+            }
+        } else {
+            match (stanzas.last_mut(), *key) {
+                (None, "private_key") => match <[u8; 32]>::from_hex(value) {
+                    Ok(bytes) => {
+                        sk = if bytes.ct_eq(&[0u8; 32]).into() {
+                            None
+                        } else {
+                            Some(StaticSecret::from(bytes))
+                        };
+                        Ok(())
+                    }
+                    Err(_) => Err(ConfigError::InvalidHexValue),
+                },
+                (None, "listen_port") => value
+                    .parse::<u16>()
+                    .map(|_| ())
+                    .map_err(|_| ConfigError::InvalidPortNumber),
+                (None, "fwmark") => value
+                    .parse::<u32>()
+                    .map(|_| ())
+                    .map_err(|_| ConfigError::InvalidFwmark),
+                (None, "replace_peers") => match *value {
+                    "true" => {
+                        current.clear();
+                        Ok(())
+                    }
+                    _ => Err(ConfigError::UnsupportedValue),
+                },
+                (Some(stanza), "remove") => {
+                    stanza.remove = true;
+                    Ok(())
+                }
+                (Some(stanza), "update_only") => {
+                    stanza.update_only = true;
+                    Ok(())
+                }
+                (Some(stanza), "preshared_key") => match <[u8; 32]>::from_hex(value) {
+                    Ok(psk) => {
+                        stanza.peer.preshared_key = psk;
+                        Ok(())
+                    }
+                    Err(_) => Err(ConfigError::InvalidHexValue),
+                },
+                (Some(stanza), "endpoint") => match value.parse::<SocketAddr>() {
+                    Ok(endpoint) => {
+                        stanza.peer.endpoint = Some(endpoint);
+                        Ok(())
+                    }
+                    Err(_) => Err(ConfigError::InvalidSocketAddr),
+                },
+                (Some(stanza), "persistent_keepalive_interval") => match value.parse::<u64>() {
+                    Ok(secs) => {
+                        stanza.peer.persistent_keepalive_interval = secs;
+                        Ok(())
+                    }
+                    Err(_) => Err(ConfigError::InvalidKeepaliveInterval),
+                },
+                (Some(stanza), "replace_allowed_ips") => {
+                    stanza.peer.allowed_ips.clear();
+                    Ok(())
+                }
+                (Some(stanza), "allowed_ip") => {
+                    let mut split = value.splitn(2, "/");
+                    let addr: Option<IpAddr> = split.next().and_then(|x| x.parse().ok());
+                    let cidr: Option<u32> = split.next().and_then(|x| x.parse().ok());
+                    match (addr, cidr) {
+                        (Some(addr @ IpAddr::V4(_)), Some(cidr)) if cidr <= 32 => {
+                            stanza.peer.allowed_ips.push((addr, cidr));
+                            Ok(())
+                        }
+                        (Some(addr @ IpAddr::V6(_)), Some(cidr)) if cidr <= 128 => {
+                            stanza.peer.allowed_ips.push((addr, cidr));
+                            Ok(())
+                        }
+                        _ => Err(ConfigError::InvalidAllowedIp),
+                    }
+                }
+                (Some(_), "protocol_version") => value
+                    .parse::<usize>()
+                    .map(|_| ())
+                    .map_err(|_| ConfigError::UnsupportedProtocolVersion),
+                _ => Err(ConfigError::InvalidKey),
+            }
+        };
+        if let Err(e) = res {
+            errors.push(e);
+        }
+    }
+
+    // the peers of the resulting configuration
+    // (peers updated with update_only are only configured if they exist)
+    let mut peers = current;
+    for stanza in stanzas {
+        if !stanza.remove && (stanza.existing || !stanza.update_only) {
+            peers.push(stanza.peer);
+        }
+    }
+
+    // a device may be configured before its private key is set
+    errors.extend(
+        validate_config(sk.as_ref(), &peers[..])
+            .into_iter()
+            .filter(|e| match e {
+                ConfigError::MissingPrivateKey => false,
+                _ => true,
+            }),
+    );
+    errors
+}
+
 pub struct LineParser<'a, C: Configuration> {
     config: &'a C,
     state: ParserState,
@@ -66,9 +239,14 @@ impl<'a, C: Configuration> LineParser<'a, C> {
                 config.add_peer(&peer.public_key);
             }
 
-            for (ip, cidr) in &peer.allowed_ips {
-                log::trace!("flush peer, add allowed_ips : {}/{}", ip.to_string(), cidr);
-                config.add_allowed_ip(&peer.public_key, *ip, *cidr);
+            if peer.replace_allowed_ips {
+                log::trace!("flush peer, replace allowed_ips");
+                config.set_allowed_ips(&peer.public_key, &peer.allowed_ips[..]);
+            } else {
+                for (ip, cidr) in &peer.allowed_ips {
+                    log::trace!("flush peer, add allowed_ips : {}/{}", ip.to_string(), cidr);
+                    config.add_allowed_ip(&peer.public_key, *ip, *cidr);
+                }
             }
 
             if let Some(psk) = peer.preshared_key {
@@ -250,3 +428,113 @@ impl<'a, C: Configuration> LineParser<'a, C> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::configuration::WireGuardConfig;
+    use crate::platform::dummy;
+    use crate::wireguard::WireGuard;
+
+    fn assert_errors(errors: Vec<ConfigError>, expected: &[ConfigError]) {
+        assert_eq!(
+            format!("{:?}", errors),
+            format!("{:?}", expected),
+            "unexpected errors"
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        let (_fake, _reader, tun_writer, _) = dummy::TunTest::create(false);
+        let wg: WireGuard<dummy::TunTest, dummy::PairBind> = WireGuard::new(tun_writer);
+        let config = WireGuardConfig::new(wg);
+
+        let sk = StaticSecret::from([0x1; 32]);
+        let own = hex::encode(PublicKey::from(&sk).as_bytes());
+        config.set_private_key(Some(sk));
+
+        let pk2 = PublicKey::from(&StaticSecret::from([0x2; 32]));
+        let pk3 = PublicKey::from(&StaticSecret::from([0x3; 32]));
+        let peer2 = hex::encode(pk2.as_bytes());
+        let peer3 = hex::encode(pk3.as_bytes());
+
+        // a valid transcript
+        let lines = [
+            ("listen_port", "51820"),
+            ("public_key", peer2.as_str()),
+            ("endpoint", "127.0.0.1:51820"),
+            ("persistent_keepalive_interval", "25"),
+            ("allowed_ip", "10.0.0.0/24"),
+            ("allowed_ip", "fd00::/64"),
+        ];
+        assert_errors(validate(&config, &lines), &[]);
+
+        // a transcript with three distinct errors
+        let lines = [
+            ("public_key", peer2.as_str()),
+            ("allowed_ip", "10.0.0.0/33"),
+            ("public_key", own.as_str()),
+            ("public_key", peer2.as_str()),
+        ];
+        assert_errors(
+            validate(&config, &lines),
+            &[
+                ConfigError::InvalidAllowedIp,
+                ConfigError::PeerIsSelf,
+                ConfigError::DuplicatePeer,
+            ],
+        );
+
+        // interface keys are only accepted before the first peer
+        let lines = [("public_key", peer2.as_str()), ("listen_port", "51820")];
+        assert_errors(validate(&config, &lines), &[ConfigError::InvalidKey]);
+
+        // persistent keepalive without an endpoint
+        let lines = [
+            ("public_key", peer2.as_str()),
+            ("persistent_keepalive_interval", "25"),
+        ];
+        assert_errors(
+            validate(&config, &lines),
+            &[ConfigError::KeepaliveWithoutEndpoint],
+        );
+
+        // the configuration resulting from the transcript is checked (current peers included)
+        config.add_peer(&pk2);
+        config.add_allowed_ip(&pk2, "10.0.0.0".parse().unwrap(), 24);
+
+        let lines = [
+            ("public_key", peer3.as_str()),
+            ("allowed_ip", "10.0.0.1/24"),
+        ];
+        assert_errors(
+            validate(&config, &lines),
+            &[ConfigError::ConflictingAllowedIp],
+        );
+
+        let lines = [
+            ("public_key", peer2.as_str()),
+            ("replace_allowed_ips", "true"),
+            ("public_key", peer3.as_str()),
+            ("allowed_ip", "10.0.0.1/24"),
+        ];
+        assert_errors(validate(&config, &lines), &[]);
+
+        let lines = [
+            ("public_key", peer2.as_str()),
+            ("remove", "true"),
+            ("public_key", peer3.as_str()),
+            ("allowed_ip", "10.0.0.1/24"),
+        ];
+        assert_errors(validate(&config, &lines), &[]);
+
+        let lines = [
+            ("replace_peers", "true"),
+            ("public_key", peer3.as_str()),
+            ("allowed_ip", "10.0.0.1/24"),
+        ];
+        assert_errors(validate(&config, &lines), &[]);
+    }
+}
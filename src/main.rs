@@ -58,6 +58,8 @@ fn main() {
     let mut name = None;
     let mut drop_privileges = true;
     let mut foreground = false;
+    let mut self_test = false;
+    let mut validate = false;
     let mut args = env::args();
 
     args.next(); // skip path (argv[0])
@@ -70,6 +72,12 @@ fn main() {
             "--root" => {
                 drop_privileges = false;
             }
+            "--self-test" => {
+                self_test = true;
+            }
+            "--validate" => {
+                validate = true;
+            }
             dev => name = Some(dev.to_owned()),
         }
     }
@@ -83,6 +91,14 @@ fn main() {
         Some(name) => name,
     };
 
+    // check the cryptographic stack before use
+    if self_test {
+        if let Err(e) = wireguard::crypto_self_test() {
+            eprintln!("Cryptographic self-test failed: {}", e);
+            exit(-4);
+        }
+    }
+
     // create UAPI socket
     let uapi = plt::UAPI::bind(name.as_str()).unwrap_or_else(|e| {
         eprintln!("Failed to create UAPI listener: {}", e);
@@ -145,6 +161,16 @@ fn main() {
                 }
                 Ok(tun::TunEvent::Up(mtu)) => {
                     log::info!("Tun up (mtu = {})", mtu);
+
+                    // refuse to carry traffic with an inconsistent configuration
+                    if validate {
+                        if let Err(errors) = cfg.validate() {
+                            for e in errors {
+                                log::info!("Inconsistent configuration: {}", e);
+                            }
+                            continue;
+                        }
+                    }
                     let _ = cfg.up(mtu); // TODO: handle
                 }
                 Ok(tun::TunEvent::Down) => {
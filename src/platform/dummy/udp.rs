@@ -2,10 +2,13 @@ use hex;
 use std::error::Error;
 use std::fmt;
 use std::marker;
+use std::thread;
+use std::time::Duration;
 
 use log::debug;
 use rand::rngs::OsRng;
-use rand::Rng;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
 
 use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
 use std::sync::Arc;
@@ -75,6 +78,52 @@ impl VoidBind {
 
 /* Pair Bind */
 
+/* Simulated network conditions between the ends of a pair bind,
+ * applied independently to each direction.
+ */
+#[derive(Clone, Copy, Default)]
+pub struct Impairment {
+    pub loss: f64,       // probability of dropping a message
+    pub reorder: f64,    // probability of holding back a message until after the next
+    pub delay: Duration, // latency added to every message
+}
+
+struct Impaired {
+    conditions: Impairment,
+    rng: ChaCha8Rng,
+    held: Option<Vec<u8>>,
+}
+
+impl Impaired {
+    fn new(conditions: Impairment, seed: u64, dir: u8) -> Impaired {
+        let mut key = [0u8; 32];
+        key[..8].copy_from_slice(&seed.to_le_bytes());
+        key[8] = dir;
+        Impaired {
+            conditions,
+            rng: ChaCha8Rng::from_seed(key),
+            held: None,
+        }
+    }
+
+    // returns the messages to deliver (in order)
+    fn apply(&mut self, msg: Vec<u8>) -> Vec<Vec<u8>> {
+        if self.conditions.loss > 0.0 && self.rng.gen_bool(self.conditions.loss) {
+            return vec![];
+        }
+        if self.held.is_none()
+            && self.conditions.reorder > 0.0
+            && self.rng.gen_bool(self.conditions.reorder)
+        {
+            self.held = Some(msg);
+            return vec![];
+        }
+        let mut msgs = vec![msg];
+        msgs.extend(self.held.take());
+        msgs
+    }
+}
+
 #[derive(Clone)]
 pub struct PairReader<E> {
     id: u32,
@@ -113,10 +162,26 @@ impl Writer<UnitEndpoint> for PairWriter<UnitEndpoint> {
             hex::encode(buf)
         );
         let owned = buf.to_owned();
-        match self.send.lock().unwrap().send(owned) {
-            Err(_) => Err(BindError::Disconnected),
-            Ok(_) => Ok(()),
+        let msgs = match self.impaired.as_ref() {
+            None => vec![owned],
+            Some(impaired) => {
+                let mut impaired = impaired.lock().unwrap();
+                if impaired.conditions.delay > Duration::from_secs(0) {
+                    thread::sleep(impaired.conditions.delay);
+                }
+                impaired.apply(owned)
+            }
+        };
+        if msgs.is_empty() {
+            debug!("dummy({}): dropped", self.id);
+        }
+        let send = self.send.lock().unwrap();
+        for msg in msgs {
+            if send.send(msg).is_err() {
+                return Err(BindError::Disconnected);
+            }
         }
+        Ok(())
     }
 }
 
@@ -124,6 +189,7 @@ impl Writer<UnitEndpoint> for PairWriter<UnitEndpoint> {
 pub struct PairWriter<E> {
     id: u32,
     send: Arc<Mutex<SyncSender<Vec<u8>>>>,
+    impaired: Option<Arc<Mutex<Impaired>>>,
     _marker: marker::PhantomData<E>,
 }
 
@@ -135,6 +201,35 @@ impl PairBind {
         (PairReader<E>, PairWriter<E>),
         (PairReader<E>, PairWriter<E>),
     ) {
+        Self::connect(None)
+    }
+
+    /// Create a pair bind subject to the (simulated) network conditions.
+    ///
+    /// The losses and reorderings are deterministic given the seed
+    /// and the sequence of messages written in each direction.
+    pub fn impaired_pair<E>(
+        conditions: Impairment,
+        seed: u64,
+    ) -> (
+        (PairReader<E>, PairWriter<E>),
+        (PairReader<E>, PairWriter<E>),
+    ) {
+        Self::connect(Some((conditions, seed)))
+    }
+
+    fn connect<E>(
+        impairment: Option<(Impairment, u64)>,
+    ) -> (
+        (PairReader<E>, PairWriter<E>),
+        (PairReader<E>, PairWriter<E>),
+    ) {
+        let impaired = |dir| {
+            impairment.map(|(conditions, seed)| {
+                Arc::new(Mutex::new(Impaired::new(conditions, seed, dir)))
+            })
+        };
+
         let id1: u32 = OsRng.gen();
         let id2: u32 = OsRng.gen();
 
@@ -150,6 +245,7 @@ impl PairBind {
                 PairWriter {
                     id: id1,
                     send: Arc::new(Mutex::new(tx2)),
+                    impaired: impaired(1),
                     _marker: marker::PhantomData,
                 },
             ),
@@ -162,6 +258,7 @@ impl PairBind {
                 PairWriter {
                     id: id2,
                     send: Arc::new(Mutex::new(tx1)),
+                    impaired: impaired(2),
                     _marker: marker::PhantomData,
                 },
             ),
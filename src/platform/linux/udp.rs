@@ -6,7 +6,7 @@ use log;
 use std::convert::TryInto;
 use std::io;
 use std::mem;
-use std::net::{SocketAddr, SocketAddrV4, SocketAddrV6};
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
 use std::os::unix::io::RawFd;
 use std::ptr;
 use std::sync::Arc;
@@ -131,6 +131,21 @@ fn safe_cast<T, D>(v: &mut T) -> *mut D {
     (v as *mut T) as *mut D
 }
 
+/* IPv4-mapped IPv6 addresses (::ffff:a.b.c.d) are unreachable through the IPv6 socket
+ * (bound with IPV6_V6ONLY), hence they are converted to their canonical IPv4 form
+ * and sent over the IPv4 socket instead.
+ */
+fn unmap(addr: SocketAddr) -> SocketAddr {
+    if let SocketAddr::V6(addr) = addr {
+        let ip = addr.ip().octets();
+        if ip[..12] == [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff] {
+            let ip = Ipv4Addr::new(ip[12], ip[13], ip[14], ip[15]);
+            return SocketAddr::V4(SocketAddrV4::new(ip, addr.port()));
+        }
+    }
+    addr
+}
+
 impl Endpoint for LinuxEndpoint {
     fn clear_src(&mut self) {
         match self {
@@ -146,7 +161,7 @@ impl Endpoint for LinuxEndpoint {
     }
 
     fn from_address(addr: SocketAddr) -> Self {
-        match addr {
+        match unmap(addr) {
             SocketAddr::V4(addr) => LinuxEndpoint::V4(EndpointV4 {
                 dst: libc::sockaddr_in {
                     sin_family: libc::AF_INET as libc::sa_family_t,
@@ -664,6 +679,15 @@ impl LinuxUDP {
 impl PlatformUDP for LinuxUDP {
     type Owner = LinuxOwner;
 
+    /* Bind on all interfaces for both IP versions
+     *
+     * Rather than a single dual-stack socket, a socket is bound for each IP version
+     * (the IPv6 socket with IPV6_V6ONLY set), on the same port.
+     * Hence the bind mode is identical on all kernels, v4-mapped addresses are never
+     * received and the endpoint of a peer retains the version of the socket it was read from.
+     *
+     * If either version is unavailable (e.g. IPv6 disabled), the device binds to the other alone.
+     */
     fn bind(mut port: u16) -> Result<(Vec<Self::Reader>, Self::Writer, Self::Owner), Self::Error> {
         log::debug!("bind to port {}", port);
 
@@ -714,3 +738,53 @@ impl PlatformUDP for LinuxUDP {
         Ok((readers, writer, owner))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn echo(writer: &LinuxUDPWriter, reader: &LinuxUDPReader, dst: SocketAddr) -> SocketAddr {
+        let msg = [0x42u8; 32];
+        let mut buf = [0u8; 64];
+        writer
+            .write(&msg, &mut LinuxEndpoint::from_address(dst))
+            .expect("failed to send");
+        let (len, src) = reader.read(&mut buf).expect("failed to receive");
+        assert_eq!(&buf[..len], &msg[..]);
+        src.into_address()
+    }
+
+    #[test]
+    fn test_dual_stack() {
+        let (readers, writer, owner) = LinuxUDP::bind(0).unwrap();
+        let port = owner.get_port();
+
+        // IPv6 may be unavailable (e.g. disabled in the test environment)
+        let reader4 = readers
+            .iter()
+            .find(|reader| match reader {
+                LinuxUDPReader::V4(_) => true,
+                LinuxUDPReader::V6(_) => false,
+            })
+            .expect("failed to bind IPv4 socket");
+        let reader6 = readers.iter().find(|reader| match reader {
+            LinuxUDPReader::V4(_) => false,
+            LinuxUDPReader::V6(_) => true,
+        });
+
+        let v4: SocketAddr = SocketAddr::new("127.0.0.1".parse().unwrap(), port);
+        let v6: SocketAddr = SocketAddr::new("::1".parse().unwrap(), port);
+        let mapped: SocketAddr = SocketAddr::new("::ffff:127.0.0.1".parse().unwrap(), port);
+
+        // both address families are reachable
+        assert_eq!(echo(&writer, reader4, v4), v4);
+        match reader6 {
+            Some(reader6) => assert_eq!(echo(&writer, reader6, v6), v6),
+            None => log::info!("IPv6 unavailable, skipping the IPv6 part of the test"),
+        }
+
+        // v4-mapped endpoints are sent over IPv4 and recorded in canonical form
+        assert_eq!(LinuxEndpoint::from_address(mapped).into_address(), v4);
+        assert_eq!(echo(&writer, reader4, mapped), v4);
+    }
+}
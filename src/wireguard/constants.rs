@@ -13,6 +13,16 @@ pub const KEEPALIVE_TIMEOUT: Duration = Duration::from_secs(10);
 pub const MAX_TIMER_HANDSHAKES: usize =
     (REKEY_ATTEMPT_TIME.as_secs() / REKEY_TIMEOUT.as_secs()) as usize;
 
+// Semantics:
+// Maximum random jitter added to REKEY_TIMEOUT when retransmitting a handshake initiation,
+// to avoid peers retransmitting in lockstep.
+pub const MAX_REKEY_TIMEOUT_JITTER: Duration = Duration::from_millis(333);
+
+// Semantics:
+// Maximum interval between handshake initiations,
+// when backing off from a peer which has failed to complete a handshake for REKEY_ATTEMPT_TIME.
+pub const MAX_REKEY_BACKOFF: Duration = Duration::from_secs(60);
+
 // Semantics:
 // Maximum number of buffered handshake requests
 // (either from outside message or handshake requests triggered locally)
@@ -32,6 +42,12 @@ pub const DURATION_UNDER_LOAD: Duration = Duration::from_secs(1);
 // The payload of transport messages are padded to this multiple
 pub const MESSAGE_PADDING_MULTIPLE: usize = 16;
 
+// Semantics:
+// When enabled, the byte counters of every peer are sampled at this interval,
+// retaining the most recent STATS_HISTORY_LENGTH samples.
+pub const STATS_HISTORY_INTERVAL: Duration = Duration::from_secs(1);
+pub const STATS_HISTORY_LENGTH: usize = 64;
+
 // Semantics:
 // Longest possible duration of any WireGuard timer
 pub const TIMER_MAX_DURATION: Duration = Duration::from_secs(200);
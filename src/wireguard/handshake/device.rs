@@ -2,7 +2,9 @@ use spin::RwLock;
 use std::collections::hash_map;
 use std::collections::HashMap;
 use std::net::SocketAddr;
-use std::sync::Mutex;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use zerocopy::AsBytes;
 
 use byteorder::{ByteOrder, LittleEndian};
@@ -23,8 +25,20 @@ use super::peer::Peer;
 use super::ratelimiter::RateLimiter;
 use super::types::*;
 
+use super::super::constants::REKEY_TIMEOUT;
+use super::super::types::{Cipher, IndexStrategy, PskProvider};
+
 const MAX_PEER_PER_DEVICE: usize = 1 << 20;
 
+/* Check if the public key is a point of low order,
+ * in which case any (clamped) scalar multiplication yields the zero point
+ * and the Diffie-Hellman operations with the peer provide no security.
+ */
+fn is_low_order(pk: &PublicKey) -> bool {
+    let sk = StaticSecret::from([0x1; 32]);
+    sk.diffie_hellman(pk).as_bytes() == &[0u8; 32]
+}
+
 pub struct KeyState {
     pub(super) sk: StaticSecret, // static secret key
     pub(super) pk: PublicKey,    // static public key
@@ -39,6 +53,10 @@ pub struct Device<O> {
     id_map: RwLock<HashMap<u32, [u8; 32]>>,
     pk_map: HashMap<[u8; 32], Peer<O>>,
     limiter: Mutex<RateLimiter>,
+    pub(super) cipher: Cipher,
+    index_strategy: IndexStrategy,
+    next_index: AtomicU32, // next candidate for sequential allocation
+    psk_provider: Option<Arc<dyn PskProvider>>,
 }
 
 pub struct Iter<'a, O> {
@@ -99,6 +117,10 @@ impl<O> Device<O> {
             id_map: RwLock::new(HashMap::new()),
             pk_map: HashMap::new(),
             limiter: Mutex::new(RateLimiter::new()),
+            cipher: Cipher::default(),
+            index_strategy: IndexStrategy::default(),
+            next_index: AtomicU32::new(0),
+            psk_provider: None,
         }
     }
 
@@ -152,6 +174,32 @@ impl<O> Device<O> {
         })
     }
 
+    /// Select the Noise construction used for new handshakes
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the construction, e.g. "Noise_IKpsk2_25519_ChaChaPoly_BLAKE2s",
+    ///   only constructions in the allowlist are accepted
+    pub fn set_construction(&mut self, name: &str) -> Result<(), ConfigError> {
+        self.cipher = Cipher::from_construction(name)
+            .ok_or(ConfigError::new("Unsupported Noise construction"))?;
+        Ok(())
+    }
+
+    /// Return the name of the Noise construction used for new handshakes
+    pub fn get_construction(&self) -> &'static str {
+        self.cipher.construction()
+    }
+
+    /// Select the strategy used to allocate indexes for new handshakes
+    ///
+    /// # Arguments
+    ///
+    /// * `strategy` - Random (the default) or Sequential indexes
+    pub fn set_index_strategy(&mut self, strategy: IndexStrategy) {
+        self.index_strategy = strategy;
+    }
+
     /// Return the secret key of the device
     ///
     /// # Returns
@@ -161,6 +209,12 @@ impl<O> Device<O> {
         self.keyst.as_ref().map(|key| &key.sk)
     }
 
+    /// Return the public key of the device
+    /// (derived when the secret key was set)
+    pub fn get_pk(&self) -> Option<PublicKey> {
+        self.keyst.as_ref().map(|key| key.pk)
+    }
+
     /// Add a new public key to the state machine
     /// To remove public keys, you must create a new machine instance
     ///
@@ -174,6 +228,13 @@ impl<O> Device<O> {
             return Err(ConfigError::new("Too many peers for device"));
         }
 
+        // error if public key is of low order
+        if is_low_order(&pk) {
+            return Err(ConfigError::new(
+                "Public key of peer is a point of low order",
+            ));
+        }
+
         // error if public key matches device
         if let Some(key) = self.keyst.as_ref() {
             if pk.as_bytes() == key.pk.as_bytes() {
@@ -206,19 +267,21 @@ impl<O> Device<O> {
     ///
     /// # Returns
     ///
-    /// The call might fail if the public key is not found
-    pub fn remove(&mut self, pk: &PublicKey) -> Result<(), ConfigError> {
+    /// The opaque value of the removed peer,
+    /// the call might fail if the public key is not found
+    pub fn remove(&mut self, pk: &PublicKey) -> Result<O, ConfigError> {
         // take write-lock on receive id table
         let mut id_map = self.id_map.write();
 
         // remove the peer
-        self.pk_map
+        let peer = self
+            .pk_map
             .remove(pk.as_bytes())
             .ok_or(ConfigError::new("Public key not in device"))?;
 
         // purge the id map (linear scan)
         id_map.retain(|_, v| v != pk.as_bytes());
-        Ok(())
+        Ok(peer.opaque)
     }
 
     /// Add a psk to the peer
@@ -232,9 +295,42 @@ impl<O> Device<O> {
     ///
     /// The call might fail if the public key is not found
     pub fn set_psk(&mut self, pk: PublicKey, psk: Psk) -> Result<(), ConfigError> {
-        match self.pk_map.get_mut(pk.as_bytes()) {
-            Some(mut peer) => {
-                peer.psk = psk;
+        match self.pk_map.get(pk.as_bytes()) {
+            Some(peer) => {
+                peer.set_psk(psk);
+                Ok(())
+            }
+            _ => Err(ConfigError::new("No such public key")),
+        }
+    }
+
+    /// Replace the psk of the peer, while continuing to accept the previous psk
+    /// for the duration of the rollover window.
+    ///
+    /// Since the psk is only mixed into the response message,
+    /// the new psk only takes effect once the window closes:
+    /// during the window responses are created using the previous psk
+    /// and the previous psk is accepted when consuming a response.
+    /// Hence handshakes complete whichever end rotates first.
+    ///
+    /// # Arguments
+    ///
+    /// * `pk` - The public key of the peer
+    /// * `psk` - The new psk
+    /// * `window` - The duration for which the previous psk is accepted
+    ///
+    /// # Returns
+    ///
+    /// The call might fail if the public key is not found
+    pub fn rotate_psk(
+        &mut self,
+        pk: PublicKey,
+        psk: Psk,
+        window: Duration,
+    ) -> Result<(), ConfigError> {
+        match self.pk_map.get(pk.as_bytes()) {
+            Some(peer) => {
+                peer.rotate_psk(psk, window);
                 Ok(())
             }
             _ => Err(ConfigError::new("No such public key")),
@@ -254,11 +350,49 @@ impl<O> Device<O> {
     /// The call might fail if the public key is not found
     pub fn get_psk(&self, pk: &PublicKey) -> Result<Psk, ConfigError> {
         match self.pk_map.get(pk.as_bytes()) {
-            Some(peer) => Ok(peer.psk),
+            Some(peer) => Ok(peer.psk()),
             _ => Err(ConfigError::new("No such public key")),
         }
     }
 
+    /// Set (or unset) the source of preshared keys
+    ///
+    /// When set, the provider is polled for the latest psk of the peer
+    /// before every initiation created and every response created.
+    /// The responder uses the latest psk, while the initiator continues to accept
+    /// the previous psk for REKEY_TIMEOUT (in case the ends are briefly out of sync).
+    pub fn set_psk_provider(&mut self, provider: Option<Arc<dyn PskProvider>>) {
+        self.psk_provider = provider;
+    }
+
+    // Pull the latest psk of the peer from the psk provider (if any),
+    // returns the latest psk or None if there is no psk from the provider.
+    fn refresh_psk(&self, pk: &PublicKey, peer: &Peer<O>) -> Option<Psk> {
+        let psk = self.psk_provider.as_ref()?.psk(pk)?;
+        if peer.refresh_psk(psk, REKEY_TIMEOUT) {
+            log::debug!("handshake device, refreshed psk from provider");
+        }
+        Some(psk)
+    }
+
+    /// Reserve an id for the peer,
+    /// e.g. the (receiver) id of a session imported from another instance.
+    ///
+    /// # Returns
+    ///
+    /// True if the peer exists and the id was not already allocated
+    pub fn reserve(&self, pk: &PublicKey, id: u32) -> bool {
+        if !self.pk_map.contains_key(pk.as_bytes()) {
+            return false;
+        }
+        let mut m = self.id_map.write();
+        if m.contains_key(&id) {
+            return false;
+        }
+        m.insert(id, *pk.as_bytes());
+        true
+    }
+
     /// Release an id back to the pool
     ///
     /// # Arguments
@@ -270,6 +404,28 @@ impl<O> Device<O> {
         m.remove(&id);
     }
 
+    /// Enumerate the allocated (receiver) ids
+    ///
+    /// # Returns
+    ///
+    /// A list of (id, public key of owning peer) pairs
+    #[cfg(feature = "debug_uapi")]
+    pub fn list_ids(&self) -> Vec<(u32, PublicKey)> {
+        self.id_map
+            .read()
+            .iter()
+            .map(|(id, pk)| (*id, PublicKey::from(*pk)))
+            .collect()
+    }
+
+    /// Abort all handshakes in progress,
+    /// releasing the ids allocated for pending initiations.
+    pub fn reset_handshakes(&self) {
+        for peer in self.pk_map.values() {
+            peer.reset_state().map(|id| self.release(id));
+        }
+    }
+
     /// Begin a new handshake
     ///
     /// # Arguments
@@ -284,11 +440,14 @@ impl<O> Device<O> {
             (_, None) => Err(HandshakeError::UnknownPublicKey),
             (None, _) => Err(HandshakeError::UnknownPublicKey),
             (Some(keyst), Some(peer)) => {
+                // pull the latest psk (used when consuming the response)
+                self.refresh_psk(pk, peer);
+
                 let local = self.allocate(rng, pk);
                 let mut msg = Initiation::default();
 
                 // create noise part of initation
-                noise::create_initiation(rng, keyst, peer, pk, local, &mut msg.noise)?;
+                noise::create_initiation(rng, self.cipher, keyst, peer, pk, local, &mut msg.noise)?;
 
                 // add macs to initation
                 peer.macs
@@ -364,12 +523,27 @@ impl<O> Device<O> {
                 // prepare memory for response, TODO: take slice for zero allocation
                 let mut resp = Response::default();
 
+                // the latest psk of the provider is authoritative,
+                // otherwise the previous psk is used during a rollover window
+                let psk = self
+                    .refresh_psk(&pk, peer)
+                    .unwrap_or_else(|| peer.response_psk());
+
                 // create response (release id on error)
-                let keys = noise::create_response(rng, peer, &pk, local, st, &mut resp.noise)
-                    .map_err(|e| {
-                        self.release(local);
-                        e
-                    })?;
+                let keys = noise::create_response(
+                    rng,
+                    self.cipher,
+                    peer,
+                    &pk,
+                    &psk,
+                    local,
+                    st,
+                    &mut resp.noise,
+                )
+                .map_err(|e| {
+                    self.release(local);
+                    e
+                })?;
 
                 // add macs to response
                 peer.macs
@@ -459,7 +633,10 @@ impl<O> Device<O> {
     // Allocated a new receiver identifier for the peer
     fn allocate<R: RngCore + CryptoRng>(&self, rng: &mut R, pk: &PublicKey) -> u32 {
         loop {
-            let id = rng.gen();
+            let id = match self.index_strategy {
+                IndexStrategy::Random => rng.gen(),
+                IndexStrategy::Sequential => self.next_index.fetch_add(1, Ordering::Relaxed),
+            };
 
             // check membership with read lock
             if self.id_map.read().contains_key(&id) {
@@ -480,6 +657,7 @@ impl<O> Device<O> {
 mod tests {
     use super::*;
     use proptest::prelude::*;
+    use rand::rngs::OsRng;
     use std::collections::HashSet;
 
     proptest! {
@@ -510,4 +688,43 @@ mod tests {
             assert_eq!(ss.len(), dev.len());
         }
     }
+
+    fn allocate_ids(strategy: IndexStrategy, n: usize) -> Vec<u32> {
+        let mut dev: Device<u32> = Device::new();
+        dev.set_index_strategy(strategy);
+        let pk = PublicKey::from([1u8; 32]);
+        (0..n).map(|_| dev.allocate(&mut OsRng, &pk)).collect()
+    }
+
+    #[test]
+    fn sequential_indexes() {
+        let ids = allocate_ids(IndexStrategy::Sequential, 100);
+        assert_eq!(ids, (0..100).collect::<Vec<u32>>());
+    }
+
+    #[test]
+    fn sequential_indexes_skip_allocated() {
+        let mut dev: Device<u32> = Device::new();
+        dev.set_index_strategy(IndexStrategy::Sequential);
+        let pk = PublicKey::from([1u8; 32]);
+        dev.id_map.write().insert(1, *pk.as_bytes());
+        assert_eq!(dev.allocate(&mut OsRng, &pk), 0);
+        assert_eq!(dev.allocate(&mut OsRng, &pk), 2);
+    }
+
+    #[test]
+    fn random_indexes() {
+        let ids = allocate_ids(IndexStrategy::Random, 100);
+
+        // every index is unique
+        let set: HashSet<u32> = ids.iter().cloned().collect();
+        assert_eq!(set.len(), ids.len());
+
+        // and the indexes are not trivially sequential
+        let consecutive = ids
+            .windows(2)
+            .filter(|w| w[1] == w[0].wrapping_add(1))
+            .count();
+        assert!(consecutive < 2);
+    }
 }
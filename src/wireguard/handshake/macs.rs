@@ -28,6 +28,9 @@ const SIZE_TAG: usize = 16; // xchacha20poly1305 tag
 
 const COOKIE_UPDATE_INTERVAL: Duration = Duration::from_secs(120);
 
+// margin to account for the latency between the cookie being minted and received
+const COOKIE_RECEIVE_LATENCY: Duration = Duration::from_secs(5);
+
 macro_rules! HASH {
     ( $($input:expr),* ) => {{
         use blake2::Digest;
@@ -165,7 +168,7 @@ impl Generator {
         macs.f_mac1 = MAC!(&self.mac1_key, inner);
         macs.f_mac2 = match &self.cookie {
             Some(cookie) => {
-                if cookie.birth.elapsed() > COOKIE_UPDATE_INTERVAL {
+                if cookie.birth.elapsed() > COOKIE_UPDATE_INTERVAL - COOKIE_RECEIVE_LATENCY {
                     self.cookie = None;
                     [0u8; SIZE_MAC]
                 } else {
@@ -322,4 +325,61 @@ mod tests {
             assert!(validator.check_mac2(&inner2[..], &src, &macs), "mac2 of inner2 did not validate");
         }
     }
+
+    #[test]
+    fn test_mac1_known_answer() {
+        // computed independently: BLAKE2s-128(key = BLAKE2s-256("mac1----" || pk), inner)
+        let pk = PublicKey::from([
+            0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d,
+            0x0e, 0x0f, 0x10, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x1b,
+            0x1c, 0x1d, 0x1e, 0x1f,
+        ]);
+        let inner: Vec<u8> = (0..116).map(|i| i as u8).collect();
+        let mac1_key =
+            hex::decode("4fb2527ac956001553dc1ad9b55171b7d9daa3de1cdbcd71451830170c7e6cac")
+                .unwrap();
+        let mac1 = hex::decode("3a7d1b865f062f4af4677f1d88d7ea74").unwrap();
+
+        let mut generator = Generator::new(pk);
+        assert_eq!(&generator.mac1_key[..], &mac1_key[..]);
+
+        let mut macs = MacsFooter::default();
+        generator.generate(&inner[..], &mut macs);
+        assert_eq!(&macs.f_mac1[..], &mac1[..]);
+
+        // the validator accepts the mac1 and rejects any other
+        let validator = Validator::new(pk);
+        validator.check_mac1(&inner[..], &macs).unwrap();
+        macs.f_mac1[0] ^= 1;
+        assert!(validator.check_mac1(&inner[..], &macs).is_err());
+    }
+
+    #[test]
+    fn test_cookie_expiry() {
+        let mut msg = CookieReply::default();
+        let mut macs = MacsFooter::default();
+        let src = "192.0.2.16:8080".parse().unwrap();
+        let (validator, mut generator) = new_validator_generator();
+        let inner = [0x1u8; 32];
+
+        // obtain a fresh cookie
+        generator.generate(&inner[..], &mut macs);
+        validator.create_cookie_reply(&mut OsRng, 0, &src, &macs, &mut msg);
+        generator
+            .process(&msg)
+            .expect("failed to process CookieReply");
+
+        // age the cookie past the point where the responder may have rotated its secret
+        generator.cookie.as_mut().unwrap().birth = Instant::now()
+            - (COOKIE_UPDATE_INTERVAL - COOKIE_RECEIVE_LATENCY)
+            - Duration::from_secs(1);
+
+        // the expired cookie should not be used for mac2
+        generator.generate(&inner[..], &mut macs);
+        assert_eq!(macs.f_mac2, [0u8; SIZE_MAC], "mac2 should not be set");
+        assert!(
+            generator.cookie.is_none(),
+            "expired cookie should be dropped"
+        );
+    }
 }
@@ -360,4 +360,47 @@ mod tests {
         let msg_p = Initiation::parse(&buf[..]).unwrap();
         assert_eq!(msg, *msg_p.into_ref());
     }
+    #[test]
+    fn message_cookie_reply_identity() {
+        let mut msg: CookieReply = Default::default();
+
+        msg.f_receiver.set(202020);
+        msg.f_nonce = [
+            0x60, 0x0e, 0x1e, 0x95, 0x41, 0x6b, 0x52, 0x05, 0xa2, 0x09, 0xe1, 0xbf, 0x40, 0x05,
+            0x2f, 0xde, 0xf2, 0xad, 0x40, 0xb5, 0xf7, 0xde, 0x77, 0x35,
+        ];
+        msg.f_cookie = [
+            0xdc, 0x33, 0x90, 0x15, 0x8f, 0x82, 0x3e, 0x06, 0x44, 0xa0, 0xde, 0x4c, 0x15, 0x6c,
+            0x5d, 0xa4, 0x65, 0x99, 0xf6, 0x6c, 0xa1, 0x14, 0x77, 0xf9, 0xeb, 0x6a, 0xec, 0xc3,
+            0x3c, 0xda, 0x47, 0xe1,
+        ];
+
+        let buf: Vec<u8> = msg.as_bytes().to_vec();
+        let msg_p = CookieReply::parse(&buf[..]).unwrap();
+        assert_eq!(msg, *msg_p.into_ref());
+    }
+
+    #[test]
+    fn message_parse_invalid() {
+        let mut init: Initiation = Default::default();
+        let mut resp: Response = Default::default();
+        let mut cookie: CookieReply = Default::default();
+
+        // wrong message type
+        init.noise.f_type.set(TYPE_RESPONSE);
+        resp.noise.f_type.set(TYPE_COOKIE_REPLY);
+        cookie.f_type.set(TYPE_INITIATION);
+        assert!(Initiation::parse(init.as_bytes()).is_err());
+        assert!(Response::parse(resp.as_bytes()).is_err());
+        assert!(CookieReply::parse(cookie.as_bytes()).is_err());
+
+        // wrong message length
+        let init: Initiation = Default::default();
+        let cookie: CookieReply = Default::default();
+        let buf = init.as_bytes();
+        assert!(Initiation::parse(&buf[..buf.len() - 1]).is_err());
+        let mut buf = cookie.as_bytes().to_vec();
+        buf.push(0);
+        assert!(CookieReply::parse(&buf[..]).is_err());
+    }
 }
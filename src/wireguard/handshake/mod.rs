@@ -22,3 +22,4 @@ mod tests;
 
 pub use device::Device;
 pub use messages::{MAX_HANDSHAKE_MSG_SIZE, TYPE_COOKIE_REPLY, TYPE_INITIATION, TYPE_RESPONSE};
+pub use types::HandshakeError;
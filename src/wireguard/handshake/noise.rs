@@ -11,6 +11,7 @@ use hmac::Hmac;
 // AEAD
 use aead::{Aead, NewAead, Payload};
 use chacha20poly1305::ChaCha20Poly1305;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey};
 
 use log;
 
@@ -31,7 +32,7 @@ use super::peer::{Peer, State};
 use super::timestamp;
 use super::types::*;
 
-use super::super::types::{Key, KeyPair};
+use super::super::types::{Cipher, Key, KeyPair};
 
 // HMAC hasher (generic construction)
 
@@ -59,6 +60,8 @@ const INITIAL_HS: [u8; SIZE_HS] = [
     0x2d, 0x9c, 0x6c, 0x66, 0x22, 0x93, 0xe8, 0xb7, 0x0e, 0xe1, 0x9c, 0x65, 0xba, 0x07, 0x9e, 0xf3,
 ];
 
+const IDENTIFIER: &[u8] = b"WireGuard v1 zx2c4 Jason@zx2c4.com";
+
 const ZERO_NONCE: [u8; 12] = [0u8; 12];
 
 macro_rules! HASH {
@@ -114,28 +117,69 @@ macro_rules! KDF3 {
 }
 
 macro_rules! SEAL {
-    ($key:expr, $ad:expr, $pt:expr, $ct:expr) => {
-        ChaCha20Poly1305::new(*GenericArray::from_slice($key))
-            .encrypt(&ZERO_NONCE.into(), Payload { msg: $pt, aad: $ad })
-            .map(|ct| $ct.copy_from_slice(&ct))
-            .unwrap()
+    ($cipher:expr, $key:expr, $ad:expr, $pt:expr, $ct:expr) => {
+        match $cipher {
+            Cipher::ChaChaPoly => ChaCha20Poly1305::new(*GenericArray::from_slice($key))
+                .encrypt(&ZERO_NONCE.into(), Payload { msg: $pt, aad: $ad })
+                .map(|ct| $ct.copy_from_slice(&ct))
+                .unwrap(),
+            cipher => {
+                let pt: &[u8] = $pt;
+                let (body, tag) = $ct.split_at_mut(pt.len());
+                body.copy_from_slice(pt);
+                LessSafeKey::new(UnboundKey::new(cipher.algorithm(), &$key[..]).unwrap())
+                    .seal_in_place_separate_tag(
+                        Nonce::assume_unique_for_key(ZERO_NONCE),
+                        Aad::from($ad),
+                        body,
+                    )
+                    .map(|t| tag.copy_from_slice(t.as_ref()))
+                    .unwrap()
+            }
+        }
     };
 }
 
 macro_rules! OPEN {
-    ($key:expr, $ad:expr, $pt:expr, $ct:expr) => {
-        ChaCha20Poly1305::new(*GenericArray::from_slice($key))
-            .decrypt(&ZERO_NONCE.into(), Payload { msg: $ct, aad: $ad })
-            .map_err(|_| HandshakeError::DecryptionFailure)
-            .map(|pt| $pt.copy_from_slice(&pt))
+    ($cipher:expr, $key:expr, $ad:expr, $pt:expr, $ct:expr) => {
+        match $cipher {
+            Cipher::ChaChaPoly => ChaCha20Poly1305::new(*GenericArray::from_slice($key))
+                .decrypt(&ZERO_NONCE.into(), Payload { msg: $ct, aad: $ad })
+                .map_err(|_| HandshakeError::DecryptionFailure)
+                .map(|pt| $pt.copy_from_slice(&pt)),
+            cipher => {
+                let mut ct = $ct.to_vec();
+                LessSafeKey::new(UnboundKey::new(cipher.algorithm(), &$key[..]).unwrap())
+                    .open_in_place(
+                        Nonce::assume_unique_for_key(ZERO_NONCE),
+                        Aad::from($ad),
+                        &mut ct[..],
+                    )
+                    .map_err(|_| HandshakeError::DecryptionFailure)
+                    .map(|pt| $pt.copy_from_slice(pt))
+            }
+        }
     };
 }
 
+/* Initial chain key and hash of the construction:
+ * precomputed for the default construction (ChaChaPoly).
+ */
+fn initial_state(cipher: Cipher) -> ([u8; SIZE_CK], [u8; SIZE_HS]) {
+    match cipher {
+        Cipher::ChaChaPoly => (INITIAL_CK, INITIAL_HS),
+        cipher => {
+            let ck = HASH!(cipher.construction().as_bytes());
+            let hs = HASH!(&ck, IDENTIFIER);
+            (ck.into(), hs.into())
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    const IDENTIFIER: &[u8] = b"WireGuard v1 zx2c4 Jason@zx2c4.com";
     const CONSTRUCTION: &[u8] = b"Noise_IKpsk2_25519_ChaChaPoly_BLAKE2s";
 
     /* Sanity check precomputed initial chain key
@@ -217,6 +261,7 @@ mod tests {
 
 pub(super) fn create_initiation<R: RngCore + CryptoRng, O>(
     rng: &mut R,
+    cipher: Cipher,
     keyst: &KeyState,
     peer: &Peer<O>,
     pk: &PublicKey,
@@ -227,8 +272,7 @@ pub(super) fn create_initiation<R: RngCore + CryptoRng, O>(
     clear_stack_on_return(CLEAR_PAGES, || {
         // initialize state
 
-        let ck = INITIAL_CK;
-        let hs = INITIAL_HS;
+        let (ck, hs) = initial_state(cipher);
         let hs = HASH!(&hs, pk.as_bytes());
 
         msg.f_type.set(TYPE_INITIATION as u32);
@@ -258,6 +302,7 @@ pub(super) fn create_initiation<R: RngCore + CryptoRng, O>(
         // msg.static := Aead(k, 0, S_pub, H)
 
         SEAL!(
+            cipher,
             &key,
             &hs,                 // ad
             keyst.pk.as_bytes(), // pt
@@ -275,6 +320,7 @@ pub(super) fn create_initiation<R: RngCore + CryptoRng, O>(
         // msg.timestamp := Aead(k, 0, Timestamp(), H)
 
         SEAL!(
+            cipher,
             &key,
             &hs,                  // ad
             &timestamp::now(),    // pt
@@ -307,8 +353,8 @@ pub(super) fn consume_initiation<'a, O>(
     clear_stack_on_return(CLEAR_PAGES, || {
         // initialize new state
 
-        let ck = INITIAL_CK;
-        let hs = INITIAL_HS;
+        let cipher = device.cipher;
+        let (ck, hs) = initial_state(cipher);
         let hs = HASH!(&hs, keyst.pk.as_bytes());
 
         // C := Kdf(C, E_pub)
@@ -329,6 +375,7 @@ pub(super) fn consume_initiation<'a, O>(
         let mut pk = [0u8; 32];
 
         OPEN!(
+            cipher,
             &key,
             &hs,           // ad
             &mut pk,       // pt
@@ -354,6 +401,7 @@ pub(super) fn consume_initiation<'a, O>(
         let mut ts = timestamp::ZERO;
 
         OPEN!(
+            cipher,
             &key,
             &hs,              // ad
             &mut ts,          // pt
@@ -380,8 +428,10 @@ pub(super) fn consume_initiation<'a, O>(
 
 pub(super) fn create_response<R: RngCore + CryptoRng, O>(
     rng: &mut R,
+    cipher: Cipher,
     peer: &Peer<O>,
     pk: &PublicKey,
+    psk: &Psk,               // psk mixed into the response
     local: u32,              // sending identifier
     state: TemporaryState,   // state from "consume_initiation"
     msg: &mut NoiseResponse, // resulting response
@@ -423,7 +473,7 @@ pub(super) fn create_response<R: RngCore + CryptoRng, O>(
 
         // (C, tau, k) := Kdf3(C, Q)
 
-        let (ck, tau, key) = KDF3!(&ck, &peer.psk);
+        let (ck, tau, key) = KDF3!(&ck, psk);
 
         // H := Hash(H || tau)
 
@@ -432,6 +482,7 @@ pub(super) fn create_response<R: RngCore + CryptoRng, O>(
         // msg.empty := Aead(k, 0, [], H)
 
         SEAL!(
+            cipher,
             &key,
             &hs,              // ad
             &[],              // pt
@@ -450,6 +501,7 @@ pub(super) fn create_response<R: RngCore + CryptoRng, O>(
         Ok(KeyPair {
             birth: Instant::now(),
             initiator: false,
+            cipher,
             send: Key {
                 id: receiver,
                 key: key_send.into(),
@@ -486,6 +538,13 @@ pub(super) fn consume_response<'a, O>(
             _ => Err(HandshakeError::InvalidState),
         }?;
 
+        // check that the receiver id belongs to the pending initiation
+        // (the id is not covered by the handshake transcript)
+
+        if local != msg.f_receiver.get() {
+            return Err(HandshakeError::UnknownReceiverId);
+        }
+
         // C := Kdf1(C, E_pub)
 
         let ck = KDF1!(&ck, &msg.f_ephemeral);
@@ -503,22 +562,36 @@ pub(super) fn consume_response<'a, O>(
 
         let ck = KDF1!(&ck, keyst.sk.diffie_hellman(&eph_r_pk).as_bytes());
 
-        // (C, tau, k) := Kdf3(C, Q)
+        let open = |psk: &Psk| {
+            // (C, tau, k) := Kdf3(C, Q)
 
-        let (ck, tau, key) = KDF3!(&ck, &peer.psk);
+            let (ck, tau, key) = KDF3!(&ck, psk);
 
-        // H := Hash(H || tau)
+            // H := Hash(H || tau)
 
-        let hs = HASH!(&hs, tau);
+            let hs = HASH!(&hs, tau);
 
-        // msg.empty := Aead(k, 0, [], H)
+            // msg.empty := Aead(k, 0, [], H)
 
-        OPEN!(
-            &key,
-            &hs,          // ad
-            &mut [],      // pt
-            &msg.f_empty  // \epsilon || tag
-        )?;
+            OPEN!(
+                device.cipher,
+                &key,
+                &hs,          // ad
+                &mut [],      // pt
+                &msg.f_empty  // \epsilon || tag
+            )
+            .map(|_| ck)
+        };
+
+        // fallback to the previous psk during a rollover window
+
+        let ck = match open(&peer.psk()) {
+            Ok(ck) => ck,
+            Err(err) => match peer.previous_psk() {
+                Some(psk) => open(&psk)?,
+                None => return Err(err),
+            },
+        };
 
         // derive key-pair
 
@@ -548,6 +621,7 @@ pub(super) fn consume_response<'a, O>(
                 Some(KeyPair {
                     birth,
                     initiator: true,
+                    cipher: device.cipher,
                     send: Key {
                         id: remote,
                         key: key_send.into(),
@@ -10,6 +10,7 @@ use x25519_dalek::PublicKey;
 use x25519_dalek::StaticSecret;
 
 use clear_on_drop::clear::Clear;
+use subtle::ConstantTimeEq;
 
 use super::device::Device;
 use super::macs;
@@ -28,15 +29,22 @@ pub(super) struct Peer<O> {
 
     // mutable state
     pub state: Mutex<State>,
-    pub timestamp: Mutex<Option<timestamp::TAI64N>>,
+    pub timestamp: Mutex<Option<timestamp::TAI64N>>, // greatest timestamp seen (constant memory)
     pub last_initiation_consumption: Mutex<Option<Instant>>,
 
     // state related to DoS mitigation fields
     pub macs: Mutex<macs::Generator>,
 
+    // psk of peer (replaced during handshakes if a psk provider is set)
+    pub psk: Mutex<Psks>,
+
     // constant state
     pub ss: [u8; 32], // precomputed DH(static, static)
-    pub psk: Psk,     // psk of peer
+}
+
+pub struct Psks {
+    pub current: Psk,
+    pub previous: Option<(Psk, Instant)>, // previous psk (accepted until the instant)
 }
 
 pub enum State {
@@ -71,8 +79,68 @@ impl<O> Peer<O> {
             timestamp: Mutex::new(None),
             last_initiation_consumption: Mutex::new(None),
             ss,
-            psk: [0u8; 32],
+            psk: Mutex::new(Psks {
+                current: [0u8; 32],
+                previous: None,
+            }),
+        }
+    }
+
+    /// Return the current psk of the peer
+    pub fn psk(&self) -> Psk {
+        self.psk.lock().current
+    }
+
+    /// Return the previous psk of the peer,
+    /// if the rollover window has not yet closed.
+    pub fn previous_psk(&self) -> Option<Psk> {
+        self.psk.lock().previous.and_then(|(psk, until)| {
+            if Instant::now() < until {
+                Some(psk)
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Return the psk used to create responses:
+    /// the previous psk during the rollover window, the current psk otherwise.
+    ///
+    /// The response is accepted both by initiators which still use the previous psk
+    /// and by initiators which already rotated (these fall back to their previous psk).
+    pub fn response_psk(&self) -> Psk {
+        self.previous_psk().unwrap_or_else(|| self.psk())
+    }
+
+    /// Replace the psk (and forget any previous psk)
+    pub fn set_psk(&self, psk: Psk) {
+        *self.psk.lock() = Psks {
+            current: psk,
+            previous: None,
+        };
+    }
+
+    /// Replace the psk, accepting the previous psk for the duration of the window
+    pub fn rotate_psk(&self, psk: Psk, window: Duration) {
+        let mut psks = self.psk.lock();
+        psks.previous = Some((psks.current, Instant::now() + window));
+        psks.current = psk;
+    }
+
+    /// Rotate to the psk, unless it is already the current psk
+    /// (the psks are compared in constant time)
+    ///
+    /// # Returns
+    ///
+    /// True if the psk was replaced
+    pub fn refresh_psk(&self, psk: Psk, window: Duration) -> bool {
+        let mut psks = self.psk.lock();
+        if psks.current.ct_eq(&psk).into() {
+            return false;
         }
+        psks.previous = Some((psks.current, Instant::now() + window));
+        psks.current = psk;
+        true
     }
 
     pub fn reset_state(&self) -> Option<u32> {
@@ -97,7 +165,9 @@ impl<O> Peer<O> {
         let mut timestamp = self.timestamp.lock();
         let mut last_initiation_consumption = self.last_initiation_consumption.lock();
 
-        // check replay attack
+        // check replay attack:
+        // as prescribed by the whitepaper, only the greatest timestamp is retained
+        // and any initiation not strictly newer is rejected.
         match *timestamp {
             Some(timestamp_old) => {
                 if !timestamp::compare(&timestamp_old, &timestamp_new) {
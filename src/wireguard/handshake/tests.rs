@@ -8,11 +8,19 @@ use hex;
 
 use rand::prelude::{CryptoRng, RngCore};
 use rand::rngs::OsRng;
+use rand_chacha::ChaCha8Rng;
+use rand_core::SeedableRng;
 
 use x25519_dalek::PublicKey;
 use x25519_dalek::StaticSecret;
 
+use zerocopy::AsBytes;
+
+use super::macs;
 use super::messages::{Initiation, Response};
+use super::types::HandshakeError;
+
+use super::super::types::Cipher;
 
 fn setup_devices<R: RngCore + CryptoRng, O: Default>(
     rng: &mut R,
@@ -197,3 +205,441 @@ fn handshake_no_load() {
     dev1.remove(&pk2).unwrap();
     dev2.remove(&pk1).unwrap();
 }
+
+#[test]
+fn handshake_psk_rollover() {
+    let (pk1, mut dev1, pk2, mut dev2): (_, Device<usize>, _, _) = setup_devices(&mut OsRng);
+
+    // device-1 rotates to a new psk, device-2 still uses the old psk
+
+    let mut psk = [0u8; 32];
+    OsRng.fill_bytes(&mut psk[..]);
+    dev1.rotate_psk(pk2, psk, Duration::from_millis(200))
+        .unwrap();
+
+    // handshake authenticated with the old psk succeeds during the window
+
+    let msg1 = dev1.begin(&mut OsRng, &pk2).unwrap();
+    let (_, msg2, ks_r) = dev2
+        .process(&mut OsRng, &msg1, None)
+        .expect("failed to process initiation");
+    let (_, _, ks_i) = dev1
+        .process(&mut OsRng, &msg2.unwrap(), None)
+        .expect("failed to accept previous psk during rollover window");
+
+    let ks_r = ks_r.unwrap();
+    let ks_i = ks_i.unwrap();
+    assert_eq!(ks_i.send, ks_r.recv, "KeyI.send != KeyR.recv");
+    assert_eq!(ks_i.recv, ks_r.send, "KeyI.recv != KeyR.send");
+
+    dev1.release(ks_i.local_id());
+    dev2.release(ks_r.local_id());
+
+    // wait for the rollover window to close
+
+    thread::sleep(Duration::from_millis(250));
+
+    // handshake authenticated with the old psk is rejected after the window
+
+    let msg1 = dev1.begin(&mut OsRng, &pk2).unwrap();
+    let (_, msg2, _) = dev2
+        .process(&mut OsRng, &msg1, None)
+        .expect("failed to process initiation");
+    assert!(
+        dev1.process(&mut OsRng, &msg2.unwrap(), None).is_err(),
+        "accepted previous psk after rollover window"
+    );
+
+    dev1.remove(&pk2).unwrap();
+    dev2.remove(&pk1).unwrap();
+}
+
+#[test]
+fn handshake_psk_rollover_responder() {
+    let (pk1, mut dev1, pk2, mut dev2): (_, Device<usize>, _, _) = setup_devices(&mut OsRng);
+
+    // device-2 (the responder) rotates to a new psk, device-1 still uses the old psk
+
+    let mut psk = [0u8; 32];
+    OsRng.fill_bytes(&mut psk[..]);
+    dev2.rotate_psk(pk1, psk, Duration::from_millis(200))
+        .unwrap();
+
+    // the responder accepts the old psk during the window
+
+    let msg1 = dev1.begin(&mut OsRng, &pk2).unwrap();
+    let (_, msg2, ks_r) = dev2
+        .process(&mut OsRng, &msg1, None)
+        .expect("failed to process initiation");
+    let (_, _, ks_i) = dev1
+        .process(&mut OsRng, &msg2.unwrap(), None)
+        .expect("responder rejected previous psk during rollover window");
+
+    let ks_r = ks_r.unwrap();
+    let ks_i = ks_i.unwrap();
+    assert_eq!(ks_i.send, ks_r.recv, "KeyI.send != KeyR.recv");
+    assert_eq!(ks_i.recv, ks_r.send, "KeyI.recv != KeyR.send");
+
+    dev1.release(ks_i.local_id());
+    dev2.release(ks_r.local_id());
+
+    // wait for the rollover window to close
+
+    thread::sleep(Duration::from_millis(250));
+
+    // the responder rejects the old psk after the window
+
+    let msg1 = dev1.begin(&mut OsRng, &pk2).unwrap();
+    let (_, msg2, _) = dev2
+        .process(&mut OsRng, &msg1, None)
+        .expect("failed to process initiation");
+    assert!(
+        dev1.process(&mut OsRng, &msg2.unwrap(), None).is_err(),
+        "responder accepted previous psk after rollover window"
+    );
+
+    // once rotated on both ends, handshakes succeed again
+
+    dev1.set_psk(pk2, psk).unwrap();
+    wait();
+    let msg1 = dev1.begin(&mut OsRng, &pk2).unwrap();
+    let (_, msg2, _) = dev2
+        .process(&mut OsRng, &msg1, None)
+        .expect("failed to process initiation");
+    dev1.process(&mut OsRng, &msg2.unwrap(), None)
+        .expect("failed to process response using the new psk");
+
+    dev1.remove(&pk2).unwrap();
+    dev2.remove(&pk1).unwrap();
+}
+
+#[test]
+fn handshake_without_psk() {
+    let sk1 = StaticSecret::new(&mut OsRng);
+    let pk1 = PublicKey::from(&sk1);
+    let sk2 = StaticSecret::new(&mut OsRng);
+    let pk2 = PublicKey::from(&sk2);
+
+    // no psk configured on either end (the psk defaults to 0^32)
+
+    let mut dev1: Device<usize> = Device::new();
+    let mut dev2: Device<usize> = Device::new();
+    dev1.set_sk(Some(sk1));
+    dev2.set_sk(Some(sk2));
+    dev1.add(pk2, 0).unwrap();
+    dev2.add(pk1, 0).unwrap();
+
+    let msg1 = dev1.begin(&mut OsRng, &pk2).unwrap();
+    let (_, msg2, ks_r) = dev2
+        .process(&mut OsRng, &msg1, None)
+        .expect("failed to process initiation without psk");
+    let (_, _, ks_i) = dev1
+        .process(&mut OsRng, &msg2.unwrap(), None)
+        .expect("failed to process response without psk");
+
+    let ks_r = ks_r.unwrap();
+    let ks_i = ks_i.unwrap();
+    assert_eq!(ks_i.send, ks_r.recv, "KeyI.send != KeyR.recv");
+    assert_eq!(ks_i.recv, ks_r.send, "KeyI.recv != KeyR.send");
+
+    dev1.release(ks_i.local_id());
+    dev2.release(ks_r.local_id());
+
+    // a psk configured on only one end fails authentication
+
+    dev1.set_psk(pk2, [0x42u8; 32]).unwrap();
+
+    wait();
+    let msg1 = dev1.begin(&mut OsRng, &pk2).unwrap();
+    let (_, msg2, _) = dev2
+        .process(&mut OsRng, &msg1, None)
+        .expect("failed to process initiation");
+    assert!(
+        dev1.process(&mut OsRng, &msg2.unwrap(), None).is_err(),
+        "accepted response with mismatched psk"
+    );
+}
+
+#[test]
+fn handshake_initiation_flood() {
+    let (pk1, dev1, pk2, mut dev2): (_, Device<usize>, _, _) = setup_devices(&mut OsRng);
+
+    // add a third device as another peer of device-2
+
+    let sk3 = StaticSecret::new(&mut OsRng);
+    let pk3 = PublicKey::from(&sk3);
+    let mut dev3: Device<usize> = Device::new();
+    dev3.set_sk(Some(sk3));
+    dev3.add(pk2, 0).unwrap();
+    dev2.add(pk3, 0).unwrap();
+
+    // device-1 : first initiation is accepted
+
+    let msg1 = dev1.begin(&mut OsRng, &pk2).unwrap();
+    dev2.process(&mut OsRng, &msg1, None)
+        .expect("failed to process initiation");
+
+    // device-1 : flood of initiations is throttled
+
+    for _ in 0..5 {
+        let msg1 = dev1.begin(&mut OsRng, &pk2).unwrap();
+        match dev2.process(&mut OsRng, &msg1, None) {
+            Err(HandshakeError::InitiationFlood) => (),
+            _ => panic!("initiation flood was not throttled"),
+        }
+    }
+
+    // device-3 : is unaffected by the flood from device-1
+
+    let msg3 = dev3.begin(&mut OsRng, &pk2).unwrap();
+    dev2.process(&mut OsRng, &msg3, None)
+        .expect("initiation from other peer was throttled");
+
+    // device-1 : initiations are accepted again after the flood window
+
+    wait();
+    let msg1 = dev1.begin(&mut OsRng, &pk2).unwrap();
+    dev2.process(&mut OsRng, &msg1, None)
+        .expect("initiation was throttled after flood window");
+
+    dev2.remove(&pk1).unwrap();
+    dev2.remove(&pk3).unwrap();
+}
+
+/* The handshake device draws all randomness
+ * (sender ids, ephemeral keys and cookie secrets) from the supplied rng,
+ * hence initiations are reproducible when using a seeded rng.
+ */
+#[test]
+fn handshake_seeded_rng() {
+    let mut rng1 = ChaCha8Rng::seed_from_u64(0x5eed);
+    let mut rng2 = ChaCha8Rng::seed_from_u64(0x5eed);
+
+    let (_, dev1, pk2, _): (_, Device<usize>, _, Device<usize>) = setup_devices(&mut rng1);
+    let (_, dev3, pk4, _): (_, Device<usize>, _, Device<usize>) = setup_devices(&mut rng2);
+    assert_eq!(pk2.as_bytes(), pk4.as_bytes());
+
+    let msg1 = dev1.begin(&mut rng1, &pk2).unwrap();
+    let msg2 = dev3.begin(&mut rng2, &pk4).unwrap();
+
+    let init1 = Initiation::parse(&msg1[..]).unwrap();
+    let init2 = Initiation::parse(&msg2[..]).unwrap();
+
+    assert_eq!(
+        init1.noise.f_sender.get(),
+        init2.noise.f_sender.get(),
+        "sender id not reproducible"
+    );
+    assert_eq!(
+        init1.noise.f_ephemeral, init2.noise.f_ephemeral,
+        "ephemeral key not reproducible"
+    );
+}
+
+#[test]
+fn handshake_response_receiver_mismatch() {
+    let (pk1, dev1, pk2, dev2): (_, Device<usize>, _, _) = setup_devices(&mut OsRng);
+
+    // device-1 : allocate an id as responder (for an initiation from device-2)
+
+    let msg = dev2.begin(&mut OsRng, &pk1).unwrap();
+    let (_, _, ks) = dev1
+        .process(&mut OsRng, &msg, None)
+        .expect("failed to process initiation");
+    let other = ks.unwrap().local_id();
+
+    // device-1 : initiate, device-2 : respond
+
+    let msg1 = dev1.begin(&mut OsRng, &pk2).unwrap();
+    let (_, msg2, _) = dev2
+        .process(&mut OsRng, &msg1, None)
+        .expect("failed to process initiation");
+    let mut msg2 = msg2.unwrap();
+
+    // redirect the response to the other id allocated for the peer (with valid mac1)
+
+    {
+        let mut resp = Response::parse(&mut msg2[..]).unwrap();
+        resp.noise.f_receiver.set(other);
+        let noise = resp.noise;
+        macs::Generator::new(pk1).generate(noise.as_bytes(), &mut resp.macs);
+    }
+
+    assert!(
+        dev1.process(&mut OsRng, &msg2, None).is_err(),
+        "accepted response for id not belonging to the pending initiation"
+    );
+}
+
+#[test]
+fn handshake_duplicate_response() {
+    let (_, dev1, pk2, dev2): (_, Device<usize>, _, _) = setup_devices(&mut OsRng);
+
+    let msg1 = dev1.begin(&mut OsRng, &pk2).unwrap();
+    let (_, msg2, _) = dev2
+        .process(&mut OsRng, &msg1, None)
+        .expect("failed to process initiation");
+    let msg2 = msg2.unwrap();
+
+    // the first response completes the handshake
+
+    let (_, _, ks_i) = dev1
+        .process(&mut OsRng, &msg2, None)
+        .expect("failed to process response");
+    assert!(ks_i.is_some(), "no key-pair derived from response");
+
+    // a duplicate of the response is ignored (without deriving another key-pair)
+
+    match dev1.process(&mut OsRng, &msg2, None) {
+        Err(HandshakeError::InvalidState) => (),
+        Err(e) => panic!("unexpected error for duplicate response: {:?}", e),
+        Ok(_) => panic!("accepted duplicate response"),
+    }
+
+    // the device remains usable: a new handshake succeeds
+
+    wait();
+    let msg1 = dev1.begin(&mut OsRng, &pk2).unwrap();
+    let (_, msg2, _) = dev2
+        .process(&mut OsRng, &msg1, None)
+        .expect("failed to process initiation");
+    dev1.process(&mut OsRng, &msg2.unwrap(), None)
+        .expect("failed to process response after duplicate");
+}
+
+#[test]
+fn handshake_low_order_public_key() {
+    let mut dev: Device<usize> = Device::new();
+    dev.set_sk(Some(StaticSecret::new(&mut OsRng)));
+
+    // points of low order (including the all-zero key) are rejected
+
+    for point in vec![
+        "0000000000000000000000000000000000000000000000000000000000000000",
+        "0100000000000000000000000000000000000000000000000000000000000000",
+        "e0eb7a7c3b41b8ae1656e3faf19fc46ada098deb9c32b1fd866205165f49b800",
+        "5f9c95bca3508c24b1d0b1559c83ef5b04445cc4581c8e86d8224eddd09f1157",
+        "ecffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff7f",
+    ] {
+        let mut pk = [0u8; 32];
+        pk.copy_from_slice(&hex::decode(point).unwrap());
+        assert!(
+            dev.add(PublicKey::from(pk), 0).is_err(),
+            "accepted low order public key {}",
+            point
+        );
+    }
+
+    // a valid public key is accepted and a handshake can be initiated
+
+    let pk = PublicKey::from(&StaticSecret::new(&mut OsRng));
+    dev.add(pk, 0).unwrap();
+    dev.begin(&mut OsRng, &pk).unwrap();
+}
+
+#[test]
+fn handshake_unknown_initiator() {
+    let (_pk1, _dev1, pk2, dev2): (_, Device<usize>, _, _) = setup_devices(&mut OsRng);
+
+    // a device whose static key is not configured at the responder
+
+    let mut dev3: Device<usize> = Device::new();
+    dev3.set_sk(Some(StaticSecret::new(&mut OsRng)));
+    dev3.add(pk2, 0).unwrap();
+
+    // the initiation is dropped without a response
+
+    let msg1 = dev3.begin(&mut OsRng, &pk2).unwrap();
+    match dev2.process(&mut OsRng, &msg1, None) {
+        Err(HandshakeError::UnknownPublicKey) => (),
+        Err(e) => panic!("unexpected error: {}", e),
+        Ok(_) => panic!("responded to an unknown initiator"),
+    }
+}
+
+#[test]
+fn handshake_replayed_initiation() {
+    let (_, dev1, pk2, dev2): (_, Device<usize>, _, _) = setup_devices(&mut OsRng);
+
+    // an initiation captured (and withheld) by an attacker
+
+    let old = dev1.begin(&mut OsRng, &pk2).unwrap();
+    wait();
+
+    let msg1 = dev1.begin(&mut OsRng, &pk2).unwrap();
+    dev2.process(&mut OsRng, &msg1, None)
+        .expect("failed to process initiation");
+
+    // neither a replay nor an older initiation is accepted
+
+    wait();
+    for msg in vec![&msg1, &old] {
+        match dev2.process(&mut OsRng, msg, None) {
+            Err(HandshakeError::OldTimestamp) => (),
+            Err(e) => panic!("unexpected error for replayed initiation: {}", e),
+            Ok(_) => panic!("accepted replayed initiation"),
+        }
+    }
+
+    // a fresh initiation is accepted
+
+    let msg1 = dev1.begin(&mut OsRng, &pk2).unwrap();
+    dev2.process(&mut OsRng, &msg1, None)
+        .expect("failed to process initiation after replay");
+}
+
+#[test]
+fn handshake_aesgcm_construction() {
+    let (pk1, mut dev1, pk2, mut dev2): (_, Device<usize>, _, _) = setup_devices(&mut OsRng);
+
+    // only constructions in the allowlist are accepted
+
+    assert!(dev1
+        .set_construction("Noise_IKpsk2_25519_AESGCM_SHA256")
+        .is_err());
+    assert_eq!(
+        dev1.get_construction(),
+        "Noise_IKpsk2_25519_ChaChaPoly_BLAKE2s"
+    );
+
+    // both devices use AES-GCM
+
+    dev1.set_construction("Noise_IKpsk2_25519_AESGCM_BLAKE2s")
+        .unwrap();
+    dev2.set_construction("Noise_IKpsk2_25519_AESGCM_BLAKE2s")
+        .unwrap();
+
+    let msg1 = dev1.begin(&mut OsRng, &pk2).unwrap();
+    let (_, msg2, ks_r) = dev2
+        .process(&mut OsRng, &msg1, None)
+        .expect("failed to process initiation");
+    let (_, _, ks_i) = dev1
+        .process(&mut OsRng, &msg2.unwrap(), None)
+        .expect("failed to process response");
+
+    let ks_r = ks_r.unwrap();
+    let ks_i = ks_i.unwrap();
+    assert_eq!(ks_i.send, ks_r.recv, "KeyI.send != KeyR.recv");
+    assert_eq!(ks_i.recv, ks_r.send, "KeyI.recv != KeyR.send");
+    assert_eq!(ks_i.cipher, Cipher::AESGCM);
+    assert_eq!(ks_r.cipher, Cipher::AESGCM);
+
+    dev1.release(ks_i.local_id());
+    dev2.release(ks_r.local_id());
+
+    // avoid initiation flood detection
+    wait();
+
+    // an initiation using a different construction is rejected
+
+    dev2.set_construction("Noise_IKpsk2_25519_ChaChaPoly_BLAKE2s")
+        .unwrap();
+    let msg1 = dev1.begin(&mut OsRng, &pk2).unwrap();
+    assert!(
+        dev2.process(&mut OsRng, &msg1, None).is_err(),
+        "accepted initiation using a different construction"
+    );
+
+    dev1.remove(&pk2).unwrap();
+    dev2.remove(&pk1).unwrap();
+}
@@ -22,11 +22,61 @@ pub fn now() -> TAI64N {
     res
 }
 
+/* Check if the new timestamp is strictly greater than the old timestamp.
+ *
+ * TAI64N timestamps are big-endian, hence compared lexicographically.
+ */
 pub fn compare(old: &TAI64N, new: &TAI64N) -> bool {
-    for i in 0..12 {
-        if new[i] > old[i] {
-            return true;
+    new > old
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timestamp(secs: u64, nano: u32) -> TAI64N {
+        let mut res = [0u8; 12];
+        res[..8].copy_from_slice(&(secs + TAI64_EPOCH).to_be_bytes()[..]);
+        res[8..].copy_from_slice(&nano.to_be_bytes()[..]);
+        res
+    }
+
+    #[test]
+    fn test_compare() {
+        // (secs, nano, accepted) received in order,
+        // a timestamp is accepted only if strictly greater than every accepted before it
+        let received = [
+            (10, 500, true),
+            (10, 500, false),        // replay
+            (9, 999_999_999, false), // older second, larger nanoseconds
+            (11, 0, true),
+            (10, 999_999_999, false), // newer than the first, older than the greatest
+            (11, 1, true),
+            (12, 0, true),
+            (11, 999_999_999, false),
+        ];
+
+        let mut greatest = ZERO;
+        for (secs, nano, accepted) in received.iter() {
+            let ts = timestamp(*secs, *nano);
+            assert_eq!(
+                compare(&greatest, &ts),
+                *accepted,
+                "timestamp ({}, {})",
+                secs,
+                nano
+            );
+            if *accepted {
+                greatest = ts;
+            }
         }
     }
-    return false;
+
+    #[test]
+    fn test_now() {
+        let t1 = now();
+        let t2 = now();
+        assert!(compare(&ZERO, &t1));
+        assert!(!compare(&t2, &t1));
+    }
 }
@@ -12,6 +12,7 @@ mod handshake;
 mod peer;
 mod queue;
 mod router;
+mod selftest;
 mod timers;
 mod types;
 mod wireguard;
@@ -26,6 +27,21 @@ pub use peer::Peer;
 // represents a WireGuard interface
 pub use wireguard::WireGuard;
 
+// describes the configuration of a peer
+pub use types::PeerConfig;
+
+// allocation strategy of handshake indexes
+pub use types::IndexStrategy;
+
+// serialized sessions (for hand-off to another instance)
+pub use types::Sessions;
+
+// source of preshared keys (e.g. an out-of-band key exchange)
+pub use types::PskProvider;
+
+// self-test of the cryptographic stack
+pub use selftest::crypto_self_test;
+
 #[cfg(test)]
 pub use types::dummy_keypair;
 
@@ -4,16 +4,20 @@ use super::timers::{Events, Timers};
 use super::tun::Tun;
 use super::udp::UDP;
 
-use super::constants::REKEY_TIMEOUT;
+use super::constants::{
+    MAX_REKEY_BACKOFF, MAX_TIMER_HANDSHAKES, REKEY_TIMEOUT, STATS_HISTORY_LENGTH, TIME_HORIZON,
+};
 use super::wireguard::WireGuard;
 use super::workers::HandshakeJob;
 
+use std::cmp;
 use std::fmt;
 use std::ops::Deref;
-use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
-use std::time::{Instant, SystemTime};
+use std::time::{Duration, Instant, SystemTime};
 
+use arraydeque::{ArrayDeque, Wrapping};
 use spin::{Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
 
 use x25519_dalek::PublicKey;
@@ -29,11 +33,17 @@ pub struct PeerInner<T: Tun, B: UDP> {
     pub walltime_last_handshake: Mutex<Option<SystemTime>>, // walltime for last handshake (for UAPI status)
     pub last_handshake_sent: Mutex<Instant>,                // instant for last handshake
     pub handshake_queued: AtomicBool, // is a handshake job currently queued for the peer?
+    pub handshake_initiations: AtomicUsize, // initiations sent since the last completed handshake
 
     // stats and configuration
-    pub pk: PublicKey,       // public key
-    pub rx_bytes: AtomicU64, // received bytes
-    pub tx_bytes: AtomicU64, // transmitted bytes
+    pub pk: PublicKey,         // public key
+    pub rx_bytes: AtomicU64,   // received bytes
+    pub tx_bytes: AtomicU64,   // transmitted bytes
+    pub rx_packets: AtomicU64, // received transport messages (including keepalives)
+    pub tx_packets: AtomicU64, // transmitted transport messages (including keepalives)
+
+    // recent samples of the byte counters: (time, rx_bytes, tx_bytes)
+    pub history: Mutex<ArrayDeque<[(Instant, u64, u64); STATS_HISTORY_LENGTH], Wrapping>>,
 
     // timer model
     pub timers: RwLock<Timers>,
@@ -54,6 +64,30 @@ impl<T: Tun, B: UDP> Clone for Peer<T, B> {
 }
 
 impl<T: Tun, B: UDP> PeerInner<T, B> {
+    /* Returns the minimum interval between handshake initiations.
+     *
+     * Once the peer has failed to complete a handshake for REKEY_ATTEMPT_TIME,
+     * the interval grows exponentially (up to MAX_REKEY_BACKOFF),
+     * until a handshake is completed.
+     */
+    pub fn handshake_initiation_interval(&self) -> Duration {
+        let sent = self.handshake_initiations.load(Ordering::Relaxed);
+        if sent <= MAX_TIMER_HANDSHAKES {
+            return REKEY_TIMEOUT;
+        }
+
+        // bound the exponent to avoid overflow
+        let exp = cmp::min(sent - MAX_TIMER_HANDSHAKES, 16) as u32;
+        cmp::min(REKEY_TIMEOUT * (1 << exp), MAX_REKEY_BACKOFF)
+    }
+
+    /* Returns true if the peer has failed to complete a handshake
+     * within REKEY_ATTEMPT_TIME (since the last completed handshake).
+     */
+    pub fn handshake_failed(&self) -> bool {
+        self.handshake_initiations.load(Ordering::Relaxed) > MAX_TIMER_HANDSHAKES
+    }
+
     /* Queue a handshake request for the parallel workers
      * (if one does not already exist)
      *
@@ -65,7 +99,7 @@ impl<T: Tun, B: UDP> PeerInner<T, B> {
         // the function is rate limited
         {
             let mut lhs = self.last_handshake_sent.lock();
-            if lhs.elapsed() < REKEY_TIMEOUT {
+            if lhs.elapsed() < self.handshake_initiation_interval() {
                 log::trace!("{} : packet_send_handshake_initiation, rate-limited!", self);
                 return;
             }
@@ -88,6 +122,14 @@ impl<T: Tun, B: UDP> PeerInner<T, B> {
         }
     }
 
+    /// Record a sample of the byte counters
+    /// (dropping the oldest sample once STATS_HISTORY_LENGTH samples are retained)
+    pub fn sample_history(&self, now: Instant) {
+        let rx = self.rx_bytes.load(Ordering::Relaxed);
+        let tx = self.tx_bytes.load(Ordering::Relaxed);
+        self.history.lock().push_back((now, rx, tx));
+    }
+
     #[inline(always)]
     pub fn timers(&self) -> RwLockReadGuard<Timers> {
         self.timers.read()
@@ -123,9 +165,12 @@ impl<T: Tun, B: UDP> Peer<T, B> {
     ///
     /// - Timers to be stopped and disabled.
     /// - All keystate to be zeroed
+    /// - The handshake rate limit to be reset (allowing an immediate handshake when up)
     pub fn down(&self) {
         self.stop_timers();
         self.router.down();
+        *self.last_handshake_sent.lock() = Instant::now() - TIME_HORIZON;
+        self.handshake_initiations.store(0, Ordering::SeqCst);
     }
 
     /// Bring the peer up.
@@ -33,7 +33,47 @@ impl<T> ParallelQueue<T> {
         });
     }
 
+    /// Attempt to send a value without blocking
+    ///
+    /// # Returns
+    ///
+    /// A bool indicating if the value was queued,
+    /// the value is dropped if the queue is full or closed.
+    pub fn try_send(&self, v: T) -> bool {
+        self.queue
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|s| s.try_send(v).is_ok())
+            .unwrap_or(false)
+    }
+
     pub fn close(&self) {
         *self.queue.lock().unwrap() = None;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_try_send_full() {
+        let (queue, rxs) = ParallelQueue::new(2, 2);
+
+        // fill the queue
+        assert!(queue.try_send(1));
+        assert!(queue.try_send(2));
+
+        // value is dropped when the queue is full
+        assert!(!queue.try_send(3));
+
+        // capacity is released when a reader consumes a value
+        assert_eq!(rxs[1].recv().unwrap(), 1);
+        assert!(queue.try_send(4));
+
+        // value is dropped when the queue is closed
+        queue.close();
+        assert!(!queue.try_send(5));
+    }
+}
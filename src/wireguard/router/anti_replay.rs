@@ -17,14 +17,15 @@ const REDUNDANT_BIT_SHIFTS: usize = 5;
 
 const SIZE_OF_WORD: usize = mem::size_of::<Word>() * 8;
 
-const BITMAP_BITLEN: usize = 2048;
-const BITMAP_LEN: usize = BITMAP_BITLEN / SIZE_OF_WORD;
-const BITMAP_INDEX_MASK: u64 = BITMAP_LEN as u64 - 1;
+// bounds (and default) of the size of the bitmap in bits
+pub const MIN_BITMAP_BITLEN: usize = 128;
+pub const MAX_BITMAP_BITLEN: usize = 1 << 16;
+pub const DEFAULT_BITMAP_BITLEN: usize = 2048;
+
 const BITMAP_LOC_MASK: u64 = (SIZE_OF_WORD - 1) as u64;
-const WINDOW_SIZE: u64 = (BITMAP_BITLEN - SIZE_OF_WORD) as u64;
 
 pub struct AntiReplay {
-    bitmap: [Word; BITMAP_LEN],
+    bitmap: Box<[Word]>,
     last: u64,
 }
 
@@ -36,11 +37,54 @@ impl Default for AntiReplay {
 
 impl AntiReplay {
     pub fn new() -> Self {
+        AntiReplay::with_bitlen(DEFAULT_BITMAP_BITLEN)
+    }
+
+    /// Create a replay filter with a bitmap of the given size,
+    /// which must be a power of two between MIN_BITMAP_BITLEN and MAX_BITMAP_BITLEN.
+    ///
+    /// The window of accepted sequence numbers is one word smaller than the bitmap.
+    pub fn with_bitlen(bitlen: usize) -> Self {
         debug_assert_eq!(1 << REDUNDANT_BIT_SHIFTS, SIZE_OF_WORD);
-        debug_assert_eq!(BITMAP_BITLEN % SIZE_OF_WORD, 0);
+        debug_assert!(AntiReplay::valid_bitlen(bitlen));
         AntiReplay {
             last: 0,
-            bitmap: [0; BITMAP_LEN],
+            bitmap: vec![0; bitlen / SIZE_OF_WORD].into_boxed_slice(),
+        }
+    }
+
+    /// Resume the replay filter of a session at the greatest sequence number seen
+    /// (e.g. by another instance), conservatively treating every prior sequence number as seen.
+    pub fn resume(last: u64, bitlen: usize) -> Self {
+        debug_assert!(AntiReplay::valid_bitlen(bitlen));
+        AntiReplay {
+            last,
+            bitmap: vec![!0; bitlen / SIZE_OF_WORD].into_boxed_slice(),
+        }
+    }
+
+    /// Check if the bitmap size is supported
+    pub fn valid_bitlen(bitlen: usize) -> bool {
+        bitlen.is_power_of_two() && bitlen >= MIN_BITMAP_BITLEN && bitlen <= MAX_BITMAP_BITLEN
+    }
+
+    /// Returns the number of sequence numbers (behind the greatest seen) accepted
+    pub fn window_size(&self) -> u64 {
+        ((self.bitmap.len() - 1) * SIZE_OF_WORD) as u64
+    }
+
+    #[inline(always)]
+    fn index_mask(&self) -> u64 {
+        self.bitmap.len() as u64 - 1
+    }
+
+    /// Returns the greatest sequence number seen (none if no sequence number was seen)
+    pub fn greatest(&self) -> Option<u64> {
+        // a last of zero is ambiguous: zero was seen iff its bit is set
+        if self.last == 0 && self.bitmap[0] & 1 == 0 {
+            None
+        } else {
+            Some(self.last)
         }
     }
 
@@ -53,12 +97,12 @@ impl AntiReplay {
             return true;
         }
 
-        if self.last - seq > WINDOW_SIZE {
+        if self.last - seq > self.window_size() {
             return false;
         }
 
         let bit_location = seq & BITMAP_LOC_MASK;
-        let index = (seq >> REDUNDANT_BIT_SHIFTS) & BITMAP_INDEX_MASK;
+        let index = (seq >> REDUNDANT_BIT_SHIFTS) & self.index_mask();
 
         self.bitmap[index as usize] & (1 << bit_location) == 0
     }
@@ -73,11 +117,13 @@ impl AntiReplay {
             let index_cur = self.last >> REDUNDANT_BIT_SHIFTS;
             let diff = index - index_cur;
 
-            if diff >= BITMAP_LEN as u64 {
-                self.bitmap = [0; BITMAP_LEN];
+            if diff >= self.bitmap.len() as u64 {
+                for word in self.bitmap.iter_mut() {
+                    *word = 0;
+                }
             } else {
                 for i in 0..diff {
-                    let real_index = (index_cur + i + 1) & BITMAP_INDEX_MASK;
+                    let real_index = (index_cur + i + 1) & self.index_mask();
                     self.bitmap[real_index as usize] = 0;
                 }
             }
@@ -85,7 +131,7 @@ impl AntiReplay {
             self.last = seq;
         }
 
-        let index = index & BITMAP_INDEX_MASK;
+        let index = index & self.index_mask();
         let bit_location = seq & BITMAP_LOC_MASK;
         self.bitmap[index as usize] |= 1 << bit_location;
     }
@@ -117,6 +163,8 @@ mod tests {
     #[test]
     fn anti_replay() {
         let mut ar = AntiReplay::new();
+        let window_size = ar.window_size();
+        assert_eq!(window_size, (DEFAULT_BITMAP_BITLEN - SIZE_OF_WORD) as u64);
 
         for i in 0..20000 {
             assert!(ar.update(i));
@@ -127,11 +175,11 @@ mod tests {
         }
 
         assert!(ar.update(65536));
-        for i in (65536 - WINDOW_SIZE)..65535 {
+        for i in (65536 - window_size)..65535 {
             assert!(ar.update(i));
         }
 
-        for i in (65536 - 10 * WINDOW_SIZE)..65535 {
+        for i in (65536 - 10 * window_size)..65535 {
             assert!(!ar.check(i));
         }
 
@@ -147,11 +195,67 @@ mod tests {
         let next = u64::max_value();
         assert!(ar.update(next));
         assert!(!ar.check(next));
-        for i in (next - WINDOW_SIZE)..next {
+        for i in (next - window_size)..next {
             assert!(ar.update(i));
         }
-        for i in (next - 20 * WINDOW_SIZE)..next {
+        for i in (next - 20 * window_size)..next {
+            assert!(!ar.check(i));
+        }
+    }
+
+    #[test]
+    fn anti_replay_resume() {
+        let mut ar = AntiReplay::resume(5000, DEFAULT_BITMAP_BITLEN);
+        assert_eq!(ar.greatest(), Some(5000));
+
+        for i in 0..=5000 {
             assert!(!ar.check(i));
         }
+
+        assert!(ar.update(5001));
+        assert!(!ar.update(5001));
+        assert!(ar.update(5003));
+        assert!(ar.update(5002));
+    }
+
+    #[test]
+    fn anti_replay_greatest() {
+        let mut ar = AntiReplay::new();
+        assert_eq!(ar.greatest(), None);
+
+        // zero is distinguished from nothing received
+        assert!(ar.update(0));
+        assert_eq!(ar.greatest(), Some(0));
+        assert!(ar.update(7));
+        assert_eq!(ar.greatest(), Some(7));
+    }
+
+    #[test]
+    fn anti_replay_small_window() {
+        assert!(!AntiReplay::valid_bitlen(0));
+        assert!(!AntiReplay::valid_bitlen(64));
+        assert!(!AntiReplay::valid_bitlen(1000));
+        assert!(!AntiReplay::valid_bitlen(MAX_BITMAP_BITLEN * 2));
+        assert!(AntiReplay::valid_bitlen(MIN_BITMAP_BITLEN));
+
+        let mut ar = AntiReplay::with_bitlen(MIN_BITMAP_BITLEN);
+        let window_size = ar.window_size();
+        assert_eq!(window_size, (MIN_BITMAP_BITLEN - SIZE_OF_WORD) as u64);
+
+        assert!(ar.update(1000));
+
+        // reordered within the window: accepted (once)
+        assert!(ar.update(1000 - window_size));
+        assert!(ar.update(1000 - 1));
+        assert!(!ar.update(1000 - 1));
+
+        // reordered beyond the window: dropped
+        assert!(!ar.update(1000 - window_size - 1));
+        assert!(!ar.update(1000 - 2 * window_size));
+
+        // accepted by the default window
+        let mut ar = AntiReplay::new();
+        assert!(ar.update(1000));
+        assert!(ar.update(1000 - 2 * window_size));
     }
 }
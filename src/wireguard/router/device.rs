@@ -1,6 +1,8 @@
 use std::collections::HashMap;
+use std::net::IpAddr;
 use std::ops::Deref;
-use std::sync::atomic::AtomicBool;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{SyncSender, TrySendError};
 use std::sync::Arc;
 use std::thread;
 use std::time::Instant;
@@ -9,9 +11,10 @@ use log;
 use spin::{Mutex, RwLock};
 use zerocopy::LayoutVerified;
 
-use super::anti_replay::AntiReplay;
+use super::anti_replay::{AntiReplay, DEFAULT_BITMAP_BITLEN};
 
 use super::constants::PARALLEL_QUEUE_SIZE;
+use super::icmp;
 use super::messages::{TransportHeader, TYPE_TRANSPORT};
 use super::peer::{new_peer, Peer, PeerHandle};
 use super::types::{Callbacks, RouterError};
@@ -31,6 +34,22 @@ pub struct DeviceInner<E: Endpoint, C: Callbacks, T: tun::Writer, B: udp::Writer
     // outbound writer (Bind)
     pub outbound: RwLock<(bool, Option<B>)>,
 
+    // respond to unroutable packets with ICMP "destination unreachable"
+    pub icmp_unreachable: AtomicBool,
+
+    // size of the replay filter bitmap (in bits) of new sessions
+    pub replay_bitlen: AtomicUsize,
+
+    // sinks receiving a copy of every packet written to the TUN device
+    pub observers: RwLock<Vec<(usize, SyncSender<Vec<u8>>)>>,
+    pub observer_id: AtomicUsize,
+
+    // source IPs of the peer endpoints (with the number of peers sharing each IP)
+    pub endpoint_ips: Mutex<HashMap<IpAddr, usize>>,
+
+    // encrypted messages awaiting transmission (held back by a pacer or being written to the bind)
+    pub egress: AtomicUsize,
+
     // routing
     pub recv: RwLock<HashMap<u32, Arc<DecryptionState<E, C, T, B>>>>, // receiver id -> decryption state
     pub table: RoutingTable<Peer<E, C, T, B>>,
@@ -39,6 +58,25 @@ pub struct DeviceInner<E: Endpoint, C: Callbacks, T: tun::Writer, B: udp::Writer
     pub work: ParallelQueue<JobUnion<E, C, T, B>>,
 }
 
+impl<E: Endpoint, C: Callbacks, T: tun::Writer, B: udp::Writer<E>> DeviceInner<E, C, T, B> {
+    /// Pass a copy of the inbound packet to every observer:
+    /// observers which are not keeping up miss the packet
+    /// and disconnected observers are removed.
+    pub fn observe(&self, packet: &[u8]) {
+        let mut gone = vec![];
+        for (id, sink) in self.observers.read().iter() {
+            match sink.try_send(packet.to_vec()) {
+                Err(TrySendError::Full(_)) => log::trace!("observer {} is full", id),
+                Err(TrySendError::Disconnected(_)) => gone.push(*id),
+                Ok(()) => (),
+            }
+        }
+        if !gone.is_empty() {
+            self.observers.write().retain(|(id, _)| !gone.contains(id));
+        }
+    }
+}
+
 pub struct EncryptionState {
     pub keypair: Arc<KeyPair>, // keypair
     pub nonce: u64,            // next available nonce
@@ -113,6 +151,12 @@ impl<E: Endpoint, C: Callbacks, T: tun::Writer, B: udp::Writer<E>> DeviceHandle<
                 work,
                 inbound: tun,
                 outbound: RwLock::new((true, None)),
+                icmp_unreachable: AtomicBool::new(false),
+                replay_bitlen: AtomicUsize::new(DEFAULT_BITMAP_BITLEN),
+                observers: RwLock::new(vec![]),
+                observer_id: AtomicUsize::new(0),
+                endpoint_ips: Mutex::new(HashMap::new()),
+                egress: AtomicUsize::new(0),
                 recv: RwLock::new(HashMap::new()),
                 table: RoutingTable::new(),
             }),
@@ -138,6 +182,12 @@ impl<E: Endpoint, C: Callbacks, T: tun::Writer, B: udp::Writer<E>> DeviceHandle<
     }
 
     pub fn send_raw(&self, msg: &[u8], dst: &mut E) -> Result<(), B::Error> {
+        // an empty datagram is meaningless to the peer
+        if msg.is_empty() {
+            log::debug!("router: dropping empty message");
+            return Ok(());
+        }
+
         let bind = self.state.outbound.read();
         if bind.0 {
             if let Some(bind) = bind.1.as_ref() {
@@ -147,6 +197,51 @@ impl<E: Endpoint, C: Callbacks, T: tun::Writer, B: udp::Writer<E>> DeviceHandle<
         return Ok(());
     }
 
+    /// Enable (or disable) responding to packets without a cryptokey route
+    /// with an ICMP (or ICMPv6) "destination unreachable" error written to the TUN device.
+    pub fn set_icmp_unreachable(&self, enabled: bool) {
+        self.state
+            .icmp_unreachable
+            .store(enabled, Ordering::Relaxed);
+    }
+
+    /// Set the size (in bits) of the replay filter of sessions established from now on.
+    ///
+    /// Larger filters tolerate more reordering, at the cost of memory per session.
+    ///
+    /// # Returns
+    ///
+    /// False if the size is not a power of two within the supported bounds
+    pub fn set_replay_window(&self, bitlen: usize) -> bool {
+        if !AntiReplay::valid_bitlen(bitlen) {
+            return false;
+        }
+        self.state.replay_bitlen.store(bitlen, Ordering::Relaxed);
+        true
+    }
+
+    /// Register a (read-only) observer of the inbound traffic,
+    /// which receives a copy of every packet written to the TUN device.
+    ///
+    /// Observers never delay the TUN device:
+    /// packets are dropped for the observer if the channel is full.
+    /// The observer is removed once the receiver is dropped.
+    pub fn add_observer(&self, sink: SyncSender<Vec<u8>>) {
+        let id = self.state.observer_id.fetch_add(1, Ordering::Relaxed);
+        self.state.observers.write().push((id, sink));
+    }
+
+    /// Check if the IP address belongs to the endpoint of any peer
+    pub fn is_known_source(&self, ip: &IpAddr) -> bool {
+        self.state.endpoint_ips.lock().contains_key(ip)
+    }
+
+    /// Return the number of encrypted transport messages (of every peer) awaiting transmission,
+    /// i.e. held back by a pacer or blocked writing to the bind.
+    pub fn egress_queue_depth(&self) -> usize {
+        self.state.egress.load(Ordering::Relaxed)
+    }
+
     /// Brings the router down.
     /// When the router is brought down it:
     /// - Prevents transmission of outbound messages.
@@ -193,11 +288,25 @@ impl<E: Endpoint, C: Callbacks, T: tun::Writer, B: udp::Writer<E>> DeviceHandle<
         let packet = &msg[SIZE_MESSAGE_PREFIX..];
 
         // lookup peer based on IP packet destination address
-        let peer = self
-            .state
-            .table
-            .get_route(packet)
-            .ok_or(RouterError::NoCryptoKeyRoute)?;
+        let peer = match self.state.table.get_route(packet) {
+            Some(peer) => peer,
+            None => {
+                // let local applications fail fast
+                if self.state.icmp_unreachable.load(Ordering::Relaxed) {
+                    if let Some(reply) = icmp::unreachable(packet) {
+                        let _ = self.state.inbound.write(&reply[..]).map_err(|e| {
+                            log::debug!("failed to write ICMP error to TUN: {:?}", e);
+                        });
+                    }
+                }
+                return Err(RouterError::NoCryptoKeyRoute);
+            }
+        };
+
+        // drop protocols not carried by the peer
+        if !peer.allows_protocol(packet) {
+            return Err(RouterError::ProtocolNotAllowed);
+        }
 
         // schedule for encryption and transmission to peer
         peer.send(msg, true);
@@ -0,0 +1,180 @@
+use super::ip::*;
+
+use std::cmp;
+use std::mem;
+
+use zerocopy::LayoutVerified;
+
+const PROTOCOL_ICMP: u8 = 1;
+const PROTOCOL_ICMP6: u8 = 58;
+
+const ICMP_DEST_UNREACH: u8 = 3;
+const ICMP_HOST_UNREACH: u8 = 1;
+
+const ICMP6_DEST_UNREACH: u8 = 1;
+const ICMP6_NO_ROUTE: u8 = 0;
+
+const SIZE_IP4_HEADER: usize = 20;
+const SIZE_IP6_HEADER: usize = mem::size_of::<IPv6Header>();
+const SIZE_ICMP_HEADER: usize = 8;
+
+// maximum size of an ICMP error message (RFC 1812 / RFC 4443)
+const MAX_ICMP4_SIZE: usize = 576;
+const MAX_ICMP6_SIZE: usize = 1280;
+
+const TTL: u8 = 64;
+
+/* Compute the internet checksum (RFC 1071) over the concatenation of the chunks.
+ *
+ * Every chunk, except for the last, must be of even length.
+ */
+fn checksum(chunks: &[&[u8]]) -> u16 {
+    let mut sum: u32 = 0;
+    for chunk in chunks {
+        for word in chunk.chunks(2) {
+            let hi = word[0] as u32;
+            let lo = *word.get(1).unwrap_or(&0) as u32;
+            sum += (hi << 8) | lo;
+        }
+    }
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/* Check if an ICMP error may be sent in response to the packet with the source address:
+ * never in response to unspecified, broadcast or multicast sources.
+ */
+fn valid_source4(src: &[u8; 4]) -> bool {
+    *src != [0; 4] && *src != [0xff; 4] && src[0] & 0xf0 != 0xe0
+}
+
+fn valid_source6(src: &[u8; 16]) -> bool {
+    *src != [0; 16] && src[0] != 0xff
+}
+
+fn unreachable4(packet: &[u8]) -> Option<Vec<u8>> {
+    let (header, _): (LayoutVerified<&[u8], IPv4Header>, _) =
+        LayoutVerified::new_from_prefix(packet)?;
+
+    let ihl = (packet[0] & 0xf) as usize * 4;
+    let fragment = ((packet[6] & 0x1f) as u16) << 8 | packet[7] as u16;
+    let protocol = packet[9];
+
+    // only respond to the first fragment of well-formed packets
+    if ihl < SIZE_IP4_HEADER || packet.len() < ihl || fragment != 0 {
+        return None;
+    }
+
+    // never respond to an ICMP error message
+    if protocol == PROTOCOL_ICMP {
+        match packet.get(ihl) {
+            Some(3) | Some(4) | Some(5) | Some(11) | Some(12) | None => return None,
+            _ => (),
+        }
+    }
+
+    if !valid_source4(&header.f_source) {
+        return None;
+    }
+
+    // quote as much of the original packet as fits
+    let quoted = cmp::min(
+        cmp::min(packet.len(), header.f_total_len.get() as usize),
+        MAX_ICMP4_SIZE - SIZE_IP4_HEADER - SIZE_ICMP_HEADER,
+    );
+    let length = SIZE_IP4_HEADER + SIZE_ICMP_HEADER + quoted;
+
+    let mut reply = vec![0u8; length];
+
+    // IPv4 header (the reply originates from the unreachable destination)
+    reply[0] = 0x45;
+    reply[2..4].copy_from_slice(&(length as u16).to_be_bytes());
+    reply[8] = TTL;
+    reply[9] = PROTOCOL_ICMP;
+    reply[12..16].copy_from_slice(&header.f_destination);
+    reply[16..20].copy_from_slice(&header.f_source);
+    let sum = checksum(&[&reply[..SIZE_IP4_HEADER]]);
+    reply[10..12].copy_from_slice(&sum.to_be_bytes());
+
+    // ICMP message
+    let icmp = &mut reply[SIZE_IP4_HEADER..];
+    icmp[0] = ICMP_DEST_UNREACH;
+    icmp[1] = ICMP_HOST_UNREACH;
+    icmp[SIZE_ICMP_HEADER..].copy_from_slice(&packet[..quoted]);
+    let sum = checksum(&[&icmp[..]]);
+    icmp[2..4].copy_from_slice(&sum.to_be_bytes());
+
+    Some(reply)
+}
+
+fn unreachable6(packet: &[u8]) -> Option<Vec<u8>> {
+    let (header, _): (LayoutVerified<&[u8], IPv6Header>, _) =
+        LayoutVerified::new_from_prefix(packet)?;
+
+    // never respond to an ICMPv6 error message (types below 128)
+    let next_header = packet[6];
+    if next_header == PROTOCOL_ICMP6 {
+        match packet.get(SIZE_IP6_HEADER) {
+            Some(t) if *t >= 128 => (),
+            _ => return None,
+        }
+    }
+
+    if !valid_source6(&header.f_source) {
+        return None;
+    }
+
+    // quote as much of the original packet as fits
+    let quoted = cmp::min(
+        cmp::min(packet.len(), header.f_len.get() as usize + SIZE_IP6_HEADER),
+        MAX_ICMP6_SIZE - SIZE_IP6_HEADER - SIZE_ICMP_HEADER,
+    );
+    let payload = SIZE_ICMP_HEADER + quoted;
+
+    let mut reply = vec![0u8; SIZE_IP6_HEADER + payload];
+
+    // IPv6 header (the reply originates from the unreachable destination)
+    reply[0] = 0x60;
+    reply[4..6].copy_from_slice(&(payload as u16).to_be_bytes());
+    reply[6] = PROTOCOL_ICMP6;
+    reply[7] = TTL;
+    reply[8..24].copy_from_slice(&header.f_destination);
+    reply[24..40].copy_from_slice(&header.f_source);
+
+    // ICMPv6 message (checksum covers the pseudo-header)
+    let (ip, icmp) = reply.split_at_mut(SIZE_IP6_HEADER);
+    icmp[0] = ICMP6_DEST_UNREACH;
+    icmp[1] = ICMP6_NO_ROUTE;
+    icmp[SIZE_ICMP_HEADER..].copy_from_slice(&packet[..quoted]);
+    let sum = checksum(&[
+        &ip[8..40],
+        &(payload as u32).to_be_bytes(),
+        &[0, 0, 0, PROTOCOL_ICMP6],
+        &icmp[..],
+    ]);
+    icmp[2..4].copy_from_slice(&sum.to_be_bytes());
+
+    Some(reply)
+}
+
+/* Synthesize an ICMP (or ICMPv6) "destination unreachable" error for an IP packet,
+ * to be written back into the tunnel when the packet cannot be routed.
+ *
+ * # Arguments
+ *
+ * - `packet`: The unroutable IP packet
+ *
+ * # Returns
+ *
+ * The ICMP error, or None if no error should be sent in response to the packet
+ * (e.g. if it is malformed or itself an ICMP error).
+ */
+pub fn unreachable(packet: &[u8]) -> Option<Vec<u8>> {
+    match packet.get(0)? >> 4 {
+        VERSION_IP4 => unreachable4(packet),
+        VERSION_IP6 => unreachable6(packet),
+        _ => None,
+    }
+}
@@ -28,6 +28,19 @@ pub struct IPv6Header {
     pub f_destination: [u8; 16],
 }
 
+/* Returns the protocol of the IP packet,
+ * for IPv6 the next header field of the fixed header
+ * (extension headers are not traversed).
+ */
+#[inline(always)]
+pub fn inner_protocol(packet: &[u8]) -> Option<u8> {
+    match packet.get(0)? >> 4 {
+        VERSION_IP4 if packet.len() >= mem::size_of::<IPv4Header>() => Some(packet[9]),
+        VERSION_IP6 if packet.len() >= mem::size_of::<IPv6Header>() => Some(packet[6]),
+        _ => None,
+    }
+}
+
 #[inline(always)]
 pub fn inner_length(packet: &[u8]) -> Option<usize> {
     match packet.get(0)? >> 4 {
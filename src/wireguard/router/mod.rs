@@ -1,8 +1,10 @@
 mod anti_replay;
 mod constants;
 mod device;
+mod icmp;
 mod ip;
 mod messages;
+mod pacer;
 mod peer;
 mod route;
 mod types;
@@ -32,5 +34,5 @@ pub const fn message_data_len(payload: usize) -> usize {
 
 pub use device::DeviceHandle as Device;
 pub use messages::TYPE_TRANSPORT;
-pub use peer::PeerHandle;
+pub use peer::{ExportedKeyPair, ExportedSession, PeerHandle};
 pub use types::Callbacks;
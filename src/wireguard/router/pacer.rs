@@ -0,0 +1,68 @@
+use std::time::{Duration, Instant};
+
+/* Releases messages at a steady rate (smoothing bursts):
+ * consecutive messages are spaced by at least the interval,
+ * while idle periods do not accumulate credit for later bursts.
+ */
+pub struct Pacer {
+    interval: Duration,
+    next: Option<Instant>,
+}
+
+impl Pacer {
+    /// Create a pacer releasing (at most) rate messages per second
+    pub fn new(rate: u32) -> Pacer {
+        debug_assert!(rate > 0);
+        Pacer {
+            interval: Duration::from_secs(1) / rate,
+            next: None,
+        }
+    }
+
+    /// Reserve the next slot for transmission
+    ///
+    /// # Arguments
+    ///
+    /// - `now`: The current time
+    ///
+    /// # Returns
+    ///
+    /// The time at which the message may be sent (no earlier than now)
+    pub fn release(&mut self, now: Instant) -> Instant {
+        let at = match self.next {
+            Some(next) if next > now => next,
+            _ => now,
+        };
+        self.next = Some(at + self.interval);
+        at
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pacer_burst() {
+        // 2 messages per millisecond
+        let mut pacer = Pacer::new(2000);
+        let interval = Duration::from_micros(500);
+
+        // a burst is spread out at the configured rate
+        let start = Instant::now();
+        let times: Vec<Instant> = (0..10).map(|_| pacer.release(start)).collect();
+        assert_eq!(times[0], start);
+        for pair in times.windows(2) {
+            assert_eq!(pair[1] - pair[0], interval);
+        }
+
+        // messages arriving slower than the rate are not delayed
+        let later = start + Duration::from_secs(1);
+        assert_eq!(pacer.release(later), later);
+        assert_eq!(pacer.release(later + 2 * interval), later + 2 * interval);
+
+        // but the idle period does not permit a faster burst
+        let now = later + 2 * interval;
+        assert_eq!(pacer.release(now), now + interval);
+    }
+}
@@ -5,6 +5,8 @@ use super::anti_replay::AntiReplay;
 use super::device::DecryptionState;
 use super::device::Device;
 use super::device::EncryptionState;
+use super::ip::inner_protocol;
+use super::pacer::Pacer;
 
 use super::constants::*;
 use super::types::{Callbacks, RouterError};
@@ -15,15 +17,18 @@ use super::receive::ReceiveJob;
 use super::send::SendJob;
 use super::worker::JobUnion;
 
+use std::collections::VecDeque;
 use std::mem;
 use std::net::{IpAddr, SocketAddr};
 use std::ops::Deref;
-use std::sync::atomic::AtomicBool;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
 
 use arraydeque::{ArrayDeque, Wrapping};
+use hjul::{Runner, Timer};
 use log;
-use spin::Mutex;
+use spin::{Mutex, RwLock};
 
 pub struct KeyWheel {
     next: Option<Arc<KeyPair>>,     // next key state (unconfirmed)
@@ -32,6 +37,29 @@ pub struct KeyWheel {
     retired: Vec<u32>,              // retired ids
 }
 
+// A key-pair exported for hand-off to another instance
+pub struct ExportedKeyPair {
+    pub keypair: KeyPair,
+    pub nonce: u64, // next nonce for encryption (only used by the current key-pair)
+    pub counter: Option<u64>, // greatest counter received (none if nothing was received)
+}
+
+// The key-wheel of a peer exported for hand-off to another instance
+#[derive(Default)]
+pub struct ExportedSession {
+    pub previous: Option<ExportedKeyPair>,
+    pub current: Option<ExportedKeyPair>,
+    pub next: Option<ExportedKeyPair>, // unconfirmed
+}
+
+// A transport message held back by the pacer (until its slot is reached)
+pub struct PacedMessage {
+    at: Instant,
+    msg: Vec<u8>,
+    keypair: Arc<KeyPair>,
+    counter: u64,
+}
+
 pub struct PeerInner<E: Endpoint, C: Callbacks, T: tun::Writer, B: udp::Writer<E>> {
     pub device: Device<E, C, T, B>,
     pub opaque: C::Opaque,
@@ -41,6 +69,13 @@ pub struct PeerInner<E: Endpoint, C: Callbacks, T: tun::Writer, B: udp::Writer<E
     pub keys: Mutex<KeyWheel>,
     pub enc_key: Mutex<Option<EncryptionState>>,
     pub endpoint: Mutex<Option<E>>,
+    pub allowed_protocols: spin::RwLock<Option<Vec<u8>>>, // None allows every protocol
+    pub outbound_drops: AtomicU64, // transport messages dropped due to a full outbound queue
+    pub replays: AtomicU64,        // transport messages rejected by the replay protector
+    pub pacer: Mutex<Option<Pacer>>, // paces the transmission of transport messages (if enabled)
+    pub paced: Mutex<VecDeque<PacedMessage>>, // messages held back by the pacer (in order)
+    pub pacing_timer: RwLock<Option<Timer>>, // releases the held back messages
+    pub releasing: Mutex<()>,      // held by the thread releasing paced messages
 }
 
 pub struct Peer<E: Endpoint, C: Callbacks, T: tun::Writer, B: udp::Writer<E>> {
@@ -105,10 +140,11 @@ impl EncryptionState {
 
 impl<E: Endpoint, C: Callbacks, T: tun::Writer, B: udp::Writer<E>> DecryptionState<E, C, T, B> {
     fn new(peer: Peer<E, C, T, B>, keypair: &Arc<KeyPair>) -> DecryptionState<E, C, T, B> {
+        let bitlen = peer.device.replay_bitlen.load(Ordering::Relaxed);
         DecryptionState {
             confirmed: AtomicBool::new(keypair.initiator),
             keypair: keypair.clone(),
-            protector: spin::Mutex::new(AntiReplay::new()),
+            protector: spin::Mutex::new(AntiReplay::with_bitlen(bitlen)),
             death: keypair.birth + REJECT_AFTER_TIME,
             peer,
         }
@@ -146,7 +182,16 @@ impl<E: Endpoint, C: Callbacks, T: tun::Writer, B: udp::Writer<E>> Drop for Peer
         keys.previous = None;
 
         *peer.enc_key.lock() = None;
-        *peer.endpoint.lock() = None;
+        peer.update_endpoint(None);
+
+        // discard messages held back by the pacer
+
+        if let Some(timer) = peer.pacing_timer.read().as_ref() {
+            timer.stop();
+        }
+        let mut paced = peer.paced.lock();
+        peer.device.egress.fetch_sub(paced.len(), Ordering::Relaxed);
+        paced.clear();
 
         log::debug!("peer dropped & removed from device");
     }
@@ -174,6 +219,13 @@ pub fn new_peer<E: Endpoint, C: Callbacks, T: tun::Writer, B: udp::Writer<E>>(
                     retired: vec![],
                 }),
                 staged_packets: spin::Mutex::new(ArrayDeque::new()),
+                allowed_protocols: spin::RwLock::new(None),
+                outbound_drops: AtomicU64::new(0),
+                replays: AtomicU64::new(0),
+                pacer: spin::Mutex::new(None),
+                paced: spin::Mutex::new(VecDeque::new()),
+                pacing_timer: spin::RwLock::new(None),
+                releasing: spin::Mutex::new(()),
             }),
         }
     };
@@ -182,6 +234,41 @@ pub fn new_peer<E: Endpoint, C: Callbacks, T: tun::Writer, B: udp::Writer<E>>(
 }
 
 impl<E: Endpoint, C: Callbacks, T: tun::Writer, B: udp::Writer<E>> PeerInner<E, C, T, B> {
+    /// Check if the protocol of the (inner) IP packet may be carried by the peer
+    pub fn allows_protocol(&self, packet: &[u8]) -> bool {
+        match self.allowed_protocols.read().as_ref() {
+            None => true,
+            Some(protocols) => inner_protocol(packet)
+                .map(|protocol| protocols.contains(&protocol))
+                .unwrap_or(false),
+        }
+    }
+
+    /// Replace the endpoint of the peer,
+    /// keeping the set of endpoint IPs of the device up to date.
+    pub fn update_endpoint(&self, endpoint: Option<E>) {
+        let mut current = self.endpoint.lock();
+        let old = current.as_ref().map(|e| e.into_address().ip());
+        let new = endpoint.as_ref().map(|e| e.into_address().ip());
+        *current = endpoint;
+        if old == new {
+            return;
+        }
+
+        let mut ips = self.device.endpoint_ips.lock();
+        if let Some(ip) = old {
+            if let Some(count) = ips.get_mut(&ip) {
+                *count -= 1;
+                if *count == 0 {
+                    ips.remove(&ip);
+                }
+            }
+        }
+        if let Some(ip) = new {
+            *ips.entry(ip).or_insert(0) += 1;
+        }
+    }
+
     /// Send a raw message to the peer (used for handshake messages)
     ///
     /// # Arguments
@@ -192,6 +279,12 @@ impl<E: Endpoint, C: Callbacks, T: tun::Writer, B: udp::Writer<E>> PeerInner<E,
     ///
     /// Unit if packet was sent, or an error indicating why sending failed
     pub fn send_raw(&self, msg: &[u8]) -> Result<(), RouterError> {
+        // an empty datagram is meaningless to the peer
+        if msg.is_empty() {
+            log::debug!("peer.send_raw, dropping empty message");
+            return Ok(());
+        }
+
         // send to endpoint (if known)
         match self.endpoint.lock().as_mut() {
             Some(endpoint) => {
@@ -209,6 +302,95 @@ impl<E: Endpoint, C: Callbacks, T: tun::Writer, B: udp::Writer<E>> PeerInner<E,
             None => Err(RouterError::NoEndpoint),
         }
     }
+
+    // Send an encrypted transport message and notify the callbacks (for timers)
+    fn transmit(&self, msg: &[u8], keypair: &Arc<KeyPair>, counter: u64) {
+        let xmit = self.send_raw(msg).is_ok();
+        self.device.egress.fetch_sub(1, Ordering::Relaxed);
+        C::send(&self.opaque, msg.len(), xmit, keypair, counter);
+    }
+
+    /// Send an encrypted transport message to the peer,
+    /// holding it back until the next slot of the pacer (if enabled).
+    ///
+    /// Held back messages are released by the pacing timer (or the next caller),
+    /// the caller is never blocked waiting for a slot.
+    pub fn transmit_paced(&self, msg: Vec<u8>, keypair: Arc<KeyPair>, counter: u64) {
+        self.device.egress.fetch_add(1, Ordering::Relaxed);
+        let now = Instant::now();
+        let slot = self.pacer.lock().as_mut().map(|pacer| pacer.release(now));
+        {
+            let mut paced = self.paced.lock();
+
+            // send immediately, unless messages are held back (preserving the order)
+            if slot.is_none() && paced.is_empty() {
+                drop(paced);
+                self.transmit(&msg[..], &keypair, counter);
+                return;
+            }
+
+            // bound the number of held back messages
+            if paced.len() >= MAX_QUEUED_PACKETS {
+                drop(paced);
+                log::debug!("pacing queue full, dropping message");
+                self.device.egress.fetch_sub(1, Ordering::Relaxed);
+                self.outbound_drops.fetch_add(1, Ordering::Relaxed);
+                C::send(&self.opaque, msg.len(), false, &keypair, counter);
+                return;
+            }
+
+            let at = slot.unwrap_or(now);
+            paced.push_back(PacedMessage {
+                at,
+                msg,
+                keypair,
+                counter,
+            });
+
+            // ensure the message is released,
+            // even if another thread is currently releasing messages
+            if paced.len() == 1 {
+                self.arm_pacing(at - now);
+            }
+        }
+        self.release_paced();
+    }
+
+    // Send the held back messages whose slot has been reached
+    // and arm the pacing timer for the next held back message.
+    //
+    // Messages are released by a single thread at a time (preserving their order),
+    // other threads return rather than wait.
+    fn release_paced(&self) {
+        let _releasing = match self.releasing.try_lock() {
+            Some(guard) => guard,
+            None => return,
+        };
+        loop {
+            let now = Instant::now();
+            let next = {
+                let mut paced = self.paced.lock();
+                match paced.front() {
+                    Some(msg) if msg.at <= now => paced.pop_front(),
+                    Some(msg) => {
+                        self.arm_pacing(msg.at - now);
+                        None
+                    }
+                    None => None,
+                }
+            };
+            match next {
+                Some(msg) => self.transmit(&msg.msg[..], &msg.keypair, msg.counter),
+                None => break,
+            }
+        }
+    }
+
+    fn arm_pacing(&self, duration: Duration) {
+        if let Some(timer) = self.pacing_timer.read().as_ref() {
+            timer.reset(duration);
+        }
+    }
 }
 
 impl<E: Endpoint, C: Callbacks, T: tun::Writer, B: udp::Writer<E>> Peer<E, C, T, B> {
@@ -232,8 +414,8 @@ impl<E: Endpoint, C: Callbacks, T: tun::Writer, B: udp::Writer<E>> Peer<E, C, T,
                     (None, true)
                 }
                 Some(mut state) => {
-                    // avoid integer overflow in nonce
-                    if state.nonce >= REJECT_AFTER_MESSAGES - 1 {
+                    // avoid integer overflow in nonce (and the use of expired key-pairs)
+                    if state.nonce >= REJECT_AFTER_MESSAGES - 1 || state.keypair.is_expired() {
                         log::debug!("encryption key expired");
                         *enc_key = None;
                         if stage {
@@ -248,6 +430,8 @@ impl<E: Endpoint, C: Callbacks, T: tun::Writer, B: udp::Writer<E>> Peer<E, C, T,
                             state.nonce += 1;
                             (Some(job), false)
                         } else {
+                            log::debug!("outbound queue full, dropping message");
+                            self.outbound_drops.fetch_add(1, Ordering::Relaxed);
                             (None, false)
                         }
                     }
@@ -258,6 +442,7 @@ impl<E: Endpoint, C: Callbacks, T: tun::Writer, B: udp::Writer<E>> Peer<E, C, T,
         if need_key {
             log::debug!("request new key");
             debug_assert!(job.is_none());
+            self.expire_keys(Instant::now());
             C::need_key(&self.opaque);
         };
 
@@ -267,6 +452,50 @@ impl<E: Endpoint, C: Callbacks, T: tun::Writer, B: udp::Writer<E>> Peer<E, C, T,
         }
     }
 
+    // Remove the key-pairs expired at the given instant from the key-wheel,
+    // such that their ids no longer map to the peer.
+    // (the ids are released once the next key-pair is added, as for zero_keys)
+    pub(super) fn expire_keys(&self, now: Instant) {
+        let mut keys = self.keys.lock();
+
+        // update key-wheel
+        let mut expired: Vec<u32> = Vec::with_capacity(3);
+        let mut expire = |slot: &mut Option<Arc<KeyPair>>| {
+            if slot.as_ref().map(|k| k.is_expired_at(now)).unwrap_or(false) {
+                slot.take().map(|k| expired.push(k.local_id()));
+            }
+        };
+        expire(&mut keys.next);
+        expire(&mut keys.current);
+        expire(&mut keys.previous);
+        if expired.is_empty() {
+            return;
+        }
+        log::debug!(
+            "peer.expire_keys: removing {} expired key-pairs",
+            expired.len()
+        );
+        keys.retired.extend(&expired[..]);
+
+        // update inbound "recv" map
+        {
+            let mut recv = self.device.recv.write();
+            for id in expired {
+                recv.remove(&id);
+            }
+        }
+
+        // clear expired encryption state
+        let mut enc_key = self.enc_key.lock();
+        if enc_key
+            .as_ref()
+            .map(|state| state.keypair.is_expired_at(now))
+            .unwrap_or(false)
+        {
+            *enc_key = None;
+        }
+    }
+
     // Transmit all staged packets
     fn send_staged(&self) -> bool {
         log::trace!("peer.send_staged");
@@ -306,6 +535,10 @@ impl<E: Endpoint, C: Callbacks, T: tun::Writer, B: udp::Writer<E>> Peer<E, C, T,
             mem::swap(&mut keys.next, &mut swap);
             mem::swap(&mut keys.current, &mut swap);
             mem::swap(&mut keys.previous, &mut swap);
+            debug_assert!(
+                swap.is_none(),
+                "previous is cleared when an unconfirmed key-pair is added"
+            );
 
             // tell the world outside the router that a key was confirmed
             C::key_confirmed(&self.opaque);
@@ -320,6 +553,40 @@ impl<E: Endpoint, C: Callbacks, T: tun::Writer, B: udp::Writer<E>> Peer<E, C, T,
 }
 
 impl<E: Endpoint, C: Callbacks, T: tun::Writer, B: udp::Writer<E>> PeerHandle<E, C, T, B> {
+    /// Pace the transmission of transport messages to the peer (smoothing bursts)
+    ///
+    /// Messages are held back (without blocking the workers) until their slot is reached,
+    /// then released by a timer: the precision is limited by the tick of the runner.
+    /// Messages already held back when pacing is disabled are released at their slot.
+    ///
+    /// # Arguments
+    ///
+    /// - `rate`: The maximum number of messages per second, None (or zero) disables pacing
+    /// - `runner`: The timer runner used to release held back messages
+    pub fn set_pacing(&self, rate: Option<u32>, runner: &Runner) {
+        {
+            let mut timer = self.peer.pacing_timer.write();
+            if timer.is_none() {
+                let peer: Weak<PeerInner<E, C, T, B>> = Arc::downgrade(&self.peer.inner);
+                *timer = Some(runner.timer(move || {
+                    if let Some(peer) = peer.upgrade() {
+                        peer.release_paced();
+                    }
+                }));
+            }
+        }
+        *self.peer.pacer.lock() = rate.filter(|rate| *rate > 0).map(Pacer::new);
+    }
+
+    /// Restrict the IP protocols carried by the peer (in both directions)
+    ///
+    /// # Arguments
+    ///
+    /// - `protocols`: The allowed IP protocol numbers (e.g. 6 for TCP), None allows every protocol
+    pub fn set_allowed_protocols(&self, protocols: Option<Vec<u8>>) {
+        *self.peer.allowed_protocols.write() = protocols;
+    }
+
     /// Set the endpoint of the peer
     ///
     /// # Arguments
@@ -332,7 +599,7 @@ impl<E: Endpoint, C: Callbacks, T: tun::Writer, B: udp::Writer<E>> PeerHandle<E,
     /// as sockets should be "unsticked" when manually updating the endpoint
     pub fn set_endpoint(&self, endpoint: E) {
         log::trace!("peer.set_endpoint");
-        *self.peer.endpoint.lock() = Some(endpoint);
+        self.peer.update_endpoint(Some(endpoint));
     }
 
     /// Returns the current endpoint of the peer (for configuration)
@@ -345,6 +612,111 @@ impl<E: Endpoint, C: Callbacks, T: tun::Writer, B: udp::Writer<E>> PeerHandle<E,
         self.peer.endpoint.lock().as_ref().map(|e| e.into_address())
     }
 
+    /// Check if the peer has a session which can be used for transmission
+    ///
+    /// # Returns
+    ///
+    /// A bool indicating if a confirmed key-pair, which has not yet expired
+    /// (neither by age nor by exhausting the nonce space), is available for encryption.
+    pub fn has_session(&self) -> bool {
+        self.peer
+            .enc_key
+            .lock()
+            .as_ref()
+            .map(|state| state.death > Instant::now() && state.nonce < REJECT_AFTER_MESSAGES - 1)
+            .unwrap_or(false)
+    }
+
+    /// Return the remaining lifetime of the current session (until REJECT_AFTER_TIME)
+    ///
+    /// # Arguments
+    ///
+    /// - `now`: The current time
+    ///
+    /// # Returns
+    ///
+    /// None if no (non-expired) key-pair is available for encryption.
+    pub fn session_remaining(&self, now: Instant) -> Option<Duration> {
+        self.peer.enc_key.lock().as_ref().and_then(|state| {
+            if state.death > now {
+                Some(state.death - now)
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Return the age of the current session
+    ///
+    /// # Arguments
+    ///
+    /// - `now`: The current time
+    ///
+    /// # Returns
+    ///
+    /// None if no (non-expired) key-pair is available for encryption.
+    pub fn session_age(&self, now: Instant) -> Option<Duration> {
+        self.peer.enc_key.lock().as_ref().and_then(|state| {
+            if state.death > now && now >= state.keypair.birth {
+                Some(now - state.keypair.birth)
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Check which side initiated the handshake of the current session
+    ///
+    /// # Returns
+    ///
+    /// None if no key-pair is available for encryption,
+    /// otherwise a bool indicating if the handshake was initiated locally.
+    pub fn session_initiator(&self) -> Option<bool> {
+        self.peer
+            .enc_key
+            .lock()
+            .as_ref()
+            .map(|state| state.keypair.initiator)
+    }
+
+    /// Return the number of outbound transport messages queued for the peer
+    /// (awaiting encryption or transmission).
+    pub fn outbound_queue_depth(&self) -> usize {
+        self.peer.outbound.len()
+    }
+
+    /// Return the number of outbound transport messages dropped
+    /// because the outbound queue of the peer was full.
+    pub fn outbound_drops(&self) -> u64 {
+        self.peer.outbound_drops.load(Ordering::Relaxed)
+    }
+
+    /// Return the number of inbound transport messages rejected
+    /// as replayed (or too old for the replay window).
+    pub fn replays(&self) -> u64 {
+        self.peer.replays.load(Ordering::Relaxed)
+    }
+
+    /// Return the slot of the key-wheel holding the key-pair with the (receiver) id
+    ///
+    /// # Returns
+    ///
+    /// The name of the slot, or None if the id does not belong to any key-pair of the peer
+    #[cfg(feature = "debug_uapi")]
+    pub fn key_slot(&self, id: u32) -> Option<&'static str> {
+        let keys = self.peer.keys.lock();
+        let holds = |slot: &Option<Arc<KeyPair>>| slot.as_ref().map(|kp| kp.local_id()) == Some(id);
+        if holds(&keys.next) {
+            Some("next")
+        } else if holds(&keys.current) {
+            Some("current")
+        } else if holds(&keys.previous) {
+            Some("previous")
+        } else {
+            None
+        }
+    }
+
     /// Zero all key-material related to the peer
     pub fn zero_keys(&self) {
         log::trace!("peer.zero_keys");
@@ -371,6 +743,15 @@ impl<E: Endpoint, C: Callbacks, T: tun::Writer, B: udp::Writer<E>> PeerHandle<E,
         *self.peer.enc_key.lock() = None;
     }
 
+    /// Remove the key-pairs which have expired (are older than REJECT_AFTER_TIME)
+    ///
+    /// # Arguments
+    ///
+    /// - `now`: The current time
+    pub fn expire_keys(&self, now: Instant) {
+        self.peer.expire_keys(now)
+    }
+
     pub fn down(&self) {
         self.zero_keys();
     }
@@ -402,18 +783,28 @@ impl<E: Endpoint, C: Callbacks, T: tun::Writer, B: udp::Writer<E>> PeerHandle<E,
             let mut keys = self.peer.keys.lock();
             let mut release = mem::replace(&mut keys.retired, vec![]);
 
-            // update key-wheel
+            // update key-wheel (collecting the key-pairs which leave the wheel)
+            let mut dropped = Vec::with_capacity(2);
             if new.initiator {
                 // start using key for encryption
                 *self.peer.enc_key.lock() = Some(EncryptionState::new(&new));
 
-                // move current into previous
-                keys.previous = keys.current.as_ref().map(|v| v.clone());
+                // move current into previous,
+                // unless an unconfirmed key-pair is in use by the other end (as its current)
+                let previous = match keys.next.take() {
+                    Some(next) => {
+                        dropped.extend(keys.current.take());
+                        Some(next)
+                    }
+                    None => keys.current.take(),
+                };
+                dropped.extend(mem::replace(&mut keys.previous, previous));
                 keys.current = Some(new.clone());
             } else {
                 // store the key and await confirmation
-                keys.previous = keys.next.as_ref().map(|v| v.clone());
-                keys.next = Some(new.clone());
+                // (previous is cleared, such that no key-pair is lost when confirmed)
+                dropped.extend(keys.next.replace(new.clone()));
+                dropped.extend(keys.previous.take());
             };
 
             // update incoming packet id map
@@ -421,11 +812,11 @@ impl<E: Endpoint, C: Callbacks, T: tun::Writer, B: udp::Writer<E>> PeerHandle<E,
                 log::trace!("peer.add_keypair: updating inbound id map");
                 let mut recv = self.peer.device.recv.write();
 
-                // purge recv map of previous id
-                keys.previous.as_ref().map(|k| {
+                // purge recv map of the ids no longer in the key-wheel
+                for k in dropped {
                     recv.remove(&k.local_id());
                     release.push(k.local_id());
-                });
+                }
 
                 // map new id to decryption state
                 debug_assert!(!recv.contains_key(&new.recv.id));
@@ -457,6 +848,137 @@ impl<E: Endpoint, C: Callbacks, T: tun::Writer, B: udp::Writer<E>> PeerHandle<E,
         release
     }
 
+    /// Export the key-wheel of the peer, for hand-off to another instance
+    ///
+    /// Every key-pair is removed from the key-wheel and the encryption state is taken,
+    /// hence no further messages are encrypted by this instance using the key-pairs;
+    /// ensuring that the exported nonce is never reused.
+    /// Messages can still be decrypted until the session is exported.
+    ///
+    /// # Returns
+    ///
+    /// The previous, current and next key-pair
+    /// (with the next nonce and the greatest counter received),
+    /// or None if the peer has no key-pairs.
+    pub fn export_session(&self) -> Option<ExportedSession> {
+        let mut keys = self.peer.keys.lock();
+        let mut enc_key = self.peer.enc_key.lock();
+        let mut recv = self.peer.device.recv.write();
+
+        // the current key-pair is exported along with its nonce
+        let state = enc_key.take();
+        let nonce = state.as_ref().map(|state| state.nonce).unwrap_or(0);
+        let previous = keys.previous.take();
+        let current = keys.current.take().or(state.map(|state| state.keypair));
+        let next = keys.next.take();
+
+        let mut export = |keypair: Option<Arc<KeyPair>>, nonce: u64| {
+            keypair.map(|keypair| {
+                let counter = recv
+                    .remove(&keypair.local_id())
+                    .and_then(|dec| dec.protector.lock().greatest());
+                keys.retired.push(keypair.local_id());
+                ExportedKeyPair {
+                    keypair: (*keypair).clone(),
+                    nonce,
+                    counter,
+                }
+            })
+        };
+
+        let exported = ExportedSession {
+            previous: export(previous, 0),
+            current: export(current, nonce),
+            next: export(next, 0),
+        };
+
+        if exported.previous.is_none() && exported.current.is_none() && exported.next.is_none() {
+            None
+        } else {
+            Some(exported)
+        }
+    }
+
+    /// Import a session exported by another instance (see export_session).
+    ///
+    /// # Arguments
+    ///
+    /// - `session`: The key-pairs of the session,
+    ///    every counter prior to the greatest counter received is rejected
+    ///    (every counter is accepted by a key-pair which has received nothing).
+    ///
+    /// # Returns
+    ///
+    /// True if the session was imported.
+    /// Sessions are refused if the peer already has key-pairs (which could cause nonce reuse),
+    /// every key-pair has expired, or the nonce space is exhausted.
+    /// Expired key-pairs of an imported session are skipped.
+    pub fn import_session(&self, session: ExportedSession) -> bool {
+        let unexpired = |exported: Option<ExportedKeyPair>| {
+            exported.filter(|exported| !exported.keypair.is_expired())
+        };
+        let previous = unexpired(session.previous);
+        let current = unexpired(session.current);
+        let next = unexpired(session.next);
+
+        if previous.is_none() && current.is_none() && next.is_none() {
+            return false;
+        }
+        if let Some(current) = current.as_ref() {
+            if current.nonce >= REJECT_AFTER_MESSAGES - 1 {
+                return false;
+            }
+        }
+
+        let mut keys = self.peer.keys.lock();
+        let mut enc_key = self.peer.enc_key.lock();
+        if enc_key.is_some()
+            || keys.previous.is_some()
+            || keys.current.is_some()
+            || keys.next.is_some()
+        {
+            return false;
+        }
+
+        let mut recv = self.peer.device.recv.write();
+        if [&previous, &current, &next]
+            .iter()
+            .filter_map(|exported| exported.as_ref())
+            .any(|exported| recv.contains_key(&exported.keypair.recv.id))
+        {
+            return false;
+        }
+
+        // the previous and current key-pairs were confirmed by the exporting instance,
+        // while the next key-pair awaits confirmation (as on the exporting instance)
+        let bitlen = self.peer.device.replay_bitlen.load(Ordering::Relaxed);
+        let mut insert = |exported: Option<ExportedKeyPair>, confirmed: bool| {
+            exported.map(|exported| {
+                let keypair = Arc::new(exported.keypair);
+                let dec = DecryptionState::new(self.peer.clone(), &keypair);
+                if confirmed {
+                    dec.confirmed.store(true, Ordering::SeqCst);
+                }
+                *dec.protector.lock() = match exported.counter {
+                    Some(counter) => AntiReplay::resume(counter, bitlen),
+                    None => AntiReplay::with_bitlen(bitlen),
+                };
+                recv.insert(keypair.recv.id, Arc::new(dec));
+                (keypair, exported.nonce)
+            })
+        };
+
+        keys.previous = insert(previous, true).map(|(keypair, _)| keypair);
+        keys.next = insert(next, false).map(|(keypair, _)| keypair);
+        if let Some((keypair, nonce)) = insert(current, true) {
+            let mut state = EncryptionState::new(&keypair);
+            state.nonce = nonce;
+            *enc_key = Some(state);
+            keys.current = Some(keypair);
+        }
+        true
+    }
+
     pub fn send_keepalive(&self) {
         log::trace!("peer.send_keepalive");
         self.peer.send(vec![0u8; SIZE_MESSAGE_PREFIX], false)
@@ -499,6 +1021,16 @@ impl<E: Endpoint, C: Callbacks, T: tun::Writer, B: udp::Writer<E>> PeerHandle<E,
         self.peer.device.table.remove(&self.peer)
     }
 
+    /// Replace the subnets mapped to the peer,
+    /// the update is atomic with respect to routing.
+    ///
+    /// # Arguments
+    ///
+    /// - ips: The new subnets, represented by as mask/size
+    pub fn set_allowed_ips(&self, ips: &[(IpAddr, u32)]) {
+        self.peer.device.table.replace(&self.peer, ips)
+    }
+
     pub fn clear_src(&self) {
         (*self.peer.endpoint.lock()).as_mut().map(|e| e.clear_src());
     }
@@ -41,6 +41,10 @@ impl<J: SequentialJob> Queue<J> {
         self.queue.lock().push_back(job).is_ok()
     }
 
+    pub fn len(&self) -> usize {
+        self.queue.lock().len()
+    }
+
     pub fn consume(&self) {
         // check if we are the first contender
         let pos = self.contenders.fetch_add(1, Ordering::SeqCst);
@@ -170,6 +174,33 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_queue_len() {
+        struct TestJob {}
+
+        impl SequentialJob for TestJob {
+            fn is_ready(&self) -> bool {
+                false
+            }
+
+            fn sequential_work(self) {}
+        }
+
+        let queue = Queue::new();
+        assert_eq!(queue.len(), 0);
+
+        // jobs which are not ready remain in the queue
+        for i in 0..INORDER_QUEUE_SIZE {
+            assert!(queue.push(TestJob {}));
+            queue.consume();
+            assert_eq!(queue.len(), i + 1);
+        }
+
+        // the depth is bounded by the size of the queue
+        assert!(!queue.push(TestJob {}));
+        assert_eq!(queue.len(), INORDER_QUEUE_SIZE);
+    }
+
     /* Fuzz the Queue */
     #[test]
     fn test_fuzz_queue() {
@@ -10,9 +10,9 @@ use super::super::{tun, udp, Endpoint};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
-use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305};
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey};
 use spin::Mutex;
-use zerocopy::{AsBytes, LayoutVerified};
+use zerocopy::LayoutVerified;
 
 struct Inner<E: Endpoint, C: Callbacks, T: tun::Writer, B: udp::Writer<E>> {
     ready: AtomicBool,                       // job status
@@ -81,6 +81,12 @@ impl<E: Endpoint, C: Callbacks, T: tun::Writer, B: udp::Writer<E>> ParallelJob
 
             // process buffer
             let ok = (|| {
+                // refuse expired key-pairs
+                if job.state.keypair.is_expired() {
+                    log::debug!("inbound worker: key-pair expired");
+                    return false;
+                }
+
                 // cast to header followed by payload
                 let (header, packet): (LayoutVerified<&mut [u8], TransportHeader>, &mut [u8]) =
                     match LayoutVerified::new_from_prefix(&mut msg.1[..]) {
@@ -89,13 +95,19 @@ impl<E: Endpoint, C: Callbacks, T: tun::Writer, B: udp::Writer<E>> ParallelJob
                     };
 
                 // create nonce object
-                let mut nonce = [0u8; 12];
-                debug_assert_eq!(nonce.len(), CHACHA20_POLY1305.nonce_len());
-                nonce[4..].copy_from_slice(header.f_counter.as_bytes());
+                let nonce = job.state.keypair.cipher.nonce(header.f_counter.get());
+                debug_assert_eq!(
+                    nonce.len(),
+                    job.state.keypair.cipher.algorithm().nonce_len()
+                );
                 let nonce = Nonce::assume_unique_for_key(nonce);
                 // do the weird ring AEAD dance
                 let key = LessSafeKey::new(
-                    UnboundKey::new(&CHACHA20_POLY1305, &job.state.keypair.recv.key[..]).unwrap(),
+                    UnboundKey::new(
+                        job.state.keypair.cipher.algorithm(),
+                        &job.state.keypair.recv.key[..],
+                    )
+                    .unwrap(),
                 );
 
                 // attempt to open (and authenticate) the body
@@ -109,8 +121,12 @@ impl<E: Endpoint, C: Callbacks, T: tun::Writer, B: udp::Writer<E>> ParallelJob
                     return false;
                 }
 
-                // check crypto-key router
-                packet.len() == SIZE_TAG || peer.device.table.check_route(&peer, &packet)
+                // check crypto-key router (keep-alive messages carry no packet)
+                if packet.len() != SIZE_TAG && !peer.device.table.check_route(&peer, &packet) {
+                    log::debug!("inbound worker: source address not allowed for peer");
+                    return false;
+                }
+                true
             })();
 
             // remove message in case of failure:
@@ -158,6 +174,7 @@ impl<E: Endpoint, C: Callbacks, T: tun::Writer, B: udp::Writer<E>> SequentialJob
         // check for replay
         if !job.state.protector.lock().update(header.f_counter.get()) {
             log::debug!("inbound worker: replay detected");
+            peer.replays.fetch_add(1, Ordering::Relaxed);
             return;
         }
 
@@ -167,19 +184,28 @@ impl<E: Endpoint, C: Callbacks, T: tun::Writer, B: udp::Writer<E>> SequentialJob
             peer.confirm_key(&job.state.keypair);
         }
 
-        // update endpoint
-        *peer.endpoint.lock() = endpoint;
-
         // check if should be written to TUN
         // (keep-alive and malformed packets will have no inner length)
-        if let Some(inner) = inner_length(packet) {
-            if inner + SIZE_TAG <= packet.len() {
-                let _ = peer.device.inbound.write(&packet[..inner]).map_err(|e| {
-                    log::debug!("failed to write inbound packet to TUN: {:?}", e);
-                });
+        let inner = inner_length(packet).filter(|inner| inner + SIZE_TAG <= packet.len());
+
+        // drop disallowed protocols before updating the endpoint, counters and timers
+        if let Some(inner) = inner {
+            if !peer.allows_protocol(&packet[..inner]) {
+                log::debug!("inbound worker: protocol not allowed for peer");
+                return;
             }
         }
 
+        // update endpoint
+        peer.update_endpoint(endpoint);
+
+        if let Some(inner) = inner {
+            let _ = peer.device.inbound.write(&packet[..inner]).map_err(|e| {
+                log::debug!("failed to write inbound packet to TUN: {:?}", e);
+            });
+            peer.device.observe(&packet[..inner]);
+        }
+
         // trigger callback
         C::recv(&peer.opaque, msg.1.len(), true, &job.state.keypair);
     }
@@ -70,6 +70,28 @@ impl<T: Eq + Clone> RoutingTable<T> {
         }
     }
 
+    // atomically replace the keys mapping to the given value
+    // (without any window where the value is unreachable or has stale keys)
+    pub fn replace(&self, value: &T, ips: &[(IpAddr, u32)]) {
+        let mut v4 = self.ipv4.write();
+        let mut v6 = self.ipv6.write();
+
+        for (ip, cidr) in Self::collect(&*v4, value) {
+            v4.remove(ip, cidr);
+        }
+
+        for (ip, cidr) in Self::collect(&*v6, value) {
+            v6.remove(ip, cidr);
+        }
+
+        for (ip, cidr) in ips {
+            match ip {
+                IpAddr::V4(ip) => v4.insert(ip.mask(*cidr), *cidr, value.clone()),
+                IpAddr::V6(ip) => v6.insert(ip.mask(*cidr), *cidr, value.clone()),
+            };
+        }
+    }
+
     #[inline(always)]
     pub fn get_route(&self, packet: &[u8]) -> Option<T> {
         match packet.get(0)? >> 4 {
@@ -122,7 +144,7 @@ impl<T: Eq + Clone> RoutingTable<T> {
                         .longest_match(Ipv4Addr::from(header.f_source))
                         .map(|(_, _, p)| p == peer)
                 })
-                .is_some(),
+                .unwrap_or(false),
 
             Some(VERSION_IP6) => LayoutVerified::new_from_prefix(packet)
                 .and_then(|(header, _): (LayoutVerified<&[u8], IPv6Header>, _)| {
@@ -131,8 +153,135 @@ impl<T: Eq + Clone> RoutingTable<T> {
                         .longest_match(Ipv6Addr::from(header.f_source))
                         .map(|(_, _, p)| p == peer)
                 })
-                .is_some(),
+                .unwrap_or(false),
             _ => false,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ipv4_packet(dst: Ipv4Addr) -> Vec<u8> {
+        let mut packet = vec![0u8; 20];
+        packet[0] = 0x45;
+        packet[16..20].copy_from_slice(&dst.octets());
+        packet
+    }
+
+    fn ipv4_packet_from(src: Ipv4Addr) -> Vec<u8> {
+        let mut packet = vec![0u8; 20];
+        packet[0] = 0x45;
+        packet[12..16].copy_from_slice(&src.octets());
+        packet
+    }
+
+    fn ipv6_packet_from(src: Ipv6Addr) -> Vec<u8> {
+        let mut packet = vec![0u8; 40];
+        packet[0] = 0x60;
+        packet[8..24].copy_from_slice(&src.octets());
+        packet
+    }
+
+    #[test]
+    fn test_overlapping_prefixes() {
+        let table: RoutingTable<usize> = RoutingTable::new();
+        table.insert("10.0.0.0".parse().unwrap(), 8, 1);
+        table.insert("10.0.1.0".parse().unwrap(), 24, 2);
+
+        // the longest prefix wins
+        assert_eq!(
+            table.get_route(&ipv4_packet("10.0.1.1".parse().unwrap())),
+            Some(2)
+        );
+        assert_eq!(
+            table.get_route(&ipv4_packet("10.0.2.1".parse().unwrap())),
+            Some(1)
+        );
+        assert_eq!(
+            table.get_route(&ipv4_packet("11.0.0.1".parse().unwrap())),
+            None
+        );
+
+        // sources are only accepted from the peer owning the longest prefix
+        let inner = ipv4_packet_from("10.0.1.1".parse().unwrap());
+        let outer = ipv4_packet_from("10.0.2.1".parse().unwrap());
+        assert!(table.check_route(&2, &inner));
+        assert!(!table.check_route(&1, &inner));
+        assert!(table.check_route(&1, &outer));
+        assert!(!table.check_route(&2, &outer));
+        assert!(!table.check_route(&1, &ipv4_packet_from("11.0.0.1".parse().unwrap())));
+    }
+
+    #[test]
+    fn test_default_route() {
+        let table: RoutingTable<usize> = RoutingTable::new();
+        table.insert("0.0.0.0".parse().unwrap(), 0, 1);
+        table.insert("::".parse().unwrap(), 0, 1);
+        table.insert("10.0.0.0".parse().unwrap(), 24, 2);
+
+        assert_eq!(
+            table.get_route(&ipv4_packet("1.2.3.4".parse().unwrap())),
+            Some(1)
+        );
+        assert_eq!(
+            table.get_route(&ipv4_packet("10.0.0.1".parse().unwrap())),
+            Some(2)
+        );
+
+        assert!(table.check_route(&1, &ipv4_packet_from("1.2.3.4".parse().unwrap())));
+        assert!(table.check_route(&1, &ipv6_packet_from("fd00::1".parse().unwrap())));
+        assert!(!table.check_route(&2, &ipv6_packet_from("fd00::1".parse().unwrap())));
+        assert!(!table.check_route(&1, &ipv4_packet_from("10.0.0.1".parse().unwrap())));
+    }
+
+    #[test]
+    fn test_replace() {
+        let table: RoutingTable<usize> = RoutingTable::new();
+        table.insert("10.0.0.0".parse().unwrap(), 24, 1);
+        table.insert("10.0.1.0".parse().unwrap(), 24, 1);
+        table.insert("10.0.2.0".parse().unwrap(), 24, 2);
+
+        // replace the subnets of the first value
+        table.replace(
+            &1,
+            &[
+                ("10.0.3.0".parse().unwrap(), 24),
+                ("fd00::".parse().unwrap(), 64),
+            ],
+        );
+
+        // no stale entries remain
+        let mut ips = table.list(&1);
+        ips.sort();
+        assert_eq!(
+            ips,
+            vec![
+                ("10.0.3.0".parse().unwrap(), 24),
+                ("fd00::".parse().unwrap(), 64)
+            ]
+        );
+        assert_eq!(
+            table.get_route(&ipv4_packet("10.0.0.1".parse().unwrap())),
+            None
+        );
+        assert_eq!(
+            table.get_route(&ipv4_packet("10.0.1.1".parse().unwrap())),
+            None
+        );
+
+        // routing follows the new set
+        assert_eq!(
+            table.get_route(&ipv4_packet("10.0.3.1".parse().unwrap())),
+            Some(1)
+        );
+
+        // other values are unaffected
+        assert_eq!(table.list(&2), vec![("10.0.2.0".parse().unwrap(), 24)]);
+        assert_eq!(
+            table.get_route(&ipv4_packet("10.0.2.1".parse().unwrap())),
+            Some(2)
+        );
+    }
+}
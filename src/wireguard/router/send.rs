@@ -7,12 +7,13 @@ use super::{REJECT_AFTER_MESSAGES, SIZE_TAG};
 
 use super::super::{tun, udp, Endpoint};
 
+use std::mem;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
-use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305};
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey};
 use spin::Mutex;
-use zerocopy::{AsBytes, LayoutVerified};
+use zerocopy::LayoutVerified;
 
 struct Inner<E: Endpoint, C: Callbacks, T: tun::Writer, B: udp::Writer<E>> {
     ready: AtomicBool,
@@ -86,15 +87,14 @@ impl<E: Endpoint, C: Callbacks, T: tun::Writer, B: udp::Writer<E>> ParallelJob
             header.f_counter.set(job.counter);
 
             // create a nonce object
-            let mut nonce = [0u8; 12];
-            debug_assert_eq!(nonce.len(), CHACHA20_POLY1305.nonce_len());
-            nonce[4..].copy_from_slice(header.f_counter.as_bytes());
+            let nonce = job.keypair.cipher.nonce(job.counter);
+            debug_assert_eq!(nonce.len(), job.keypair.cipher.algorithm().nonce_len());
             let nonce = Nonce::assume_unique_for_key(nonce);
 
             // encrypt contents of transport message in-place
             let tag_offset = packet.len() - SIZE_TAG;
             let key = LessSafeKey::new(
-                UnboundKey::new(&CHACHA20_POLY1305, &job.keypair.send.key[..]).unwrap(),
+                UnboundKey::new(job.keypair.cipher.algorithm(), &job.keypair.send.key[..]).unwrap(),
             );
             let tag = key
                 .seal_in_place_separate_tag(nonce, Aad::empty(), &mut packet[..tag_offset])
@@ -125,12 +125,10 @@ impl<E: Endpoint, C: Callbacks, T: tun::Writer, B: udp::Writer<E>> SequentialJob
         );
         log::trace!("processing sequential send job");
 
-        // send to peer
+        // send to peer (once the next slot is reached, if paced)
         let job = &self.0;
-        let msg = job.buffer.lock();
-        let xmit = job.peer.send_raw(&msg[..]).is_ok();
-
-        // trigger callback (for timers)
-        C::send(&job.peer.opaque, msg.len(), xmit, &job.keypair, job.counter);
+        let msg = mem::replace(&mut *job.buffer.lock(), vec![]);
+        job.peer
+            .transmit_paced(msg, job.keypair.clone(), job.counter);
     }
 }
@@ -4,23 +4,33 @@ use super::{Callbacks, Device};
 
 use super::message_data_len;
 
+use super::super::constants::REJECT_AFTER_TIME;
+use super::constants::{INORDER_QUEUE_SIZE, MAX_QUEUED_PACKETS};
+
 use super::super::dummy;
 use super::super::dummy_keypair;
 use super::super::tests::make_packet;
 
 use crate::platform::udp::Reader;
 
-use std::net::IpAddr;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::ops::Deref;
 use std::sync::atomic::AtomicUsize;
 use std::sync::atomic::Ordering;
 use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
 use std::sync::Arc;
 use std::sync::Mutex;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use env_logger;
+use hjul::Runner;
 use num_cpus;
+use pnet::packet::icmp::{self, IcmpPacket, IcmpTypes};
+use pnet::packet::icmpv6::{self, Icmpv6Packet, Icmpv6Types};
+use pnet::packet::ip::IpNextHeaderProtocols;
+use pnet::packet::ipv4::{self, Ipv4Packet, MutableIpv4Packet};
+use pnet::packet::ipv6::{Ipv6Packet, MutableIpv6Packet};
+use pnet::packet::Packet;
 use rand::Rng;
 use test::Bencher;
 
@@ -553,3 +563,675 @@ fn test_bidirectional() {
         }
     }
 }
+
+#[test]
+fn test_has_session() {
+    init();
+
+    // create device
+    let (_fake, _reader, tun_writer, _mtu) = dummy::TunTest::create(false);
+    let router: Device<_, TestCallbacks, _, _> = Device::new(1, tun_writer);
+    router.set_outbound_writer(dummy::VoidBind::new());
+
+    // add new peer
+    let opaque = Opaque::new();
+    let peer = router.new_peer(opaque.clone());
+    assert!(!peer.has_session(), "peer without key-pairs has no session");
+
+    // an unconfirmed key-pair can not be used for sending
+    peer.add_keypair(dummy_keypair(false));
+    assert!(
+        !peer.has_session(),
+        "unconfirmed key-pair should not constitute a session"
+    );
+
+    // a confirmed key-pair can be used for sending
+    peer.add_keypair(dummy_keypair(true));
+    assert!(
+        peer.has_session(),
+        "confirmed key-pair should constitute a session"
+    );
+
+    // an expired key-pair can not be used for sending
+    // (the key-pair of a new handshake has a fresh id)
+    let mut expired = dummy_keypair(true);
+    expired.recv.id += 1;
+    expired.birth = Instant::now() - REJECT_AFTER_TIME - Duration::from_secs(1);
+    peer.add_keypair(expired);
+    assert!(
+        !peer.has_session(),
+        "expired key-pair should not constitute a session"
+    );
+}
+
+#[test]
+fn test_expired_keypair() {
+    init();
+
+    let ((_bind_reader1, bind_writer1), (bind_reader2, _bind_writer2)) = dummy::PairBind::pair();
+
+    let (_fake1, _, tun_writer1, _) = dummy::TunTest::create(false);
+    let router1: Device<_, TestCallbacks, _, _> = Device::new(1, tun_writer1);
+    router1.set_outbound_writer(bind_writer1);
+
+    let (_fake2, _, tun_writer2, _) = dummy::TunTest::create(false);
+    let router2: Device<_, TestCallbacks, _, _> = Device::new(1, tun_writer2);
+
+    let opaque1 = Opaque::new();
+    let opaque2 = Opaque::new();
+    let peer1 = router1.new_peer(opaque1.clone());
+    let peer2 = router2.new_peer(opaque2.clone());
+    peer1.add_allowed_ip("192.168.2.0".parse().unwrap(), 24);
+    peer1.set_endpoint(dummy::UnitEndpoint::new());
+
+    let expired = |initiator| {
+        let mut keypair = dummy_keypair(initiator);
+        keypair.birth = Instant::now() - REJECT_AFTER_TIME - Duration::from_secs(1);
+        assert!(keypair.is_expired());
+        keypair
+    };
+
+    // an expired key-pair is not used for encryption: a new key is requested instead
+    peer1.add_keypair(expired(true));
+    assert_eq!(opaque1.need_key.wait(TIMEOUT), Some(()));
+    let msg = make_packet(
+        SIZE_MSG,
+        "10.0.0.1".parse().unwrap(),
+        "192.168.2.10".parse().unwrap(),
+        0,
+    );
+    router1.send(pad(&msg)).unwrap();
+    assert_eq!(opaque1.need_key.wait(TIMEOUT), Some(()));
+    assert_eq!(opaque1.send.now(), None, "sent with an expired key-pair");
+
+    // an expired key-pair is not used for decryption: the message is dropped
+    let keypair = dummy_keypair(true);
+    assert!(!keypair.is_expired());
+    peer1.add_keypair(keypair);
+    let mut buf = vec![0u8; SIZE_MSG * 2];
+    let (len, from) = bind_reader2.read(&mut buf).unwrap();
+    buf.truncate(len);
+
+    peer2.add_keypair(expired(false));
+    router2.recv(from, buf).unwrap();
+    assert_eq!(
+        opaque2.recv.wait(TIMEOUT),
+        None,
+        "received with an expired key-pair"
+    );
+    assert_eq!(opaque2.key_confirmed.now(), None);
+}
+
+#[test]
+fn test_expire_keys() {
+    init();
+
+    let (_fake, _reader, tun_writer, _mtu) = dummy::TunTest::create(false);
+    let router: Device<_, TestCallbacks, _, _> = Device::new(1, tun_writer);
+    router.set_outbound_writer(dummy::VoidBind::new());
+
+    let peer = router.new_peer(Opaque::new());
+    let keypair = dummy_keypair(true);
+    let birth = keypair.birth;
+    let id = keypair.local_id();
+    peer.add_keypair(keypair);
+
+    // a transport message for the key-pair (with an invalid tag)
+    let mut msg = vec![0u8; SIZE_KEEPALIVE];
+    msg[0] = 4;
+    msg[4..8].copy_from_slice(&id.to_le_bytes());
+
+    // the key-pair is retained until it expires (advancing a simulated clock)
+    peer.expire_keys(birth + REJECT_AFTER_TIME - Duration::from_secs(1));
+    assert!(peer.has_session());
+    assert!(router.recv(dummy::UnitEndpoint::new(), msg.clone()).is_ok());
+
+    // once expired the id no longer maps to the peer
+    peer.expire_keys(birth + REJECT_AFTER_TIME);
+    assert!(!peer.has_session());
+    assert_eq!(peer.session_initiator(), None);
+    assert!(router.recv(dummy::UnitEndpoint::new(), msg).is_err());
+
+    // the id is released when the next key-pair is added
+    let released = peer.add_keypair(dummy_keypair(true));
+    assert!(released.contains(&id));
+}
+
+#[test]
+fn test_key_rotation() {
+    init();
+
+    let ((_bind_reader1, bind_writer1), (bind_reader2, _bind_writer2)) = dummy::PairBind::pair();
+
+    let (_fake1, _, tun_writer1, _) = dummy::TunTest::create(false);
+    let router1: Device<_, TestCallbacks, _, _> = Device::new(1, tun_writer1);
+    router1.set_outbound_writer(bind_writer1);
+
+    let (_fake2, _, tun_writer2, _) = dummy::TunTest::create(false);
+    let router2: Device<_, TestCallbacks, _, _> = Device::new(1, tun_writer2);
+
+    let opaque2 = Opaque::new();
+    let peer1 = router1.new_peer(Opaque::new());
+    let peer2 = router2.new_peer(opaque2.clone());
+    peer1.add_allowed_ip("192.168.2.0".parse().unwrap(), 24);
+    peer1.set_endpoint(dummy::UnitEndpoint::new());
+    peer2.add_allowed_ip("10.0.0.0".parse().unwrap(), 8);
+
+    // the key-pairs of successive handshakes (with distinct ids and keys)
+    let keypair = |n: u32, initiator| {
+        let mut keypair = dummy_keypair(initiator);
+        keypair.send.id += n;
+        keypair.recv.id += n;
+        keypair.send.key[0] ^= n as u8;
+        keypair.recv.key[0] ^= n as u8;
+        keypair
+    };
+
+    let read = || {
+        let mut buf = vec![0u8; SIZE_MSG * 2];
+        let (len, from) = bind_reader2.read(&mut buf).unwrap();
+        buf.truncate(len);
+        (from, buf)
+    };
+
+    // establish a session: the responder confirms the key-pair with the keepalive
+    let handshake = |n| {
+        let released = peer2.add_keypair(keypair(n, false));
+        peer1.add_keypair(keypair(n, true));
+        let (from, msg) = read();
+        let len = msg.len();
+        router2.recv(from, msg).unwrap();
+        assert_eq!(opaque2.recv.wait(TIMEOUT), Some((len, true)));
+        released
+    };
+
+    let old_id = keypair(0, false).local_id();
+    assert!(handshake(0).is_empty());
+
+    // messages encrypted using the first session, delivered after rotation
+    let mut delayed = vec![];
+    for id in 0..2 {
+        let msg = make_packet(
+            SIZE_MSG,
+            "10.0.0.1".parse().unwrap(),
+            "192.168.2.10".parse().unwrap(),
+            id,
+        );
+        router1.send(pad(&msg)).unwrap();
+        delayed.push(read());
+    }
+
+    // the old id still maps to the previous session
+    assert!(handshake(1).is_empty());
+    let (from, msg) = delayed.remove(0);
+    let len = msg.len();
+    router2.recv(from, msg).unwrap();
+    assert_eq!(opaque2.recv.wait(TIMEOUT), Some((len, true)));
+
+    // until the previous session is retired (and the id released)
+    assert_eq!(handshake(2), vec![old_id]);
+    let (from, msg) = delayed.remove(0);
+    assert!(router2.recv(from, msg).is_err());
+    assert_eq!(opaque2.recv.now(), None);
+}
+
+#[test]
+fn test_outbound_drops() {
+    init();
+
+    // the other end is never read: transmission blocks once the pair bind is full
+    let ((_bind_reader1, bind_writer1), (bind_reader2, _bind_writer2)) = dummy::PairBind::pair();
+
+    let (_fake, _reader, tun_writer, _mtu) = dummy::TunTest::create(false);
+    let router: Device<_, TestCallbacks, _, _> = Device::new(1, tun_writer);
+    router.set_outbound_writer(bind_writer1);
+
+    let opaque = Opaque::new();
+    let peer = router.new_peer(opaque.clone());
+    peer.add_allowed_ip("192.168.1.0".parse().unwrap(), 24);
+    peer.set_endpoint(dummy::UnitEndpoint::new());
+
+    // confirming the key-pair sends a keepalive
+    peer.add_keypair(dummy_keypair(true));
+    assert_eq!(peer.outbound_drops(), 0);
+
+    // overflow the outbound queue
+    let num_packets = 2 * INORDER_QUEUE_SIZE;
+    for id in 0..num_packets {
+        let msg = make_packet(
+            SIZE_MSG,
+            "10.0.0.1".parse().unwrap(),
+            "192.168.1.20".parse().unwrap(),
+            id as u64,
+        );
+        router.send(pad(&msg)).unwrap();
+    }
+
+    let drops = peer.outbound_drops();
+    assert!(
+        drops >= (num_packets - INORDER_QUEUE_SIZE - 256) as u64,
+        "too few drops: {}",
+        drops
+    );
+
+    // every other message is transmitted once the other end is read
+    let mut buf = vec![0u8; SIZE_MSG * 2];
+    for _ in 0..(num_packets as u64 + 1 - drops) {
+        bind_reader2.read(&mut buf).unwrap();
+    }
+    assert_eq!(peer.outbound_drops(), drops);
+}
+
+#[test]
+fn test_outbound_queue_depth() {
+    init();
+
+    // the other end is never read: transmission blocks once the pair bind is full
+    let ((_bind_reader1, bind_writer1), (_bind_reader2, _bind_writer2)) = dummy::PairBind::pair();
+
+    let (_fake, _reader, tun_writer, _mtu) = dummy::TunTest::create(false);
+    let router: Device<_, TestCallbacks, _, _> = Device::new(1, tun_writer);
+    router.set_outbound_writer(bind_writer1);
+
+    let opaque = Opaque::new();
+    let peer = router.new_peer(opaque.clone());
+    peer.add_allowed_ip("192.168.1.0".parse().unwrap(), 24);
+    peer.set_endpoint(dummy::UnitEndpoint::new());
+    assert_eq!(peer.outbound_queue_depth(), 0);
+    assert_eq!(router.egress_queue_depth(), 0);
+
+    let send = |num_packets: usize| {
+        for id in 0..num_packets {
+            let msg = make_packet(
+                SIZE_MSG,
+                "10.0.0.1".parse().unwrap(),
+                "192.168.1.20".parse().unwrap(),
+                id as u64,
+            );
+            router.send(pad(&msg)).unwrap();
+        }
+    };
+
+    // fill the pair bind (the keepalive confirming the key-pair included),
+    // such that the worker blocks writing the last message
+    peer.add_keypair(dummy_keypair(true));
+    send(128);
+    for _ in 0..128 {
+        assert!(opaque.send.wait(TIMEOUT).is_some(), "message was not sent");
+    }
+    let start = Instant::now();
+    while router.egress_queue_depth() != 1 {
+        assert!(start.elapsed() < TIMEOUT, "transmission did not block");
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(peer.outbound_queue_depth(), 0);
+
+    // subsequent messages remain queued for the peer
+    let num_queued = 10;
+    send(num_queued);
+    assert_eq!(peer.outbound_queue_depth(), num_queued);
+    assert_eq!(router.egress_queue_depth(), 1);
+    assert_eq!(opaque.send.now(), None, "unexpected send event");
+}
+
+#[test]
+fn test_pacing() {
+    init();
+
+    let (_fake, _reader, tun_writer, _mtu) = dummy::TunTest::create(false);
+    let router: Device<_, TestCallbacks, _, _> = Device::new(1, tun_writer);
+    router.set_outbound_writer(dummy::VoidBind::new());
+    let runner = Runner::new(Duration::from_millis(10), 1000, 16);
+
+    // a paced and an unpaced peer (sharing the single worker)
+    let rate = 10;
+    let interval = Duration::from_secs(1) / rate;
+    let mut peers = vec![];
+    for subnet in vec!["192.168.1.0", "192.168.2.0"] {
+        let opaque = Opaque::new();
+        let peer = router.new_peer(opaque.clone());
+        peer.add_allowed_ip(subnet.parse().unwrap(), 24);
+
+        // confirming the key-pair sends a keepalive
+        peer.add_keypair(dummy_keypair(true));
+        assert_eq!(opaque.send.wait(TIMEOUT), Some((SIZE_KEEPALIVE, false)));
+        peers.push((peer, opaque));
+    }
+    peers[0].0.set_pacing(Some(rate), &runner);
+
+    // a burst to the paced peer, followed by a message to the unpaced peer
+    let src = "10.0.0.1".parse().unwrap();
+    let start = Instant::now();
+    for id in 0..5 {
+        let msg = make_packet(SIZE_MSG, src, "192.168.1.20".parse().unwrap(), id);
+        router.send(pad(&msg)).unwrap();
+    }
+    let msg = make_packet(SIZE_MSG, src, "192.168.2.20".parse().unwrap(), 0);
+    router.send(pad(&msg)).unwrap();
+
+    // the worker is not blocked by the paced peer
+    assert_eq!(
+        peers[1].1.send.wait(TIMEOUT),
+        Some((SIZE_KEEPALIVE + SIZE_MSG, false))
+    );
+    assert!(start.elapsed() < 4 * interval, "worker blocked by pacing");
+
+    // the burst is spread out at the rate
+    for _ in 0..5 {
+        assert_eq!(
+            peers[0].1.send.wait(TIMEOUT),
+            Some((SIZE_KEEPALIVE + SIZE_MSG, false))
+        );
+    }
+    assert!(start.elapsed() >= 4 * interval, "burst not paced");
+    assert_eq!(peers[0].1.send.now(), None, "unexpected send event");
+
+    // disabling pacing sends messages immediately
+    peers[0].0.set_pacing(None, &runner);
+    let start = Instant::now();
+    for id in 0..5 {
+        let msg = make_packet(SIZE_MSG, src, "192.168.1.20".parse().unwrap(), id);
+        router.send(pad(&msg)).unwrap();
+    }
+    for _ in 0..5 {
+        assert_eq!(
+            peers[0].1.send.wait(TIMEOUT),
+            Some((SIZE_KEEPALIVE + SIZE_MSG, false))
+        );
+    }
+    assert!(start.elapsed() < 4 * interval);
+}
+
+#[test]
+fn test_staged_packets() {
+    init();
+
+    let (_fake, _reader, tun_writer, _mtu) = dummy::TunTest::create(false);
+    let router: Device<_, TestCallbacks, dummy::TunWriter, dummy::VoidBind> =
+        Device::new(1, tun_writer);
+
+    let opaque = Opaque::new();
+    let peer = router.new_peer(opaque.clone());
+    peer.add_allowed_ip("192.168.1.0".parse().unwrap(), 24);
+
+    // without a key-pair, messages are staged (dropping the oldest when full)
+    let packet = |id| {
+        pad(&make_packet(
+            SIZE_MSG,
+            "10.0.0.1".parse().unwrap(),
+            "192.168.1.20".parse().unwrap(),
+            id as u64,
+        ))
+    };
+    for id in 0..(MAX_QUEUED_PACKETS + 3) {
+        router.send(packet(id)).unwrap();
+    }
+    assert_eq!(opaque.need_key.wait(TIMEOUT), Some(()));
+
+    {
+        let staged = peer.staged_packets.lock();
+        assert_eq!(staged.len(), MAX_QUEUED_PACKETS);
+        assert_eq!(staged.front(), Some(&packet(3)));
+        assert_eq!(staged.back(), Some(&packet(MAX_QUEUED_PACKETS + 2)));
+    }
+
+    peer.purge_staged_packets();
+    assert!(peer.staged_packets.lock().is_empty());
+}
+
+#[test]
+fn test_replays() {
+    init();
+
+    let ((_bind_reader1, bind_writer1), (bind_reader2, _bind_writer2)) = dummy::PairBind::pair();
+
+    let (_fake1, _, tun_writer1, _) = dummy::TunTest::create(false);
+    let router1: Device<_, TestCallbacks, _, _> = Device::new(1, tun_writer1);
+    router1.set_outbound_writer(bind_writer1);
+
+    let (_fake2, _, tun_writer2, _) = dummy::TunTest::create(false);
+    let router2: Device<_, TestCallbacks, _, _> = Device::new(1, tun_writer2);
+
+    let opaque2 = Opaque::new();
+    let peer1 = router1.new_peer(Opaque::new());
+    let peer2 = router2.new_peer(opaque2.clone());
+    peer1.set_endpoint(dummy::UnitEndpoint::new());
+    peer2.add_keypair(dummy_keypair(false));
+
+    // confirming the key-pair sends a keepalive
+    peer1.add_keypair(dummy_keypair(true));
+    let mut buf = vec![0u8; SIZE_MSG];
+    let (len, from) = bind_reader2.read(&mut buf).unwrap();
+    buf.truncate(len);
+
+    router2.recv(from, buf.clone()).unwrap();
+    assert_eq!(opaque2.recv.wait(TIMEOUT), Some((SIZE_KEEPALIVE, true)));
+    assert_eq!(peer2.replays(), 0);
+
+    // the replayed message is rejected and counted
+    router2.recv(dummy::UnitEndpoint::new(), buf).unwrap();
+    let start = Instant::now();
+    while peer2.replays() == 0 {
+        assert!(start.elapsed() < TIMEOUT, "replay was not detected");
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(peer2.replays(), 1);
+    assert_eq!(opaque2.recv.now(), None, "replay was processed");
+}
+
+#[test]
+fn test_inbound_source_check() {
+    init();
+
+    let ((_bind_reader1, bind_writer1), (bind_reader2, _bind_writer2)) = dummy::PairBind::pair();
+
+    let (_fake1, _, tun_writer1, _) = dummy::TunTest::create(false);
+    let router1: Device<_, TestCallbacks, _, _> = Device::new(1, tun_writer1);
+    router1.set_outbound_writer(bind_writer1);
+
+    let (_fake2, _, tun_writer2, _) = dummy::TunTest::create(false);
+    let router2: Device<_, TestCallbacks, _, _> = Device::new(1, tun_writer2);
+
+    let opaque2 = Opaque::new();
+    let peer1 = router1.new_peer(Opaque::new());
+    let peer2 = router2.new_peer(opaque2.clone());
+    let other = router2.new_peer(Opaque::new());
+
+    peer1.add_allowed_ip("192.168.2.0".parse().unwrap(), 24);
+    peer1.set_endpoint(dummy::UnitEndpoint::new());
+    peer2.add_allowed_ip("10.0.0.0".parse().unwrap(), 8);
+    other.add_allowed_ip("10.0.1.0".parse().unwrap(), 24);
+    peer2.add_keypair(dummy_keypair(false));
+
+    let forward = || {
+        let mut buf = vec![0u8; SIZE_MSG * 2];
+        let (len, from) = bind_reader2.read(&mut buf).unwrap();
+        buf.truncate(len);
+        router2.recv(from, buf).unwrap();
+        len
+    };
+
+    // the keepalive confirming the key-pair is exempt
+    peer1.add_keypair(dummy_keypair(true));
+    let len = forward();
+    assert_eq!(opaque2.recv.wait(TIMEOUT), Some((len, true)));
+
+    // only packets from the addresses routed to the peer are accepted
+    for (src, allowed) in &[
+        ("10.0.0.1", true),
+        ("10.0.1.1", false), // routed to the other peer
+        ("172.16.0.1", false),
+        ("10.2.0.1", true),
+    ] {
+        let msg = make_packet(
+            SIZE_MSG,
+            src.parse().unwrap(),
+            "192.168.2.10".parse().unwrap(),
+            0,
+        );
+        router1.send(pad(&msg)).unwrap();
+        let len = forward();
+        if *allowed {
+            assert_eq!(opaque2.recv.wait(TIMEOUT), Some((len, true)), "{}", src);
+        } else {
+            assert_eq!(opaque2.recv.wait(TIMEOUT), None, "{}", src);
+        }
+    }
+}
+
+#[test]
+fn test_send_raw_empty() {
+    init();
+
+    // create device with a pair bind
+    let ((_, bind_writer1), (bind_reader2, _)) = dummy::PairBind::pair();
+    let (_fake, _reader, tun_writer, _mtu) = dummy::TunTest::create(false);
+    let router: Device<_, TestCallbacks, _, _> = Device::new(1, tun_writer);
+    router.set_outbound_writer(bind_writer1);
+
+    // add new peer with an endpoint
+    let peer = router.new_peer(Opaque::new());
+    peer.set_endpoint(dummy::UnitEndpoint::new());
+
+    // the empty message should not be written to the bind
+    let msg = [0x1, 0x2, 0x3, 0x4];
+    peer.send_raw(&[])
+        .expect("sending empty message should not fail");
+    peer.send_raw(&msg).expect("failed to send message");
+
+    // the first datagram received is the non-empty message
+    let mut buf = vec![0u8; SIZE_MSG];
+    let (len, _) = bind_reader2.read(&mut buf).unwrap();
+    assert_eq!(&buf[..len], &msg[..], "empty datagram was written to bind");
+}
+
+// prefix an IP packet with space for the transport header
+fn prefix(packet: &[u8]) -> Vec<u8> {
+    let mut msg = vec![0u8; SIZE_MESSAGE_PREFIX];
+    msg.extend_from_slice(packet);
+    msg
+}
+
+#[test]
+fn test_icmp_unreachable_ipv4() {
+    init();
+
+    let make_ipv4 = |src: &str, dst: &str, payload: &[u8], icmp: bool| {
+        let mut msg = vec![0u8; MutableIpv4Packet::minimum_packet_size() + payload.len()];
+        let length = msg.len() as u16;
+        let mut packet = MutableIpv4Packet::new(&mut msg[..]).unwrap();
+        packet.set_version(4);
+        packet.set_header_length(5);
+        packet.set_total_length(length);
+        packet.set_ttl(64);
+        packet.set_next_level_protocol(if icmp {
+            IpNextHeaderProtocols::Icmp
+        } else {
+            IpNextHeaderProtocols::Udp
+        });
+        packet.set_source(src.parse().unwrap());
+        packet.set_destination(dst.parse().unwrap());
+        packet.set_payload(payload);
+        msg
+    };
+
+    // create device writing to a fake TUN device
+    let (fake, _reader, tun_writer, _mtu) = dummy::TunTest::create(true);
+    let router: Device<_, TestCallbacks, _, _> = Device::new(1, tun_writer);
+    router.set_outbound_writer(dummy::VoidBind::new());
+
+    let error = make_ipv4("10.0.0.1", "10.0.1.1", &[3, 1, 0, 0, 0, 0, 0, 0], true);
+    let unroutable = make_ipv4("10.0.0.1", "10.0.1.2", &[0x42; 32], false);
+
+    // disabled by default
+    assert!(router.send(prefix(&unroutable)).is_err());
+
+    // no response to an ICMP error
+    router.set_icmp_unreachable(true);
+    assert!(router.send(prefix(&error)).is_err());
+
+    // response to an unroutable packet (the first message written to the TUN device)
+    assert!(router.send(prefix(&unroutable)).is_err());
+    let reply = fake.read();
+
+    let ip = Ipv4Packet::new(&reply[..]).unwrap();
+    assert_eq!(ip.get_total_length() as usize, reply.len());
+    assert_eq!(ip.get_checksum(), ipv4::checksum(&ip));
+    assert_eq!(ip.get_next_level_protocol(), IpNextHeaderProtocols::Icmp);
+    assert_eq!(ip.get_source(), "10.0.1.2".parse::<Ipv4Addr>().unwrap());
+    assert_eq!(
+        ip.get_destination(),
+        "10.0.0.1".parse::<Ipv4Addr>().unwrap()
+    );
+
+    let msg = IcmpPacket::new(ip.payload()).unwrap();
+    assert_eq!(msg.get_icmp_type(), IcmpTypes::DestinationUnreachable);
+    assert_eq!(msg.get_checksum(), icmp::checksum(&msg));
+    assert_eq!(
+        &msg.payload()[4..],
+        &unroutable[..],
+        "original packet not quoted"
+    );
+}
+
+#[test]
+fn test_icmp_unreachable_ipv6() {
+    init();
+
+    let make_ipv6 = |src: &str, dst: &str, payload: &[u8], icmp: bool| {
+        let mut msg = vec![0u8; MutableIpv6Packet::minimum_packet_size() + payload.len()];
+        let mut packet = MutableIpv6Packet::new(&mut msg[..]).unwrap();
+        packet.set_version(6);
+        packet.set_payload_length(payload.len() as u16);
+        packet.set_hop_limit(64);
+        packet.set_next_header(if icmp {
+            IpNextHeaderProtocols::Icmpv6
+        } else {
+            IpNextHeaderProtocols::Udp
+        });
+        packet.set_source(src.parse().unwrap());
+        packet.set_destination(dst.parse().unwrap());
+        packet.set_payload(payload);
+        msg
+    };
+
+    // create device writing to a fake TUN device
+    let (fake, _reader, tun_writer, _mtu) = dummy::TunTest::create(true);
+    let router: Device<_, TestCallbacks, _, _> = Device::new(1, tun_writer);
+    router.set_outbound_writer(dummy::VoidBind::new());
+
+    let error = make_ipv6("fd00::1", "fd01::1", &[1, 0, 0, 0, 0, 0, 0, 0], true);
+    let unroutable = make_ipv6("fd00::1", "fd01::2", &[0x42; 32], false);
+
+    // disabled by default
+    assert!(router.send(prefix(&unroutable)).is_err());
+
+    // no response to an ICMPv6 error
+    router.set_icmp_unreachable(true);
+    assert!(router.send(prefix(&error)).is_err());
+
+    // response to an unroutable packet (the first message written to the TUN device)
+    assert!(router.send(prefix(&unroutable)).is_err());
+    let reply = fake.read();
+
+    let ip = Ipv6Packet::new(&reply[..]).unwrap();
+    assert_eq!(
+        ip.get_payload_length() as usize + Ipv6Packet::minimum_packet_size(),
+        reply.len()
+    );
+    assert_eq!(ip.get_next_header(), IpNextHeaderProtocols::Icmpv6);
+    assert_eq!(ip.get_source(), "fd01::2".parse::<Ipv6Addr>().unwrap());
+    assert_eq!(ip.get_destination(), "fd00::1".parse::<Ipv6Addr>().unwrap());
+
+    let msg = Icmpv6Packet::new(ip.payload()).unwrap();
+    assert_eq!(msg.get_icmpv6_type(), Icmpv6Types::DestinationUnreachable);
+    assert_eq!(
+        msg.get_checksum(),
+        icmpv6::checksum(&msg, &ip.get_source(), &ip.get_destination())
+    );
+    assert_eq!(
+        &msg.payload()[4..],
+        &unroutable[..],
+        "original packet not quoted"
+    );
+}
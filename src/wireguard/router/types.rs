@@ -39,6 +39,7 @@ pub enum RouterError {
     UnknownReceiverId,
     NoEndpoint,
     SendError,
+    ProtocolNotAllowed,
 }
 
 impl fmt::Display for RouterError {
@@ -51,6 +52,7 @@ impl fmt::Display for RouterError {
             }
             RouterError::NoEndpoint => write!(f, "No endpoint for peer"),
             RouterError::SendError => write!(f, "Failed to send packet on bind"),
+            RouterError::ProtocolNotAllowed => write!(f, "IP protocol not allowed for peer"),
         }
     }
 }
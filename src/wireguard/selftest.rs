@@ -0,0 +1,323 @@
+/* Self-test of the cryptographic stack:
+ *
+ * Known-answer tests of every primitive (fixed keys and inputs, expected outputs),
+ * followed by complete handshakes between two local devices (with fixed static keys)
+ * for every supported construction.
+ * The handshakes draw their randomness (ephemeral keys and ids) from a fixed rng,
+ * hence the derived key-pairs are known answers
+ * (the timestamps of the initiations are not mixed into the key-pairs).
+ *
+ * Intended to be run at start-up (in high-assurance deployments)
+ * to detect a miscompiled or misconfigured crypto backend.
+ */
+
+use super::handshake;
+use super::types::Cipher;
+
+use std::fmt;
+
+use aead::{Aead, NewAead, Payload};
+use blake2::Blake2s;
+use chacha20poly1305::ChaCha20Poly1305;
+use generic_array::GenericArray;
+use hmac::Hmac;
+use rand::prelude::{CryptoRng, RngCore};
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey};
+use x25519_dalek::{PublicKey, StaticSecret};
+
+// X25519 test vector (RFC 7748, section 6.1)
+const X25519_SK_A: &str = "77076d0a7318a57d3c16c17251b26645df4c2f87ebc0992ab177fba51db92c2a";
+const X25519_PK_A: &str = "8520f0098930a754748b7ddcb43ef75a0dbf3a0d26381af4eba4a98eaa9b4e6a";
+const X25519_SK_B: &str = "5dab087e624a8a4b79e17f8b83800ee66f3bb1292618b6fd1c2f8b27ff88e0eb";
+const X25519_PK_B: &str = "de9edb7d7b7dc1b4d35b61c2ece435373f8343c85b78674dadfc7e146f882b4f";
+const X25519_SHARED: &str = "4a5d9d5ba4ce2de1728e3bf480350f25e07e21c947d19e3376f09b3c1e161742";
+
+// C := Hash(Construction) and Hmac(C, Identifier)
+const CONSTRUCTION: &[u8] = b"Noise_IKpsk2_25519_ChaChaPoly_BLAKE2s";
+const IDENTIFIER: &[u8] = b"WireGuard v1 zx2c4 Jason@zx2c4.com";
+const BLAKE2S_CONSTRUCTION: &str =
+    "60e26daef327efc02ec335e2a025d2d016eb4206f87277f52d38d1988b78cd36";
+const HMAC_BLAKE2S_IDENTIFIER: &str =
+    "dcc7bc1ec6293d2d2d9236dc485c42a77249e0d427e5dc4188a5438250052e9f";
+
+// AEAD of a transport message (ciphertext || tag),
+// the counter spans several bytes to cover the layout of the nonce
+const AEAD_KEY: [u8; 32] = [0x53; 32];
+const AEAD_COUNTER: u64 = 0x01_0203_0405;
+const AEAD_PLAINTEXT: &[u8] = b"WireGuard transport self-test!";
+const AEAD_CHACHAPOLY: &str = "6a8be907cd7bdb701d6550ec61d589bf62735d60188c818ccd3b85de6375\
+                               eb3337c89b6bd57010cafc45eb10b641";
+const AEAD_AESGCM: &str = "6bbce4e339e14e9e23a9e0f16bb0051c4c5f5ba83ac06c40a8cef546edc8\
+                           4f05e6958b601f290a9ef9b77faaaad0";
+
+// AEAD of a handshake message (zero nonce, C as associated data)
+const AEAD_HANDSHAKE: &str = "6f5b76675ff914ae83b17a0b587966060d9b88015473007d4c2c6bc6325e\
+                              e3034c3dc4d188249fc570f55690acb2";
+
+// Key-pair derived by the initiator of a handshake (send key || receive key),
+// with the X25519 keys above as static keys and FixedRng(0x11), FixedRng(0x22) as rngs
+const HANDSHAKE_CHACHAPOLY: &str = "35592ddce740c40650069356f4a3d91e834bf0980df0\
+                                    aabc3052c9be8189b8834fee8ac7c06a9542da352325\
+                                    1820536713c7e4b3b53c9216b9b9e1ebd7170b91";
+const HANDSHAKE_AESGCM: &str = "9910e967effdc9e0e5e1cbcc1db5ed3f09266d8077b3\
+                                88b87ceef23fe97c46e597273ac4e1a4d19a72bafb7a\
+                                8204706f4b638b3db74c1f6d23607b3ce0dbc22e";
+
+/* An "rng" repeating a single byte:
+ * makes the handshake deterministic, it must only ever be used for the self-test.
+ */
+struct FixedRng(u8);
+
+impl RngCore for FixedRng {
+    fn next_u32(&mut self) -> u32 {
+        u32::from_le_bytes([self.0; 4])
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        u64::from_le_bytes([self.0; 8])
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for byte in dest.iter_mut() {
+            *byte = self.0;
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+impl CryptoRng for FixedRng {}
+
+#[derive(Debug)]
+pub enum SelfTestError {
+    X25519,
+    Blake2s,
+    HmacBlake2s,
+    Aead(Cipher),
+    Handshake(Cipher),
+}
+
+impl fmt::Display for SelfTestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SelfTestError::X25519 => write!(f, "X25519 known-answer test failed"),
+            SelfTestError::Blake2s => write!(f, "BLAKE2s known-answer test failed"),
+            SelfTestError::HmacBlake2s => write!(f, "HMAC-BLAKE2s known-answer test failed"),
+            SelfTestError::Aead(cipher) => write!(f, "{:?} known-answer test failed", cipher),
+            SelfTestError::Handshake(cipher) => write!(f, "{:?} handshake test failed", cipher),
+        }
+    }
+}
+
+fn decode(s: &str) -> Vec<u8> {
+    hex::decode(s).unwrap()
+}
+
+fn decode32(s: &str) -> [u8; 32] {
+    let mut key = [0u8; 32];
+    key.copy_from_slice(&decode(s));
+    key
+}
+
+fn check(ok: bool, err: SelfTestError) -> Result<(), SelfTestError> {
+    if ok {
+        Ok(())
+    } else {
+        Err(err)
+    }
+}
+
+fn x25519() -> Result<(), SelfTestError> {
+    let sk_a = StaticSecret::from(decode32(X25519_SK_A));
+    let sk_b = StaticSecret::from(decode32(X25519_SK_B));
+    let pk_a = PublicKey::from(&sk_a);
+    let pk_b = PublicKey::from(&sk_b);
+    check(
+        pk_a.as_bytes()[..] == decode(X25519_PK_A)[..]
+            && pk_b.as_bytes()[..] == decode(X25519_PK_B)[..]
+            && sk_a.diffie_hellman(&pk_b).as_bytes()[..] == decode(X25519_SHARED)[..]
+            && sk_b.diffie_hellman(&pk_a).as_bytes()[..] == decode(X25519_SHARED)[..],
+        SelfTestError::X25519,
+    )
+}
+
+fn blake2s() -> Result<(), SelfTestError> {
+    let ck = {
+        use blake2::Digest;
+        let mut hsh = Blake2s::new();
+        hsh.input(CONSTRUCTION);
+        hsh.result()
+    };
+    check(
+        ck[..] == decode(BLAKE2S_CONSTRUCTION)[..],
+        SelfTestError::Blake2s,
+    )?;
+
+    let code = {
+        use hmac::Mac;
+        let mut mac = Hmac::<Blake2s>::new_varkey(&ck[..]).unwrap();
+        mac.input(IDENTIFIER);
+        mac.result().code()
+    };
+    check(
+        code[..] == decode(HMAC_BLAKE2S_IDENTIFIER)[..],
+        SelfTestError::HmacBlake2s,
+    )
+}
+
+// the transport AEAD (as implemented by ring)
+fn transport(cipher: Cipher, expected: &str) -> Result<(), SelfTestError> {
+    let nonce = cipher.nonce(AEAD_COUNTER);
+    let key = LessSafeKey::new(UnboundKey::new(cipher.algorithm(), &AEAD_KEY).unwrap());
+
+    let mut msg = AEAD_PLAINTEXT.to_vec();
+    key.seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::empty(), &mut msg)
+        .map_err(|_| SelfTestError::Aead(cipher))?;
+    check(msg == decode(expected), SelfTestError::Aead(cipher))?;
+
+    let pt = key
+        .open_in_place(Nonce::assume_unique_for_key(nonce), Aad::empty(), &mut msg)
+        .map_err(|_| SelfTestError::Aead(cipher))?;
+    check(pt == AEAD_PLAINTEXT, SelfTestError::Aead(cipher))
+}
+
+// the handshake AEAD (as implemented by the chacha20poly1305 crate)
+fn handshake_aead() -> Result<(), SelfTestError> {
+    let ad = decode(BLAKE2S_CONSTRUCTION);
+    let aead = ChaCha20Poly1305::new(*GenericArray::from_slice(&AEAD_KEY));
+    let ct = aead
+        .encrypt(
+            &[0u8; 12].into(),
+            Payload {
+                msg: AEAD_PLAINTEXT,
+                aad: &ad[..],
+            },
+        )
+        .map_err(|_| SelfTestError::Aead(Cipher::ChaChaPoly))?;
+    check(
+        ct == decode(AEAD_HANDSHAKE),
+        SelfTestError::Aead(Cipher::ChaChaPoly),
+    )?;
+
+    let pt = aead
+        .decrypt(
+            &[0u8; 12].into(),
+            Payload {
+                msg: &ct[..],
+                aad: &ad[..],
+            },
+        )
+        .map_err(|_| SelfTestError::Aead(Cipher::ChaChaPoly))?;
+    check(
+        pt == AEAD_PLAINTEXT,
+        SelfTestError::Aead(Cipher::ChaChaPoly),
+    )
+}
+
+// a complete handshake between two devices
+fn handshake(cipher: Cipher, expected: &str) -> Result<(), SelfTestError> {
+    let err = || SelfTestError::Handshake(cipher);
+
+    let sk1 = StaticSecret::from(decode32(X25519_SK_A));
+    let sk2 = StaticSecret::from(decode32(X25519_SK_B));
+    let pk1 = PublicKey::from(&sk1);
+    let pk2 = PublicKey::from(&sk2);
+
+    let mut dev1: handshake::Device<()> = handshake::Device::new();
+    let mut dev2: handshake::Device<()> = handshake::Device::new();
+
+    dev1.set_sk(Some(sk1));
+    dev2.set_sk(Some(sk2));
+    dev1.add(pk2, ()).map_err(|_| err())?;
+    dev2.add(pk1, ()).map_err(|_| err())?;
+    dev1.set_construction(cipher.construction())
+        .map_err(|_| err())?;
+    dev2.set_construction(cipher.construction())
+        .map_err(|_| err())?;
+
+    let mut rng1 = FixedRng(0x11);
+    let mut rng2 = FixedRng(0x22);
+
+    let msg1 = dev1.begin(&mut rng1, &pk2).map_err(|_| err())?;
+    let (_, msg2, ks_r) = dev2.process(&mut rng2, &msg1, None).map_err(|_| err())?;
+    let (_, _, ks_i) = dev1
+        .process(&mut rng1, &msg2.ok_or_else(err)?, None)
+        .map_err(|_| err())?;
+
+    let expected = decode(expected);
+    match (ks_i, ks_r) {
+        (Some(ks_i), Some(ks_r)) => check(
+            ks_i.send.key[..] == expected[..32]
+                && ks_i.recv.key[..] == expected[32..]
+                && ks_i.send.id == rng2.next_u32()
+                && ks_i.recv.id == rng1.next_u32()
+                && ks_i.initiator
+                && !ks_r.initiator
+                && ks_i.cipher == cipher
+                && ks_r.cipher == cipher
+                && ks_i.send.id == ks_r.recv.id
+                && ks_i.recv.id == ks_r.send.id
+                && ks_i.send.key == ks_r.recv.key
+                && ks_i.recv.key == ks_r.send.key,
+            err(),
+        ),
+        _ => Err(err()),
+    }
+}
+
+/// Run the self-test of the cryptographic stack
+///
+/// # Returns
+///
+/// The first failed test (if any)
+pub fn crypto_self_test() -> Result<(), SelfTestError> {
+    x25519()?;
+    blake2s()?;
+    transport(Cipher::ChaChaPoly, AEAD_CHACHAPOLY)?;
+    transport(Cipher::AESGCM, AEAD_AESGCM)?;
+    handshake_aead()?;
+    handshake(Cipher::ChaChaPoly, HANDSHAKE_CHACHAPOLY)?;
+    handshake(Cipher::AESGCM, HANDSHAKE_AESGCM)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crypto_self_test() {
+        if let Err(e) = crypto_self_test() {
+            panic!("{}", e);
+        }
+    }
+
+    #[test]
+    fn test_transport_mismatch() {
+        // the known answer of one cipher is rejected for the other
+        assert!(transport(Cipher::ChaChaPoly, AEAD_AESGCM).is_err());
+        assert!(transport(Cipher::AESGCM, AEAD_CHACHAPOLY).is_err());
+    }
+
+    #[test]
+    fn test_handshake_mismatch() {
+        // the known answer of one construction is rejected for the other
+        assert!(handshake(Cipher::ChaChaPoly, HANDSHAKE_AESGCM).is_err());
+        assert!(handshake(Cipher::AESGCM, HANDSHAKE_CHACHAPOLY).is_err());
+    }
+
+    #[test]
+    fn test_nonce_layout() {
+        // the counter is little-endian for ChaChaPoly and big-endian for AESGCM
+        assert_eq!(
+            Cipher::ChaChaPoly.nonce(AEAD_COUNTER),
+            [0, 0, 0, 0, 0x05, 0x04, 0x03, 0x02, 0x01, 0, 0, 0]
+        );
+        assert_eq!(
+            Cipher::AESGCM.nonce(AEAD_COUNTER),
+            [0, 0, 0, 0, 0, 0, 0, 0x01, 0x02, 0x03, 0x04, 0x05]
+        );
+    }
+}
@@ -1,9 +1,21 @@
+use super::constants::*;
 use super::dummy;
-use super::wireguard::WireGuard;
+use super::dummy_keypair;
+use super::peer::Peer;
+use super::router::message_data_len;
+use super::types::{PeerConfig, PskProvider};
+use super::udp::Writer;
+use super::wireguard::{WireGuard, SESSIONS_VERSION, SIZE_SESSION};
 
 use std::convert::TryInto;
-use std::net::IpAddr;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::mpsc::sync_channel;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 
+use byteorder::{ByteOrder, LittleEndian};
 use hex;
 use rand_chacha::ChaCha8Rng;
 use rand_core::{RngCore, SeedableRng};
@@ -59,6 +71,94 @@ fn init() {
     let _ = env_logger::builder().is_test(true).try_init();
 }
 
+/* Two instances of WireGuard connected over a pair bind,
+ * each configured with the other as a peer:
+ * the first routes 192.168.2.0/24 to the second, the second routes 192.168.1.0/24 to the first.
+ *
+ * No endpoint is configured, tests decide which instance knows the other.
+ */
+struct ConnectedPair {
+    fake1: dummy::TunFakeIO,
+    fake2: dummy::TunFakeIO,
+    wg1: WireGuard<dummy::TunTest, dummy::PairBind>,
+    wg2: WireGuard<dummy::TunTest, dummy::PairBind>,
+    pk1: PublicKey,
+    pk2: PublicKey,
+    peer1: Peer<dummy::TunTest, dummy::PairBind>, // the first instance (as a peer of the second)
+    peer2: Peer<dummy::TunTest, dummy::PairBind>, // the second instance (as a peer of the first)
+}
+
+type PairBinds = (
+    (
+        dummy::PairReader<dummy::UnitEndpoint>,
+        dummy::PairWriter<dummy::UnitEndpoint>,
+    ),
+    (
+        dummy::PairReader<dummy::UnitEndpoint>,
+        dummy::PairWriter<dummy::UnitEndpoint>,
+    ),
+);
+
+fn connected_pair() -> ConnectedPair {
+    connected_pair_with(dummy::PairBind::pair())
+}
+
+/* As connected_pair, but over the provided pair bind (e.g. an impaired one)
+ */
+fn connected_pair_with(binds: PairBinds) -> ConnectedPair {
+    let (fake1, tun_reader1, tun_writer1, _) = dummy::TunTest::create(true);
+    let wg1: WireGuard<dummy::TunTest, dummy::PairBind> = WireGuard::new(tun_writer1);
+    wg1.add_tun_reader(tun_reader1);
+    wg1.up(1500);
+
+    let (fake2, tun_reader2, tun_writer2, _) = dummy::TunTest::create(true);
+    let wg2: WireGuard<dummy::TunTest, dummy::PairBind> = WireGuard::new(tun_writer2);
+    wg2.add_tun_reader(tun_reader2);
+    wg2.up(1500);
+
+    let ((bind_reader1, bind_writer1), (bind_reader2, bind_writer2)) = binds;
+
+    wg1.set_writer(bind_writer1);
+    wg2.set_writer(bind_writer2);
+
+    wg1.add_udp_reader(bind_reader1);
+    wg2.add_udp_reader(bind_reader2);
+
+    let sk1 = StaticSecret::from([0x1; 32]);
+    let sk2 = StaticSecret::from([0x2; 32]);
+
+    let pk1 = PublicKey::from(&sk1);
+    let pk2 = PublicKey::from(&sk2);
+
+    wg1.add_peer(pk2);
+    wg2.add_peer(pk1);
+
+    wg1.set_key(Some(sk1));
+    wg2.set_key(Some(sk2));
+
+    let peer2 = wg1.lookup_peer(&pk2).unwrap();
+    let peer1 = wg2.lookup_peer(&pk1).unwrap();
+
+    peer1
+        .router
+        .add_allowed_ip("192.168.1.0".parse().unwrap(), 24);
+
+    peer2
+        .router
+        .add_allowed_ip("192.168.2.0".parse().unwrap(), 24);
+
+    ConnectedPair {
+        fake1,
+        fake2,
+        wg1,
+        wg2,
+        pk1,
+        pk2,
+        peer1,
+        peer2,
+    }
+}
+
 /* Create and configure two matching pure instances of WireGuard
  */
 #[test]
@@ -190,4 +290,1175 @@ fn test_pure_wireguard() {
             );
         }
     }
+    // the handshake was initiated by the first instance
+
+    assert_eq!(peer2.router.session_initiator(), Some(true));
+    assert_eq!(peer1.router.session_initiator(), Some(false));
+
+    // one handshake and the transport messages in each direction
+    // (the sent counters are updated after transmission, allow the workers to finish)
+
+    thread::sleep(Duration::from_millis(100));
+    let transport = num_packets as u64;
+    assert_eq!(
+        wg1.message_counters(),
+        ([0, 1, 0, transport], [1, 0, 0, transport])
+    );
+    assert_eq!(
+        wg2.message_counters(),
+        ([1, 0, 0, transport], [0, 1, 0, transport])
+    );
+}
+
+/* Connect two instances over a link dropping half of all messages:
+ * lost initiations and responses should be retransmitted until the handshake completes.
+ */
+#[test]
+fn test_lossy_handshake() {
+    init();
+
+    let conditions = dummy::Impairment {
+        loss: 0.5,
+        ..Default::default()
+    };
+    let ConnectedPair {
+        fake1,
+        fake2: _fake2,
+        wg1,
+        wg2,
+        pk1: _pk1,
+        pk2: _pk2,
+        peer1: _peer1,
+        peer2,
+    } = connected_pair_with(dummy::PairBind::impaired_pair(conditions, 5));
+
+    peer2.router.set_endpoint(dummy::UnitEndpoint::new());
+
+    // a packet for the peer causes the handshake
+
+    fake1.write(make_packet(
+        100,                             // size
+        "192.168.1.20".parse().unwrap(), // src
+        "192.168.2.10".parse().unwrap(), // dst
+        0,                               // prng seed
+    ));
+
+    // wait for the handshake to complete (within the rekey attempt time)
+
+    let start = Instant::now();
+    while peer2.router.session_initiator() != Some(true) {
+        assert!(
+            start.elapsed() < REKEY_ATTEMPT_TIME,
+            "handshake did not complete over lossy link"
+        );
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    // no more initiations were received than were sent
+
+    let (received, sent) = wg1.message_counters();
+    assert!(received[1] >= 1);
+    assert!(wg2.message_counters().0[0] <= sent[0]);
+}
+
+/* Register observers of the decrypted traffic:
+ * observers receive copies of the packets written to the TUN device,
+ * while slow observers miss packets rather than stall forwarding.
+ */
+#[test]
+fn test_observer() {
+    init();
+
+    let ConnectedPair {
+        fake1,
+        fake2,
+        wg1: _wg1,
+        wg2,
+        pk1: _pk1,
+        pk2: _pk2,
+        peer1: _peer1,
+        peer2,
+    } = connected_pair();
+
+    peer2.router.set_endpoint(dummy::UnitEndpoint::new());
+
+    // a fast observer, a slow observer (never read) and a disconnected one
+
+    let num_packets = 20;
+    let (fast_tx, fast_rx) = sync_channel(num_packets);
+    let (slow_tx, slow_rx) = sync_channel(1);
+    let (gone_tx, gone_rx) = sync_channel(1);
+    wg2.add_observer(fast_tx);
+    wg2.add_observer(slow_tx);
+    wg2.add_observer(gone_tx);
+    drop(gone_rx);
+
+    let packets: Vec<Vec<u8>> = (0..num_packets)
+        .map(|id| {
+            make_packet(
+                50 + 50 * id,                    // size
+                "192.168.1.20".parse().unwrap(), // src
+                "192.168.2.10".parse().unwrap(), // dst
+                id as u64,                       // prng seed
+            )
+        })
+        .collect();
+
+    // forwarding to the TUN device is unaffected by the slow observer
+
+    for p in packets.iter() {
+        fake1.write(p.clone());
+        assert_eq!(hex::encode(fake2.read()), hex::encode(p));
+    }
+
+    // the fast observer saw every packet, the slow observer only the first
+
+    for p in packets.iter() {
+        assert_eq!(
+            hex::encode(fast_rx.recv_timeout(Duration::from_secs(1)).unwrap()),
+            hex::encode(p)
+        );
+    }
+    assert_eq!(
+        hex::encode(slow_rx.try_recv().unwrap()),
+        hex::encode(&packets[0])
+    );
+    assert!(slow_rx.try_recv().is_err());
+}
+
+/* Restrict a peer to carrying TCP:
+ * UDP packets are dropped, both when received from and when sent to the peer.
+ */
+#[test]
+fn test_allowed_protocols() {
+    init();
+
+    let ConnectedPair {
+        fake1,
+        fake2,
+        wg1,
+        wg2,
+        pk1: _pk1,
+        pk2: _pk2,
+        peer1,
+        peer2,
+    } = connected_pair();
+
+    peer2.router.set_endpoint(dummy::UnitEndpoint::new());
+
+    const TCP: u8 = 6;
+    const UDP: u8 = 17;
+
+    let packet = |protocol: u8, id: u64| {
+        let mut packet = make_packet(
+            100,                             // size
+            "192.168.1.20".parse().unwrap(), // src
+            "192.168.2.10".parse().unwrap(), // dst
+            id,                              // prng seed
+        );
+        packet[9] = protocol;
+        packet
+    };
+
+    // every protocol is carried by default (establishing a session)
+
+    let udp = packet(UDP, 0);
+    fake1.write(udp.clone());
+    assert_eq!(hex::encode(fake2.read()), hex::encode(udp));
+
+    // the receiver drops UDP from a TCP-only peer
+
+    peer1.router.set_allowed_protocols(Some(vec![TCP]));
+    let rx_packets = peer1.rx_packets.load(Ordering::Relaxed);
+
+    let udp = packet(UDP, 1);
+    let tcp = packet(TCP, 2);
+    fake1.write(udp);
+    fake1.write(tcp.clone());
+    assert_eq!(
+        hex::encode(fake2.read()),
+        hex::encode(tcp),
+        "UDP packet was not dropped by the receiver"
+    );
+
+    // the dropped packet is not counted
+    assert!(
+        wait_until(|| peer1.rx_packets.load(Ordering::Relaxed) == rx_packets + 1),
+        "dropped packet was counted"
+    );
+
+    // the sender drops UDP to a TCP-only peer (it is never transmitted)
+
+    // every transport message sent by the first instance has been received by the second
+    let settled = || wg1.message_counters().1[3] == wg2.message_counters().0[3];
+
+    peer2.router.set_allowed_protocols(Some(vec![TCP]));
+    assert!(wait_until(settled));
+    let transport = wg1.message_counters().1[3];
+
+    let tcp = packet(TCP, 4);
+    fake1.write(packet(UDP, 3));
+    fake1.write(tcp.clone());
+    assert_eq!(hex::encode(fake2.read()), hex::encode(tcp));
+    assert!(wait_until(settled));
+    assert_eq!(wg1.message_counters().1[3], transport + 1);
+
+    // lifting the restriction carries UDP again
+
+    peer1.router.set_allowed_protocols(None);
+    peer2.router.set_allowed_protocols(None);
+
+    let udp = packet(UDP, 5);
+    fake1.write(udp.clone());
+    assert_eq!(hex::encode(fake2.read()), hex::encode(udp));
+}
+
+/* Exchange data and keepalive messages:
+ * every transport message is counted as a packet,
+ * while keepalives add no payload to the byte counters.
+ */
+#[test]
+fn test_packet_counters() {
+    init();
+
+    let ConnectedPair {
+        fake1,
+        fake2,
+        wg1: _wg1,
+        wg2: _wg2,
+        pk1: _pk1,
+        pk2: _pk2,
+        peer1,
+        peer2,
+    } = connected_pair();
+
+    peer2.router.set_endpoint(dummy::UnitEndpoint::new());
+
+    // IP packets of 64, 128 and 256 bytes (multiples of the padding)
+
+    let packet = |size: usize, id: u64| {
+        make_packet(
+            size - 20,                       // size of payload
+            "192.168.1.20".parse().unwrap(), // src
+            "192.168.2.10".parse().unwrap(), // dst
+            id,                              // prng seed
+        )
+    };
+
+    // establish a session
+
+    let p = packet(64, 0);
+    fake1.write(p.clone());
+    assert_eq!(hex::encode(fake2.read()), hex::encode(p));
+    thread::sleep(Duration::from_millis(100));
+
+    let rx = || {
+        (
+            peer1.rx_packets.load(Ordering::Relaxed),
+            peer1.rx_bytes.load(Ordering::Relaxed),
+        )
+    };
+    let tx = || {
+        (
+            peer2.tx_packets.load(Ordering::Relaxed),
+            peer2.tx_bytes.load(Ordering::Relaxed),
+        )
+    };
+    let (rx_packets, rx_bytes) = rx();
+    let (tx_packets, tx_bytes) = tx();
+    assert_eq!(rx_packets, 1);
+    assert_eq!(tx_packets, 1);
+
+    // send data and a keepalive
+
+    for (id, size) in [64, 128, 256].iter().enumerate() {
+        let p = packet(*size, id as u64 + 1);
+        fake1.write(p.clone());
+        assert_eq!(hex::encode(fake2.read()), hex::encode(p));
+    }
+    peer2.router.send_keepalive();
+
+    let start = Instant::now();
+    while rx().0 < rx_packets + 4 {
+        assert!(start.elapsed() < Duration::from_secs(5), "keepalive lost");
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    // the sent counters are updated after transmission, allow the workers to finish
+    thread::sleep(Duration::from_millis(100));
+
+    let expected = (message_data_len(64)
+        + message_data_len(128)
+        + message_data_len(256)
+        + message_data_len(0)) as u64;
+    assert_eq!(rx(), (rx_packets + 4, rx_bytes + expected));
+    assert_eq!(tx(), (tx_packets + 4, tx_bytes + expected));
+}
+
+/* Check that the interval between handshake initiations backs off
+ * while the peer is unreachable and is reset once a handshake completes.
+ */
+#[test]
+fn test_handshake_backoff() {
+    init();
+
+    let (_fake, _reader, tun_writer, _) = dummy::TunTest::create(false);
+    let wg: WireGuard<dummy::TunTest, dummy::PairBind> = WireGuard::new(tun_writer);
+
+    let pk = PublicKey::from(&StaticSecret::from([0x1; 32]));
+    wg.add_peer(pk);
+    let peer = wg.lookup_peer(&pk).unwrap();
+
+    // retransmissions during the first REKEY_ATTEMPT_TIME use the fixed interval
+
+    for _ in 0..MAX_TIMER_HANDSHAKES {
+        assert_eq!(peer.handshake_initiation_interval(), REKEY_TIMEOUT);
+        assert!(!peer.handshake_failed());
+        peer.sent_handshake_initiation();
+    }
+    assert_eq!(peer.handshake_initiation_interval(), REKEY_TIMEOUT);
+    assert!(!peer.handshake_failed());
+
+    // during a prolonged outage the interval grows (up to the maximum)
+
+    let mut last = REKEY_TIMEOUT;
+    for _ in 0..16 {
+        peer.sent_handshake_initiation();
+        let interval = peer.handshake_initiation_interval();
+        assert!(interval >= last, "initiation interval decreased");
+        assert!(
+            interval <= MAX_REKEY_BACKOFF,
+            "initiation interval above maximum"
+        );
+        last = interval;
+    }
+    assert_eq!(last, MAX_REKEY_BACKOFF);
+    assert!(peer.handshake_failed());
+
+    // the interval is reset once a handshake completes
+
+    peer.timers_handshake_complete();
+    assert_eq!(peer.handshake_initiation_interval(), REKEY_TIMEOUT);
+    assert!(!peer.handshake_failed());
+}
+
+/* Complete handshakes in both directions while the psk provider changes:
+ * every handshake uses the latest psk of the provider (on both ends).
+ */
+#[test]
+fn test_psk_provider() {
+    // the latest psk of an out-of-band exchange shared by both ends
+    struct SharedProvider(Arc<AtomicU8>);
+
+    impl PskProvider for SharedProvider {
+        fn psk(&self, _pk: &PublicKey) -> Option<[u8; 32]> {
+            Some([self.0.load(Ordering::SeqCst); 32])
+        }
+    }
+
+    init();
+
+    let ConnectedPair {
+        fake1,
+        fake2,
+        wg1,
+        wg2,
+        pk1,
+        pk2,
+        peer1,
+        peer2,
+    } = connected_pair();
+    peer1.router.set_endpoint(dummy::UnitEndpoint::new());
+    peer2.router.set_endpoint(dummy::UnitEndpoint::new());
+
+    let latest = Arc::new(AtomicU8::new(0));
+    wg1.set_psk_provider(Some(Arc::new(SharedProvider(latest.clone()))));
+    wg2.set_psk_provider(Some(Arc::new(SharedProvider(latest.clone()))));
+
+    // the first instance initiates the first handshake, the second instance the next
+
+    let rounds = vec![
+        (&fake1, &fake2, &peer2, "192.168.1.20", "192.168.2.10"),
+        (&fake2, &fake1, &peer1, "192.168.2.10", "192.168.1.20"),
+    ];
+    for (round, (sender, receiver, initiator, src, dst)) in rounds.into_iter().enumerate() {
+        let psk = [round as u8 + 1; 32];
+        latest.store(psk[0], Ordering::SeqCst);
+
+        // configure distinct stale psks and clear the sessions:
+        // the handshake can only complete if both ends use the latest psk
+
+        wg1.set_psk(pk2, [0xaa; 32]);
+        wg2.set_psk(pk1, [0xbb; 32]);
+        peer1.router.zero_keys();
+        peer2.router.zero_keys();
+
+        let packet = make_packet(
+            100,                  // size
+            src.parse().unwrap(), // src
+            dst.parse().unwrap(), // dst
+            round as u64,         // prng seed
+        );
+        sender.write(packet.clone());
+        assert_eq!(
+            hex::encode(receiver.read()),
+            hex::encode(packet),
+            "handshake did not complete using the latest psk"
+        );
+        assert_eq!(initiator.router.session_initiator(), Some(true));
+
+        assert_eq!(wg1.get_psk(&pk2), Some(psk));
+        assert_eq!(wg2.get_psk(&pk1), Some(psk));
+    }
+}
+
+/* Export a session from one instance and import it into another:
+ * the importing instance should continue sending with the next nonce,
+ * and retains the unconfirmed key-pair of the exporting instance.
+ */
+#[test]
+fn test_session_migration() {
+    init();
+
+    let pk = PublicKey::from(&StaticSecret::from([0x2; 32]));
+
+    let (_fake1, _reader1, tun_writer1, _) = dummy::TunTest::create(false);
+    let wg1: WireGuard<dummy::TunTest, dummy::PairBind> = WireGuard::new(tun_writer1);
+    wg1.add_peer(pk);
+    let peer1 = wg1.lookup_peer(&pk).unwrap();
+
+    // establish a session and send 3 messages (nonces 0, 1, 2)
+    peer1.router.add_keypair(dummy_keypair(true));
+    peer1.router.send_keepalive();
+    peer1.router.send_keepalive();
+    assert!(peer1.router.has_session());
+
+    // complete a handshake as responder (awaiting confirmation)
+    peer1.router.add_keypair(dummy_keypair(false));
+
+    // the exporting instance stops using the session
+    let sessions = wg1.serialize_sessions();
+    assert_eq!(sessions.len(), 1 + 2 * SIZE_SESSION);
+    assert_eq!(sessions[0], SESSIONS_VERSION);
+    assert!(!peer1.router.has_session());
+    assert_eq!(wg1.serialize_sessions().len(), 1);
+
+    // sessions of an unsupported format are refused
+    let (_fake2, _reader2, tun_writer2, _) = dummy::TunTest::create(false);
+    let wg2: WireGuard<dummy::TunTest, dummy::PairBind> = WireGuard::new(tun_writer2);
+    wg2.add_peer(pk);
+    let peer2 = wg2.lookup_peer(&pk).unwrap();
+
+    let mut unsupported = sessions.to_vec();
+    unsupported[0] = SESSIONS_VERSION + 1;
+    assert_eq!(wg2.import_sessions(&unsupported[..]), 0);
+    assert!(!peer2.router.has_session());
+
+    // import into a fresh instance
+    assert_eq!(wg2.import_sessions(&sessions[..]), 1);
+    assert!(peer2.router.has_session());
+
+    // importing the session again is refused
+    assert_eq!(wg2.import_sessions(&sessions[..]), 0);
+
+    // continue sending (nonces 3, 4)
+    peer2.router.send_keepalive();
+    peer2.router.send_keepalive();
+
+    // a session previously exported by the instance is refused
+    // (the receiver ids remain allocated, since nonces would be reused)
+    let resumed = wg2.serialize_sessions();
+    assert_eq!(resumed.len(), 1 + 2 * SIZE_SESSION);
+    assert!(!peer2.router.has_session());
+    assert_eq!(wg2.import_sessions(&resumed[..]), 0);
+
+    // hand the session on to another instance
+    let (_fake3, _reader3, tun_writer3, _) = dummy::TunTest::create(false);
+    let wg3: WireGuard<dummy::TunTest, dummy::PairBind> = WireGuard::new(tun_writer3);
+    wg3.add_peer(pk);
+    let peer3 = wg3.lookup_peer(&pk).unwrap();
+    assert_eq!(wg3.import_sessions(&resumed[..]), 1);
+
+    let session = peer3.router.export_session().unwrap();
+    let current = session.current.unwrap();
+    assert_eq!(current.keypair.send, dummy_keypair(true).send);
+    assert_eq!(current.keypair.recv, dummy_keypair(true).recv);
+    assert_eq!(current.nonce, 5);
+
+    let next = session.next.unwrap();
+    assert_eq!(next.keypair.send, dummy_keypair(false).send);
+    assert_eq!(next.keypair.recv, dummy_keypair(false).recv);
+    assert_eq!(next.counter, None);
+    assert!(session.previous.is_none());
+
+    // the first message (nonce 0) on the imported next key-pair confirms it
+    let (_fake4, _reader4, tun_writer4, _) = dummy::TunTest::create(false);
+    let wg4: WireGuard<dummy::TunTest, dummy::PairBind> = WireGuard::new(tun_writer4);
+    wg4.up(1500);
+    wg4.add_peer(pk);
+    let peer4 = wg4.lookup_peer(&pk).unwrap();
+    assert_eq!(wg4.import_sessions(&sessions[..]), 1);
+    assert_eq!(peer4.router.session_initiator(), Some(true));
+
+    let (_fake5, _reader5, tun_writer5, _) = dummy::TunTest::create(false);
+    let remote: WireGuard<dummy::TunTest, dummy::PairBind> = WireGuard::new(tun_writer5);
+    remote.up(1500);
+    remote.add_peer(pk);
+
+    let ((bind_reader4, _bind_writer4), (_bind_reader5, bind_writer5)) = dummy::PairBind::pair();
+    wg4.add_udp_reader(bind_reader4);
+    remote.set_writer(bind_writer5);
+
+    // the remote end uses the key-pair as its current (sending to the next key-pair of wg4)
+    let peer5 = remote.lookup_peer(&pk).unwrap();
+    peer5.router.set_endpoint(dummy::UnitEndpoint::new());
+    peer5.router.add_keypair(dummy_keypair(true));
+    peer5.router.send_keepalive();
+
+    assert!(wait_until(
+        || peer4.router.session_initiator() == Some(false)
+    ));
+    assert_eq!(peer4.router.replays(), 0);
+}
+
+/* The randomness owned by the device is drawn from the rng of the device,
+ * hence identically seeded devices produce identical randomness.
+ */
+#[test]
+fn test_seeded_rng() {
+    init();
+
+    let pk = PublicKey::from(&StaticSecret::from([0x2; 32]));
+
+    let (_fake1, _reader1, tun_writer1, _) = dummy::TunTest::create(false);
+    let wg1: WireGuard<dummy::TunTest, dummy::PairBind> = WireGuard::new(tun_writer1);
+    let (_fake2, _reader2, tun_writer2, _) = dummy::TunTest::create(false);
+    let wg2: WireGuard<dummy::TunTest, dummy::PairBind> = WireGuard::new(tun_writer2);
+
+    wg1.seed_rng(0x5eed);
+    wg2.seed_rng(0x5eed);
+
+    wg1.add_peer(pk);
+    wg2.add_peer(pk);
+    assert_eq!(
+        wg1.lookup_peer(&pk).unwrap().id,
+        wg2.lookup_peer(&pk).unwrap().id
+    );
+    assert_eq!(wg1.derive_rng().next_u64(), wg2.derive_rng().next_u64());
+
+    // differently seeded devices diverge
+    wg2.seed_rng(0x5eee);
+    assert_ne!(wg1.derive_rng().next_u64(), wg2.derive_rng().next_u64());
+}
+
+/* Set the endpoint of a peer without an endpoint:
+ * a handshake with the peer is initiated at the new endpoint.
+ */
+#[test]
+fn test_set_endpoint() {
+    init();
+
+    let ConnectedPair {
+        fake1: _fake1,
+        fake2: _fake2,
+        wg1,
+        wg2,
+        pk1: _pk1,
+        pk2,
+        peer1: _peer1,
+        peer2,
+    } = connected_pair();
+    assert_eq!(peer2.router.get_endpoint(), None);
+
+    // set the endpoint (without initiating)
+
+    let addr: SocketAddr = "127.0.0.1:8080".parse().unwrap();
+    assert!(wg1.set_endpoint(&pk2, addr, false));
+    assert_eq!(peer2.router.get_endpoint(), Some(addr));
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(wg1.message_counters().1[0], 0);
+
+    // set the endpoint and initiate a handshake
+
+    assert!(wg1.set_endpoint(&pk2, addr, true));
+    let start = Instant::now();
+    while peer2.router.session_initiator() != Some(true) {
+        assert!(
+            start.elapsed() < Duration::from_secs(5),
+            "handshake was not initiated"
+        );
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(wg1.message_counters().1[0], 1);
+    assert_eq!(wg2.message_counters().0[0], 1);
+
+    // unknown peers are rejected
+
+    let other = PublicKey::from(&StaticSecret::from([0x3; 32]));
+    assert!(!wg1.set_endpoint(&other, addr, true));
+}
+
+/* Feed malformed and unsolicited messages to an instance:
+ * they should be dropped without affecting the peer state
+ * and the instance should continue to process valid messages.
+ */
+#[test]
+fn test_malformed_packets() {
+    init();
+
+    let binds = dummy::PairBind::pair();
+    // writes messages to the first instance as if sent by the second
+    let forger = (binds.1).1.clone();
+    let ConnectedPair {
+        fake1: _fake1,
+        fake2: _fake2,
+        wg1,
+        wg2,
+        pk1,
+        pk2: _pk2,
+        peer1,
+        peer2,
+    } = connected_pair_with(binds);
+
+    let keypair = dummy_keypair(true);
+    let session = keypair.local_id();
+    peer2.router.add_keypair(keypair);
+
+    let message = |ty: u32, receiver: u32, len: usize| {
+        let mut msg = vec![0xaau8; len];
+        LittleEndian::write_u32(&mut msg[0..4], ty);
+        if len >= 8 {
+            LittleEndian::write_u32(&mut msg[4..8], receiver);
+        }
+        msg
+    };
+
+    let garbage = vec![
+        vec![0x4, 0x0],              // shorter than the message type
+        message(4, session, 15),     // truncated transport message
+        message(4, 0xdeadbeef, 32),  // unknown receiver index
+        message(4, session, 32),     // invalid authentication tag
+        message(2, 0xdeadbeef, 92),  // response to no initiation
+        message(3, 0xdeadbeef, 64),  // cookie reply to no initiation
+        message(1, 0xdeadbeef, 148), // initiation with an invalid mac1
+        message(9, 0xdeadbeef, 32),  // unknown message type
+    ];
+
+    for msg in garbage.iter() {
+        forger
+            .write(&msg[..], &mut dummy::UnitEndpoint::new())
+            .unwrap();
+    }
+    thread::sleep(Duration::from_millis(100));
+
+    // the peer state is unchanged
+
+    assert_eq!(peer2.rx_packets.load(Ordering::Relaxed), 0);
+    assert_eq!(peer2.rx_bytes.load(Ordering::Relaxed), 0);
+    assert_eq!(peer2.router.session_initiator(), Some(true));
+    assert_eq!(
+        peer2.router.get_endpoint(),
+        None,
+        "endpoint learned from forgery"
+    );
+    assert_eq!(wg1.message_counters().1, [0, 0, 0, 0]);
+
+    // the initiation and response were dropped due to their invalid mac1
+    assert_eq!(wg1.invalid_mac1_drops(), 2);
+
+    // the instance completes a handshake afterwards
+
+    let addr: SocketAddr = "127.0.0.1:8080".parse().unwrap();
+    assert!(wg2.set_endpoint(&pk1, addr, true));
+
+    let start = Instant::now();
+    while peer1.router.session_initiator() != Some(true) {
+        assert!(
+            start.elapsed() < Duration::from_secs(5),
+            "handshake was not completed"
+        );
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+/* Flood an instance with handshake messages while the handshake workers are stalled:
+ * the number of pending handshake messages remains bounded
+ * and the messages exceeding the bound are dropped (and counted).
+ */
+#[test]
+fn test_handshake_flood() {
+    init();
+
+    let (_fake, _tun_reader, tun_writer, _) = dummy::TunTest::create(false);
+    let wg: WireGuard<dummy::TunTest, dummy::PairBind> = WireGuard::new(tun_writer);
+    wg.set_key(Some(StaticSecret::from([0x1; 32])));
+    wg.up(1500);
+
+    let ((bind_reader1, _bind_writer1), (_bind_reader2, bind_writer2)) = dummy::PairBind::pair();
+    wg.add_udp_reader(bind_reader1);
+
+    // initiation with an invalid mac1 (rejected by the handshake workers)
+    let mut initiation = vec![0xaau8; 148];
+    LittleEndian::write_u32(&mut initiation[0..4], 1);
+
+    let flood = MAX_QUEUED_INCOMING_HANDSHAKES + 1000;
+    {
+        // stall the handshake workers
+        let _peers = wg.peers.write();
+        for _ in 0..flood {
+            bind_writer2
+                .write(&initiation[..], &mut dummy::UnitEndpoint::new())
+                .unwrap();
+        }
+        assert!(wait_until(|| wg.message_counters().0[0] == flood as u64));
+        assert!(wg.pending.load(Ordering::SeqCst) <= MAX_QUEUED_INCOMING_HANDSHAKES);
+        assert!(wg.handshake_queue_drops() > 0);
+    }
+
+    // every message was either processed or dropped
+    assert!(wait_until(|| wg.pending.load(Ordering::SeqCst) == 0));
+    assert!(wait_until(|| {
+        wg.invalid_mac1_drops() + wg.handshake_queue_drops() == flood as u64
+    }));
+}
+
+/* Check that the remaining lifetime of the current session
+ * decreases as the (simulated) clock advances.
+ */
+#[test]
+fn test_session_remaining() {
+    init();
+
+    let (_fake, _reader, tun_writer, _) = dummy::TunTest::create(false);
+    let wg: WireGuard<dummy::TunTest, dummy::PairBind> = WireGuard::new(tun_writer);
+
+    let pk = PublicKey::from(&StaticSecret::from([0x2; 32]));
+    wg.add_peer(pk);
+    let peer = wg.lookup_peer(&pk).unwrap();
+
+    // no current session
+    assert_eq!(wg.session_remaining(&pk), None);
+
+    let keypair = dummy_keypair(true);
+    let birth = keypair.birth;
+    peer.router.add_keypair(keypair);
+
+    let remaining = wg.session_remaining(&pk).unwrap();
+    assert!(remaining <= REJECT_AFTER_TIME);
+    assert!(remaining > REJECT_AFTER_TIME - Duration::from_secs(10));
+
+    // advance the clock
+    for secs in &[0, 1, 60, 179] {
+        let now = birth + Duration::from_secs(*secs);
+        assert_eq!(
+            peer.router.session_remaining(now),
+            Some(REJECT_AFTER_TIME - Duration::from_secs(*secs))
+        );
+        assert_eq!(
+            peer.router.session_age(now),
+            Some(Duration::from_secs(*secs))
+        );
+    }
+
+    // the session expired
+    assert_eq!(
+        peer.router.session_remaining(birth + REJECT_AFTER_TIME),
+        None
+    );
+    assert_eq!(peer.router.session_age(birth + REJECT_AFTER_TIME), None);
+
+    // unknown peer
+    let other = PublicKey::from(&StaticSecret::from([0x3; 32]));
+    assert_eq!(wg.session_remaining(&other), None);
+}
+
+/* Bring an interface down mid-session:
+ * the sessions should be cleared while the configuration persists
+ * and a new handshake should be established once brought back up.
+ */
+#[test]
+fn test_down_up() {
+    init();
+
+    // create and connect two WG instances
+
+    let ConnectedPair {
+        fake1,
+        fake2,
+        wg1,
+        wg2: _wg2,
+        pk1: _pk1,
+        pk2,
+        peer1: _peer1,
+        peer2,
+    } = connected_pair();
+
+    peer2.router.set_endpoint(dummy::UnitEndpoint::new());
+
+    let send_packet = |id| {
+        let packet = make_packet(
+            100,                             // size
+            "192.168.1.20".parse().unwrap(), // src
+            "192.168.2.10".parse().unwrap(), // dst
+            id,                              // prng seed
+        );
+        fake1.write(packet.clone());
+        assert_eq!(
+            hex::encode(fake2.read()),
+            hex::encode(packet),
+            "Failed to receive valid IPv4 packet unmodified"
+        );
+    };
+
+    // establish a session (the time of the handshake is recorded)
+
+    assert!(peer2.walltime_last_handshake.lock().is_none());
+    send_packet(0);
+    assert!(wg1.is_peer_connected(&pk2));
+    let first = (*peer2.walltime_last_handshake.lock()).expect("no handshake time recorded");
+
+    // bring the first instance down: sessions are cleared, configuration persists
+
+    wg1.down();
+    assert!(!wg1.is_peer_connected(&pk2));
+    assert_eq!(
+        peer2.router.list_allowed_ips(),
+        vec![("192.168.2.0".parse().unwrap(), 24)]
+    );
+    assert!(peer2.router.get_endpoint().is_some());
+
+    // bring the first instance up: a new handshake is established
+
+    wg1.up(1500);
+    send_packet(1);
+    assert!(wg1.is_peer_connected(&pk2));
+    assert!((*peer2.walltime_last_handshake.lock()).unwrap() >= first);
+}
+
+/* Check that the timers of a removed peer are cancelled:
+ * the timers (and their callbacks holding references to the peer) are replaced,
+ * hence the persistent keepalive of the removed peer can no longer fire.
+ */
+#[test]
+fn test_remove_peer_timers() {
+    init();
+
+    // create and connect two WG instances
+
+    let ConnectedPair {
+        fake1,
+        fake2,
+        wg1,
+        wg2,
+        pk1: _pk1,
+        pk2,
+        peer1: _peer1,
+        peer2,
+    } = connected_pair();
+
+    peer2.router.set_endpoint(dummy::UnitEndpoint::new());
+
+    // establish a session
+
+    let packet = make_packet(
+        100,                             // size
+        "192.168.1.20".parse().unwrap(), // src
+        "192.168.2.10".parse().unwrap(), // dst
+        0,                               // prng seed
+    );
+    fake1.write(packet.clone());
+    assert_eq!(hex::encode(fake2.read()), hex::encode(packet));
+
+    // the persistent keepalive of the peer fires immediately
+
+    let transport = || wg2.message_counters().0[3];
+    let before = transport();
+    peer2.set_persistent_keepalive_interval(1);
+    assert!(
+        wait_until(|| transport() > before),
+        "persistent keepalive did not fire"
+    );
+
+    // once removed, the timers of the peer are replaced by dummy timers
+    // (dropping the timer callbacks holding references to the peer)
+
+    wg1.remove_peer(&pk2);
+    assert!(wg1.lookup_peer(&pk2).is_none());
+    assert_eq!(
+        peer2.get_keepalive_interval(),
+        0,
+        "timers of removed peer not replaced"
+    );
+
+    // the persistent keepalive no longer fires
+    // (once any keepalive in flight at the time of removal has been delivered)
+
+    thread::sleep(Duration::from_millis(100));
+    let removed = transport();
+    thread::sleep(Duration::from_millis(1500));
+    assert_eq!(transport(), removed, "keepalive of removed peer fired");
+}
+
+// poll the condition (for up to a second)
+fn wait_until<F: Fn() -> bool>(cond: F) -> bool {
+    let deadline = Instant::now() + Duration::from_secs(1);
+    while !cond() {
+        if Instant::now() > deadline {
+            return false;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    true
+}
+
+/* Check that initiations from source IPs without a matching peer endpoint
+ * are dropped (before any cryptographic processing) when the filter is enabled.
+ */
+#[test]
+fn test_endpoint_filter() {
+    init();
+
+    let ConnectedPair {
+        fake1,
+        fake2: _fake2,
+        wg1,
+        wg2,
+        pk1: _pk1,
+        pk2,
+        peer1,
+        peer2,
+    } = connected_pair();
+    peer2.router.set_endpoint(dummy::UnitEndpoint::new());
+
+    // the second instance does not know the endpoint of its peer
+
+    let source: IpAddr = "127.0.0.1".parse().unwrap();
+    wg2.set_endpoint_filter(true);
+    assert!(!wg2.is_known_source(&source));
+
+    // the initiation is received, but dropped without a response
+
+    fake1.write(make_packet(
+        100,                             // size
+        "192.168.1.20".parse().unwrap(), // src
+        "192.168.2.10".parse().unwrap(), // dst
+        0,                               // prng seed
+    ));
+    assert!(
+        wait_until(|| wg2.message_counters().0[0] == 1),
+        "initiation not received"
+    );
+
+    // (the initiation is dropped by the reader, before it is queued)
+    let (_, tx) = wg2.message_counters();
+    assert_eq!(wg2.pending.load(Ordering::SeqCst), 0);
+    assert_eq!(tx[1], 0, "responded to initiation from unknown source");
+    assert!(!wg1.is_peer_connected(&pk2));
+
+    // the source is accepted once it is the endpoint of a peer
+
+    peer1.router.set_endpoint(dummy::UnitEndpoint::new());
+    assert!(wg2.is_known_source(&source));
+}
+
+/* Check that applying a configuration replaces the set of peers,
+ * while unchanged peers retain their sessions.
+ */
+#[test]
+fn test_apply_config() {
+    init();
+
+    // create and connect two WG instances
+
+    let ConnectedPair {
+        fake1,
+        fake2,
+        wg1,
+        wg2: _wg2,
+        pk1: _pk1,
+        pk2,
+        peer1: _peer1,
+        peer2,
+    } = connected_pair();
+    let pk3 = PublicKey::from(&StaticSecret::from([0x3; 32]));
+    let pk4 = PublicKey::from(&StaticSecret::from([0x4; 32]));
+    wg1.add_peer(pk3);
+
+    peer2.router.set_endpoint(dummy::UnitEndpoint::new());
+
+    let send_packet = |id| {
+        let packet = make_packet(
+            100,                             // size
+            "192.168.1.20".parse().unwrap(), // src
+            "192.168.2.10".parse().unwrap(), // dst
+            id,                              // prng seed
+        );
+        fake1.write(packet.clone());
+        assert_eq!(
+            hex::encode(fake2.read()),
+            hex::encode(packet),
+            "Failed to receive valid IPv4 packet unmodified"
+        );
+    };
+
+    // establish a session
+
+    send_packet(0);
+    assert!(wg1.is_peer_connected(&pk2));
+
+    // keep the second peer, remove the third and add the fourth
+
+    let config = |psk| {
+        vec![
+            PeerConfig {
+                public_key: pk2,
+                preshared_key: psk,
+                endpoint: None,
+                persistent_keepalive_interval: 0,
+                allowed_ips: vec![("192.168.2.0".parse().unwrap(), 24)],
+            },
+            PeerConfig {
+                public_key: pk4,
+                preshared_key: [0u8; 32],
+                endpoint: Some("127.0.0.1:51820".parse().unwrap()),
+                persistent_keepalive_interval: 0,
+                allowed_ips: vec![("10.0.0.0".parse().unwrap(), 8)],
+            },
+        ]
+    };
+
+    wg1.apply_config(&config([0u8; 32]));
+
+    assert!(wg1.lookup_peer(&pk3).is_none(), "removed peer not purged");
+    let peer4 = wg1.lookup_peer(&pk4).expect("new peer not added");
+    assert_eq!(
+        peer4.router.list_allowed_ips(),
+        vec![("10.0.0.0".parse().unwrap(), 8)]
+    );
+    assert!(peer4.router.get_endpoint().is_some());
+
+    // the unchanged peer retains its session
+
+    assert!(
+        wg1.is_peer_connected(&pk2),
+        "unchanged peer lost its session"
+    );
+    send_packet(1);
+
+    // a change of psk invalidates the session
+
+    wg1.apply_config(&config([0x5; 32]));
+    assert!(!wg1.is_peer_connected(&pk2));
+    assert_eq!(wg1.get_psk(&pk2), Some([0x5; 32]));
+}
+
+/* Check that samples of the byte counters accumulate,
+ * retaining only the most recent STATS_HISTORY_LENGTH samples.
+ */
+#[test]
+fn test_stats_history() {
+    init();
+
+    let (_fake, _reader, tun_writer, _) = dummy::TunTest::create(false);
+    let wg: WireGuard<dummy::TunTest, dummy::PairBind> = WireGuard::new(tun_writer);
+
+    let pk = PublicKey::from(&StaticSecret::from([0x1; 32]));
+    wg.add_peer(pk);
+    let peer = wg.lookup_peer(&pk).unwrap();
+    assert!(wg.history(&pk).is_empty());
+
+    // sample at a simulated clock (one sample per second)
+
+    let start = Instant::now();
+    let total = STATS_HISTORY_LENGTH + 10;
+    for n in 0..total {
+        peer.rx_bytes.store(n as u64, Ordering::Relaxed);
+        peer.tx_bytes.store(2 * n as u64, Ordering::Relaxed);
+        peer.sample_history(start + Duration::from_secs(n as u64));
+
+        let history = wg.history(&pk);
+        assert_eq!(history.len(), std::cmp::min(n + 1, STATS_HISTORY_LENGTH));
+    }
+
+    // the oldest samples rolled over
+
+    let history = wg.history(&pk);
+    for (i, (time, rx, tx)) in history.into_iter().enumerate() {
+        let n = total - STATS_HISTORY_LENGTH + i;
+        assert_eq!(time, start + Duration::from_secs(n as u64));
+        assert_eq!(rx, n as u64);
+        assert_eq!(tx, 2 * n as u64);
+    }
+
+    // when enabled, the samples are taken by a timer
+
+    let pk = PublicKey::from(&StaticSecret::from([0x2; 32]));
+    wg.add_peer(pk);
+    wg.up(1500);
+    wg.set_stats_history(true);
+    thread::sleep(Duration::from_millis(1500));
+    let samples = wg.history(&pk).len();
+    assert!(samples >= 1 && samples <= 3, "{} samples", samples);
+}
+
+/* A "server side" configuration: only the client knows the endpoint of the server.
+ *
+ * Packets sent by the server before the endpoint of the client is known are staged
+ * and transmitted once the client has initiated (and confirmed) a session.
+ */
+#[test]
+fn test_unknown_endpoint() {
+    init();
+
+    let ConnectedPair {
+        fake1,
+        fake2,
+        wg1: _wg1,
+        wg2,
+        pk1,
+        pk2: _pk2,
+        peer1,
+        peer2,
+    } = connected_pair();
+
+    // only the client (first instance) knows the endpoint of the server
+
+    peer2.router.set_endpoint(dummy::UnitEndpoint::new());
+
+    // the server sends first: the packet is staged (no endpoint to handshake with)
+
+    let reply = make_packet(
+        100,                             // size
+        "192.168.2.10".parse().unwrap(), // src
+        "192.168.1.20".parse().unwrap(), // dst
+        1,                               // prng seed
+    );
+    fake2.write(reply.clone());
+    thread::sleep(Duration::from_millis(50));
+    assert_eq!(peer1.router.get_endpoint(), None);
+    assert!(!wg2.is_peer_connected(&pk1));
+
+    // the client initiates a session
+
+    let request = make_packet(
+        100,                             // size
+        "192.168.1.20".parse().unwrap(), // src
+        "192.168.2.10".parse().unwrap(), // dst
+        0,                               // prng seed
+    );
+    fake1.write(request.clone());
+    assert_eq!(
+        hex::encode(fake2.read()),
+        hex::encode(request),
+        "Failed to receive packet from client"
+    );
+
+    // the server learned the endpoint and transmitted the staged packet
+
+    assert!(peer1.router.get_endpoint().is_some());
+    assert_eq!(
+        hex::encode(fake1.read()),
+        hex::encode(reply),
+        "Failed to receive staged packet from server"
+    );
 }
@@ -1,14 +1,16 @@
 use std::marker::PhantomData;
+use std::mem;
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant, SystemTime};
 
 use hjul::{Runner, Timer};
 use log::debug;
+use rand::Rng;
 
 use super::constants::*;
 use super::peer::{Peer, PeerInner};
-use super::router::{message_data_len, Callbacks};
+use super::router::{message_data_len, Callbacks, TYPE_TRANSPORT};
 use super::tun::Tun;
 use super::types::KeyPair;
 use super::udp::UDP;
@@ -27,6 +29,13 @@ pub struct Timers {
     send_persistent_keepalive: Timer,
     zero_key_material: Timer,
     new_handshake: Timer,
+    sample_history: Timer,
+}
+
+// REKEY_TIMEOUT with a random jitter (up to MAX_REKEY_TIMEOUT_JITTER)
+fn rekey_timeout<R: Rng>(rng: &mut R) -> Duration {
+    let jitter = MAX_REKEY_TIMEOUT_JITTER.as_millis() as u64;
+    REKEY_TIMEOUT + Duration::from_millis(rng.gen_range(0, jitter + 1))
 }
 
 impl Timers {
@@ -57,6 +66,7 @@ impl<T: Tun, B: UDP> PeerInner<T, B> {
         timers.send_persistent_keepalive.stop();
         timers.zero_key_material.stop();
         timers.new_handshake.stop();
+        timers.sample_history.stop();
 
         // reset all timer state
         timers.handshake_attempts.store(0, Ordering::SeqCst);
@@ -66,6 +76,19 @@ impl<T: Tun, B: UDP> PeerInner<T, B> {
         timers.need_another_keepalive.store(false, Ordering::SeqCst);
     }
 
+    /* Cancel the timers of a peer removed from the device.
+     *
+     * The timers are stopped and replaced by dummy timers,
+     * which drops the timer callbacks holding references to the peer:
+     * ensuring that no timer of a removed peer fires on stale state.
+     */
+    pub fn cancel_timers(&self) {
+        let dummy = Timers::dummy(&*self.wg.runner.lock());
+        self.stop_timers();
+        let old = mem::replace(&mut *self.timers_mut(), dummy);
+        drop(old);
+    }
+
     pub fn start_timers(&self) {
         // take a write lock preventing simultaneous "stop_timers" call
         let mut timers = self.timers_mut();
@@ -82,6 +105,19 @@ impl<T: Tun, B: UDP> PeerInner<T, B> {
                 .send_persistent_keepalive
                 .start(Duration::from_secs(0));
         }
+
+        // start sample_history
+        if self.wg.stats_history.load(Ordering::Relaxed) {
+            timers.sample_history.start(Duration::from_secs(0));
+        }
+    }
+
+    /* Should be called after enabling the stats history (or adding a peer). */
+    pub fn timers_stats_history(&self) {
+        let timers = self.timers();
+        if timers.enabled && self.wg.stats_history.load(Ordering::Relaxed) {
+            timers.sample_history.start(Duration::from_secs(0));
+        }
     }
 
     /* should be called after an authenticated data packet is sent */
@@ -134,7 +170,9 @@ impl<T: Tun, B: UDP> PeerInner<T, B> {
         let timers = self.timers();
         if timers.enabled {
             timers.send_keepalive.stop();
-            timers.retransmit_handshake.reset(REKEY_TIMEOUT);
+            timers
+                .retransmit_handshake
+                .reset(rekey_timeout(&mut *self.wg.rng.lock()));
         }
     }
 
@@ -143,6 +181,7 @@ impl<T: Tun, B: UDP> PeerInner<T, B> {
      */
     pub fn timers_handshake_complete(&self) {
         log::trace!("timers_handshake_complete");
+        self.handshake_initiations.store(0, Ordering::SeqCst);
         let timers = self.timers();
         if timers.enabled {
             timers.retransmit_handshake.stop();
@@ -183,7 +222,9 @@ impl<T: Tun, B: UDP> PeerInner<T, B> {
         log::trace!("timers_set_retransmit_handshake");
         let timers = self.timers();
         if timers.enabled {
-            timers.retransmit_handshake.reset(REKEY_TIMEOUT);
+            timers
+                .retransmit_handshake
+                .reset(rekey_timeout(&mut *self.wg.rng.lock()));
         }
     }
 
@@ -191,6 +232,7 @@ impl<T: Tun, B: UDP> PeerInner<T, B> {
      */
     pub fn sent_handshake_initiation(&self) {
         *self.last_handshake_sent.lock() = Instant::now();
+        self.handshake_initiations.fetch_add(1, Ordering::SeqCst);
         self.timers_handshake_initiated();
         self.timers_set_retransmit_handshake();
         self.timers_any_authenticated_packet_traversal();
@@ -269,7 +311,9 @@ impl Timers {
                             REKEY_TIMEOUT.as_secs(),
                             attempts
                         );
-                        timers.retransmit_handshake.reset(REKEY_TIMEOUT);
+                        timers
+                            .retransmit_handshake
+                            .reset(rekey_timeout(&mut *peer.wg.rng.lock()));
                         peer.router.clear_src();
                         peer.packet_send_queued_handshake_initiation(true);
                     }
@@ -312,6 +356,17 @@ impl Timers {
                     peer.router.zero_keys();
                 })
             },
+            sample_history: {
+                let peer = peer.clone();
+                runner.timer(move || {
+                    log::trace!("{} : timer fired (sample_history)", peer);
+                    let timers = peer.timers();
+                    if timers.enabled && peer.wg.stats_history.load(Ordering::Relaxed) {
+                        peer.sample_history(Instant::now());
+                        timers.sample_history.start(STATS_HISTORY_INTERVAL);
+                    }
+                })
+            },
             send_persistent_keepalive: {
                 let peer = peer.clone();
                 runner.timer(move || {
@@ -342,6 +397,7 @@ impl Timers {
             send_keepalive: runner.timer(|| {}),
             send_persistent_keepalive: runner.timer(|| {}),
             zero_key_material: runner.timer(|| {}),
+            sample_history: runner.timer(|| {}),
         }
     }
 }
@@ -364,6 +420,10 @@ impl<T: Tun, B: UDP> Callbacks for Events<T, B> {
         peer.timers_any_authenticated_packet_traversal();
         peer.timers_any_authenticated_packet_sent();
         peer.tx_bytes.fetch_add(size as u64, Ordering::Relaxed);
+        peer.tx_packets.fetch_add(1, Ordering::Relaxed);
+        if sent {
+            peer.wg.tx_messages.count(TYPE_TRANSPORT);
+        }
         if size > message_data_len(0) && sent {
             peer.timers_data_sent();
         }
@@ -396,6 +456,7 @@ impl<T: Tun, B: UDP> Callbacks for Events<T, B> {
         peer.timers_any_authenticated_packet_traversal();
         peer.timers_any_authenticated_packet_received();
         peer.rx_bytes.fetch_add(size as u64, Ordering::Relaxed);
+        peer.rx_packets.fetch_add(1, Ordering::Relaxed);
         if size > 0 && sent {
             peer.timers_data_received();
         }
@@ -1,7 +1,16 @@
 use clear_on_drop::clear::Clear;
 use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::Instant;
 
+use ring::aead::{Algorithm, AES_256_GCM, CHACHA20_POLY1305};
+use subtle::ConstantTimeEq;
+use x25519_dalek::PublicKey;
+
+use super::constants::REJECT_AFTER_TIME;
+
 #[cfg(test)]
 pub fn dummy_keypair(initiator: bool) -> KeyPair {
     let k1 = Key {
@@ -16,6 +25,7 @@ pub fn dummy_keypair(initiator: bool) -> KeyPair {
         KeyPair {
             birth: Instant::now(),
             initiator: true,
+            cipher: Cipher::ChaChaPoly,
             send: k1,
             recv: k2,
         }
@@ -23,6 +33,7 @@ pub fn dummy_keypair(initiator: bool) -> KeyPair {
         KeyPair {
             birth: Instant::now(),
             initiator: false,
+            cipher: Cipher::ChaChaPoly,
             send: k2,
             recv: k1,
         }
@@ -42,10 +53,40 @@ impl Drop for Key {
     }
 }
 
-#[cfg(test)]
+/* Serialized sessions (see WireGuard::serialize_sessions):
+ * the buffer holds raw key material and is zeroed on drop.
+ */
+pub struct Sessions(Vec<u8>);
+
+impl Sessions {
+    pub fn new(len: usize) -> Sessions {
+        Sessions(vec![0u8; len])
+    }
+}
+
+impl Deref for Sessions {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        &self.0[..]
+    }
+}
+
+impl DerefMut for Sessions {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.0[..]
+    }
+}
+
+impl Drop for Sessions {
+    fn drop(&mut self) {
+        Clear::clear(&mut self.0[..])
+    }
+}
+
+// compare the key material in constant time (the id is not secret)
 impl PartialEq for Key {
     fn eq(&self, other: &Self) -> bool {
-        self.id == other.id && self.key[..] == other.key[..]
+        self.id == other.id && self.key.ct_eq(&other.key).into()
     }
 }
 
@@ -55,10 +96,89 @@ impl fmt::Debug for Key {
     }
 }
 
+/* The AEAD of the Noise construction,
+ * used for both the handshake and the transport messages.
+ */
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Cipher {
+    ChaChaPoly = 0,
+    AESGCM = 1,
+}
+
+// allowlist of supported Noise constructions
+const CONSTRUCTIONS: [(&str, Cipher); 2] = [
+    ("Noise_IKpsk2_25519_ChaChaPoly_BLAKE2s", Cipher::ChaChaPoly),
+    ("Noise_IKpsk2_25519_AESGCM_BLAKE2s", Cipher::AESGCM),
+];
+
+impl Cipher {
+    /// Lookup the cipher of a Noise construction,
+    /// returns None if the construction is not supported.
+    pub fn from_construction(name: &str) -> Option<Cipher> {
+        CONSTRUCTIONS
+            .iter()
+            .find(|(construction, _)| *construction == name)
+            .map(|(_, cipher)| *cipher)
+    }
+
+    /// The name of the Noise construction using the cipher
+    pub fn construction(&self) -> &'static str {
+        CONSTRUCTIONS
+            .iter()
+            .find(|(_, cipher)| cipher == self)
+            .map(|(construction, _)| *construction)
+            .unwrap()
+    }
+
+    /// The AEAD algorithm (as implemented by ring)
+    pub fn algorithm(&self) -> &'static Algorithm {
+        match self {
+            Cipher::ChaChaPoly => &CHACHA20_POLY1305,
+            Cipher::AESGCM => &AES_256_GCM,
+        }
+    }
+
+    /// The AEAD nonce of a transport message:
+    /// 32 bits of zeros followed by the counter,
+    /// which Noise encodes little-endian for ChaChaPoly and big-endian for AESGCM.
+    pub fn nonce(&self, counter: u64) -> [u8; 12] {
+        let mut nonce = [0u8; 12];
+        match self {
+            Cipher::ChaChaPoly => nonce[4..].copy_from_slice(&counter.to_le_bytes()),
+            Cipher::AESGCM => nonce[4..].copy_from_slice(&counter.to_be_bytes()),
+        }
+        nonce
+    }
+}
+
+impl Default for Cipher {
+    fn default() -> Self {
+        Cipher::ChaChaPoly
+    }
+}
+
+/* The strategy used to allocate the (receiver) indexes of handshakes and sessions.
+ *
+ * Random indexes are the default and do not leak information to on-path observers,
+ * sequential indexes are predictable which eases debugging and testing.
+ */
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum IndexStrategy {
+    Random,
+    Sequential,
+}
+
+impl Default for IndexStrategy {
+    fn default() -> Self {
+        IndexStrategy::Random
+    }
+}
+
 #[derive(Clone)]
 pub struct KeyPair {
     pub birth: Instant,  // when was the key-pair created
     pub initiator: bool, // has the key-pair been confirmed?
+    pub cipher: Cipher,  // AEAD used for transport messages
     pub send: Key,       // key for outbound messages
     pub recv: Key,       // key for inbound messages
 }
@@ -80,4 +200,72 @@ impl KeyPair {
     pub fn local_id(&self) -> u32 {
         self.recv.id
     }
+
+    /// A key-pair older than REJECT_AFTER_TIME must not be used
+    /// for either encryption or decryption.
+    pub fn is_expired(&self) -> bool {
+        self.is_expired_at(Instant::now())
+    }
+
+    /// Has the key-pair expired at the given instant
+    pub fn is_expired_at(&self, now: Instant) -> bool {
+        now >= self.birth + REJECT_AFTER_TIME
+    }
+}
+
+/* Describes the configuration of a peer,
+ * as applied to the device by WireGuard::apply_config.
+ */
+#[derive(Clone)]
+pub struct PeerConfig {
+    pub public_key: PublicKey,
+    pub preshared_key: [u8; 32],
+    pub endpoint: Option<SocketAddr>, // None retains any current endpoint
+    pub persistent_keepalive_interval: u64,
+    pub allowed_ips: Vec<(IpAddr, u32)>,
+}
+
+/* A source of preshared keys established out-of-band,
+ * e.g. by a (periodically refreshed) post-quantum KEM exchange.
+ *
+ * The provider is polled before every initiation and response created
+ * and the returned psk replaces the configured psk of the peer.
+ */
+pub trait PskProvider: Send + Sync {
+    /// Return the latest psk for the peer,
+    /// or None to retain the currently configured psk.
+    fn psk(&self, pk: &PublicKey) -> Option<[u8; 32]>;
+}
+
+/* Counts the number of messages of each type:
+ * initiation, response, cookie reply and transport (indexed by type - 1)
+ */
+pub struct MessageCounters([AtomicU64; 4]);
+
+impl MessageCounters {
+    pub fn new() -> Self {
+        MessageCounters([
+            AtomicU64::new(0),
+            AtomicU64::new(0),
+            AtomicU64::new(0),
+            AtomicU64::new(0),
+        ])
+    }
+
+    /// Count a message of the type (unknown types are ignored)
+    pub fn count(&self, ty: u32) {
+        if let Some(counter) = (ty as usize).checked_sub(1).and_then(|i| self.0.get(i)) {
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Return the number of messages of each type
+    pub fn get(&self) -> [u64; 4] {
+        [
+            self.0[0].load(Ordering::Relaxed),
+            self.0[1].load(Ordering::Relaxed),
+            self.0[2].load(Ordering::Relaxed),
+            self.0[3].load(Ordering::Relaxed),
+        ]
+    }
 }
@@ -3,31 +3,55 @@ use super::handshake;
 use super::peer::{Peer, PeerInner};
 use super::router;
 use super::timers::{Events, Timers};
+use super::types::{
+    Cipher, IndexStrategy, Key, KeyPair, MessageCounters, PeerConfig, PskProvider, Sessions,
+};
 
 use super::queue::ParallelQueue;
 use super::workers::HandshakeJob;
 
 use super::tun::Tun;
 use super::udp::UDP;
+use super::Endpoint;
 
 use super::workers::{handshake_worker, tun_worker, udp_worker};
 
+use std::convert::TryInto;
 use std::fmt;
+use std::net::{IpAddr, SocketAddr};
 use std::ops::Deref;
 use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::SyncSender;
 use std::sync::Arc;
 use std::sync::Condvar;
 use std::sync::Mutex as StdMutex;
 use std::thread;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
+use arraydeque::ArrayDeque;
+use byteorder::{ByteOrder, LittleEndian};
 use hjul::Runner;
-use rand::rngs::OsRng;
-use rand::Rng;
+use rand::rngs::{OsRng, StdRng};
+use rand::{Rng, SeedableRng};
 use spin::{Mutex, RwLock};
+use subtle::ConstantTimeEq;
 
 use x25519_dalek::{PublicKey, StaticSecret};
 
+/* Version of the format of serialized sessions (the first byte) */
+pub const SESSIONS_VERSION: u8 = 2;
+
+/* Size of a serialized key-pair:
+ * public key, slot, send key/id, recv key/id, initiator flag, age (ms),
+ * nonce, receive counter, cipher and whether any message was received.
+ */
+pub const SIZE_SESSION: usize = 32 + 1 + (32 + 4) * 2 + 1 + 3 * 8 + 1 + 1;
+
+// slot of a serialized key-pair in the key-wheel
+const SLOT_PREVIOUS: u8 = 0;
+const SLOT_CURRENT: u8 = 1;
+const SLOT_NEXT: u8 = 2;
+
 pub struct WireguardInner<T: Tun, B: UDP> {
     // identifier (for logging)
     pub id: u32,
@@ -47,6 +71,16 @@ pub struct WireguardInner<T: Tun, B: UDP> {
     // peer map
     pub peers: RwLock<handshake::Device<Peer<T, B>>>,
 
+    // sample the byte counters of every peer (see STATS_HISTORY_INTERVAL)
+    pub stats_history: AtomicBool,
+
+    // only accept initiations from the source IPs of peer endpoints
+    pub endpoint_filter: AtomicBool,
+
+    // source of the randomness owned by the crate
+    // (ephemeral keys, receiver ids, cookie secrets and timer jitter)
+    pub rng: Mutex<StdRng>,
+
     // cryptokey router
     pub router: router::Device<B::Endpoint, Events<T, B>, T::Writer, B::Writer>,
 
@@ -54,6 +88,16 @@ pub struct WireguardInner<T: Tun, B: UDP> {
     pub last_under_load: Mutex<Instant>,
     pub pending: AtomicUsize, // number of pending handshake packets in queue
     pub queue: ParallelQueue<HandshakeJob<B::Endpoint>>,
+
+    // number of messages of each type received and sent
+    pub rx_messages: MessageCounters,
+    pub tx_messages: MessageCounters,
+
+    // number of handshake messages dropped due to an invalid mac1
+    pub invalid_mac1: AtomicU64,
+
+    // number of handshake messages dropped due to a full handshake queue
+    pub handshake_queue_full: AtomicU64,
 }
 
 pub struct WireGuard<T: Tun, B: UDP> {
@@ -135,10 +179,14 @@ impl<T: Tun, B: UDP> WireGuard<T, B> {
         self.router.down();
 
         // set all peers down (stops timers)
-        for (_, peer) in self.peers.write().iter() {
+        let peers = self.peers.write();
+        for (_, peer) in peers.iter() {
             peer.down();
         }
 
+        // abort pending handshakes
+        peers.reset_handshakes();
+
         *enabled = false;
     }
 
@@ -168,17 +216,212 @@ impl<T: Tun, B: UDP> WireGuard<T, B> {
     }
 
     pub fn clear_peers(&self) {
+        let peers = self.list_peers();
         self.peers.write().clear();
+        for peer in peers {
+            peer.cancel_timers();
+        }
     }
 
     pub fn remove_peer(&self, pk: &PublicKey) {
-        let _ = self.peers.write().remove(pk);
+        let peer = self.peers.write().remove(pk);
+        if let Ok(peer) = peer {
+            peer.cancel_timers();
+        }
+    }
+
+    /// Replace the set of peers by the configuration (similar to "wg syncconf")
+    ///
+    /// Peers absent from the configuration are removed (cancelling their timers),
+    /// new peers are added and the attributes of existing peers are updated.
+    /// Existing peers retain their sessions, unless their preshared key changed.
+    ///
+    /// The configuration is applied atomically:
+    /// the peer map is write-locked for the entire update,
+    /// hence handshakes never observe a partially applied configuration.
+    pub fn apply_config(&self, config: &[PeerConfig]) {
+        let removed = {
+            let mut peers = self.peers.write();
+
+            // remove peers absent from the configuration
+            let absent: Vec<PublicKey> = peers
+                .iter()
+                .map(|(pk, _)| pk)
+                .filter(|pk| {
+                    !config
+                        .iter()
+                        .any(|p| p.public_key.as_bytes() == pk.as_bytes())
+                })
+                .collect();
+            let removed: Vec<Peer<T, B>> = absent
+                .iter()
+                .filter_map(|pk| peers.remove(pk).ok())
+                .collect();
+
+            for p in config {
+                // add new peers (noop for existing peers)
+                self.insert_peer(&mut peers, p.public_key);
+                let peer = match peers.get(&p.public_key) {
+                    Some(peer) => peer.clone(),
+                    None => continue, // e.g. the public key matches the device
+                };
+
+                // a change of psk invalidates the current sessions
+                let same_psk = peers
+                    .get_psk(&p.public_key)
+                    .map(|psk| psk.ct_eq(&p.preshared_key).into())
+                    .unwrap_or(false);
+                if !same_psk {
+                    let _ = peers.set_psk(p.public_key, p.preshared_key);
+                    peer.router.zero_keys();
+                }
+
+                if let Some(endpoint) = p.endpoint {
+                    if peer.router.get_endpoint() != Some(endpoint) {
+                        peer.router
+                            .set_endpoint(B::Endpoint::from_address(endpoint));
+                    }
+                }
+
+                if peer.get_keepalive_interval() != p.persistent_keepalive_interval {
+                    peer.set_persistent_keepalive_interval(p.persistent_keepalive_interval);
+                }
+
+                let mut current = peer.router.list_allowed_ips();
+                let mut ips = p.allowed_ips.clone();
+                current.sort();
+                ips.sort();
+                if current != ips {
+                    peer.router.set_allowed_ips(&ips[..]);
+                }
+            }
+            removed
+        };
+
+        // cancel the timers of removed peers (outside the peer map lock)
+        for peer in removed {
+            peer.cancel_timers();
+        }
     }
 
     pub fn lookup_peer(&self, pk: &PublicKey) -> Option<Peer<T, B>> {
         self.peers.read().get(pk).map(|p| p.clone())
     }
 
+    /// Check if the tunnel to a peer is "up"
+    ///
+    /// # Arguments
+    ///
+    /// - `pk`: The public key of the peer
+    ///
+    /// # Returns
+    ///
+    /// True if the peer exists and has a confirmed, non-expired session
+    /// which can be used for sending right now.
+    pub fn is_peer_connected(&self, pk: &PublicKey) -> bool {
+        self.lookup_peer(pk)
+            .map(|peer| peer.router.has_session())
+            .unwrap_or(false)
+    }
+
+    /// Set the endpoint of the peer (e.g. learned out-of-band)
+    ///
+    /// # Arguments
+    ///
+    /// - `pk`: The public key of the peer
+    /// - `addr`: The new endpoint
+    /// - `initiate`: Initiate a handshake with the new endpoint if the peer has no session
+    ///
+    /// # Returns
+    ///
+    /// False if the peer does not exist
+    pub fn set_endpoint(&self, pk: &PublicKey, addr: SocketAddr, initiate: bool) -> bool {
+        match self.lookup_peer(pk) {
+            None => false,
+            Some(peer) => {
+                log::debug!("{} : endpoint set to {}", peer, addr);
+                peer.router.set_endpoint(B::Endpoint::from_address(addr));
+                if initiate && !peer.router.has_session() {
+                    peer.packet_send_handshake_initiation();
+                }
+                true
+            }
+        }
+    }
+
+    /// Pace the transmission of transport messages to the peer
+    /// (see router::PeerHandle::set_pacing)
+    ///
+    /// # Arguments
+    ///
+    /// - `pk`: The public key of the peer
+    /// - `rate`: The maximum number of messages per second, None (or zero) disables pacing
+    ///
+    /// # Returns
+    ///
+    /// False if the peer does not exist
+    pub fn set_pacing(&self, pk: &PublicKey, rate: Option<u32>) -> bool {
+        match self.lookup_peer(pk) {
+            None => false,
+            Some(peer) => {
+                peer.router.set_pacing(rate, &*self.runner.lock());
+                true
+            }
+        }
+    }
+
+    /// Return the time until the current session with the peer expires
+    ///
+    /// Allows applications to rekey (e.g. by sending traffic) ahead of expiry.
+    ///
+    /// # Returns
+    ///
+    /// None if the peer does not exist or has no current session.
+    pub fn session_remaining(&self, pk: &PublicKey) -> Option<Duration> {
+        self.lookup_peer(pk)
+            .and_then(|peer| peer.router.session_remaining(Instant::now()))
+    }
+
+    /// Only accept handshake initiations from the source IP of a configured peer endpoint
+    ///
+    /// Initiations from other sources are dropped before any cryptographic processing.
+    /// Intended for fixed-topology deployments where the endpoint of every peer is known:
+    /// while enabled, peers without an endpoint cannot initiate handshakes.
+    pub fn set_endpoint_filter(&self, enabled: bool) {
+        self.endpoint_filter.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Enable (or disable) sampling of the byte counters of every peer,
+    /// retaining a short history of recent samples (see history).
+    pub fn set_stats_history(&self, enabled: bool) {
+        self.stats_history.store(enabled, Ordering::Relaxed);
+        for peer in self.list_peers() {
+            peer.timers_stats_history();
+        }
+    }
+
+    /// Return the recent samples of the byte counters of the peer
+    ///
+    /// # Returns
+    ///
+    /// A list of (time, rx_bytes, tx_bytes) samples, oldest first
+    /// (empty if the peer does not exist)
+    pub fn history(&self, pk: &PublicKey) -> Vec<(Instant, u64, u64)> {
+        self.lookup_peer(pk)
+            .map(|peer| peer.history.lock().iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Check if the IP address belongs to the endpoint of any peer
+    pub fn is_known_source(&self, ip: &IpAddr) -> bool {
+        self.router.is_known_source(ip)
+    }
+
+    /// Return the number of encrypted transport messages awaiting transmission over UDP
+    pub fn egress_queue_depth(&self) -> usize {
+        self.router.egress_queue_depth()
+    }
+
     pub fn list_peers(&self) -> Vec<Peer<T, B>> {
         let peers = self.peers.read();
         let mut list = Vec::with_capacity(peers.len());
@@ -195,6 +438,53 @@ impl<T: Tun, B: UDP> WireGuard<T, B> {
         self.router.clear_sending_keys();
     }
 
+    /// Select the Noise construction (and hence the AEAD) for new handshakes
+    ///
+    /// Sessions established before the change retain their cipher.
+    ///
+    /// # Returns
+    ///
+    /// False if the construction is not supported
+    pub fn set_construction(&self, name: &str) -> bool {
+        self.peers.write().set_construction(name).is_ok()
+    }
+
+    /// Select the strategy used to allocate the indexes of new handshakes
+    /// (sequential indexes are predictable and intended for debugging)
+    pub fn set_index_strategy(&self, strategy: IndexStrategy) {
+        self.peers.write().set_index_strategy(strategy)
+    }
+
+    /// Respond to outbound packets without a cryptokey route
+    /// with an ICMP "destination unreachable" error
+    /// (see router::Device::set_icmp_unreachable)
+    pub fn set_icmp_unreachable(&self, enabled: bool) {
+        self.router.set_icmp_unreachable(enabled)
+    }
+
+    /// Register an observer receiving a copy of every decrypted packet
+    /// (see router::Device::add_observer)
+    pub fn add_observer(&self, sink: SyncSender<Vec<u8>>) {
+        self.router.add_observer(sink)
+    }
+
+    /// Set the size (in bits) of the replay window of new sessions
+    ///
+    /// # Returns
+    ///
+    /// False if the size is not a power of two between 128 and 65536
+    pub fn set_replay_window(&self, bitlen: usize) -> bool {
+        self.router.set_replay_window(bitlen)
+    }
+
+    pub fn get_construction(&self) -> &'static str {
+        self.peers.read().get_construction()
+    }
+
+    pub fn get_pk(&self) -> Option<PublicKey> {
+        self.peers.read().get_pk()
+    }
+
     pub fn get_sk(&self) -> Option<StaticSecret> {
         self.peers
             .read()
@@ -202,28 +492,272 @@ impl<T: Tun, B: UDP> WireGuard<T, B> {
             .map(|sk| StaticSecret::from(sk.to_bytes()))
     }
 
+    /// List the (receiver) ids currently allocated by the device
+    ///
+    /// # Returns
+    ///
+    /// A list of (id, public key of owning peer, key-wheel slot) triples sorted by id.
+    /// Ids which are not held by the key-wheel belong to handshakes in progress
+    /// or retired key-pairs and are reported with the slot "handshake".
+    #[cfg(feature = "debug_uapi")]
+    pub fn list_ids(&self) -> Vec<(u32, PublicKey, &'static str)> {
+        let peers = self.peers.read();
+        let mut ids = peers.list_ids();
+        ids.sort_by_key(|(id, _)| *id);
+        ids.into_iter()
+            .map(|(id, pk)| {
+                let slot = peers
+                    .get(&pk)
+                    .and_then(|peer| peer.router.key_slot(id))
+                    .unwrap_or("handshake");
+                (id, pk, slot)
+            })
+            .collect()
+    }
+
+    /// Return the number of messages of each type
+    /// (initiation, response, cookie reply and transport) received and sent by the device
+    ///
+    /// # Returns
+    ///
+    /// A pair of (received, sent) counters, indexed by message type - 1
+    pub fn message_counters(&self) -> ([u64; 4], [u64; 4]) {
+        (self.rx_messages.get(), self.tx_messages.get())
+    }
+
+    /// Return the number of handshake messages dropped due to an invalid mac1,
+    /// e.g. messages not intended for our public key
+    pub fn invalid_mac1_drops(&self) -> u64 {
+        self.invalid_mac1.load(Ordering::Relaxed)
+    }
+
+    /// Return the number of handshake messages dropped
+    /// since MAX_QUEUED_INCOMING_HANDSHAKES messages were already pending
+    pub fn handshake_queue_drops(&self) -> u64 {
+        self.handshake_queue_full.load(Ordering::Relaxed)
+    }
+
     pub fn set_psk(&self, pk: PublicKey, psk: [u8; 32]) -> bool {
         self.peers.write().set_psk(pk, psk).is_ok()
     }
     pub fn get_psk(&self, pk: &PublicKey) -> Option<[u8; 32]> {
         self.peers.read().get_psk(pk).ok()
     }
+    pub fn rotate_psk(&self, pk: PublicKey, psk: [u8; 32], window: Duration) -> bool {
+        self.peers.write().rotate_psk(pk, psk, window).is_ok()
+    }
+
+    /// Set (or unset) the source of preshared keys
+    /// (see handshake::Device::set_psk_provider)
+    pub fn set_psk_provider(&self, provider: Option<Arc<dyn PskProvider>>) {
+        self.peers.write().set_psk_provider(provider)
+    }
+
+    /// Export the sessions of every peer,
+    /// for hand-off to another instance (e.g. during an upgrade).
+    ///
+    /// The previous, current and next key-pair of every peer is exported
+    /// and removed from this instance,
+    /// which will not encrypt any further messages using the exported key-pairs
+    /// (ensuring the exported nonces are never reused).
+    ///
+    /// # Returns
+    ///
+    /// The serialized sessions: the format version (SESSIONS_VERSION),
+    /// followed by SIZE_SESSION bytes per key-pair. The buffer is zeroed on drop.
+    pub fn serialize_sessions(&self) -> Sessions {
+        let mut keypairs = vec![];
+        for peer in self.list_peers() {
+            if let Some(session) = peer.router.export_session() {
+                let slots = vec![
+                    (SLOT_PREVIOUS, session.previous),
+                    (SLOT_CURRENT, session.current),
+                    (SLOT_NEXT, session.next),
+                ];
+                for (slot, keypair) in slots {
+                    if let Some(keypair) = keypair {
+                        keypairs.push((peer.pk, slot, keypair));
+                    }
+                }
+            }
+        }
+
+        // allocated upfront: the key material is never left behind by a reallocation
+        let mut res = Sessions::new(1 + keypairs.len() * SIZE_SESSION);
+        res[0] = SESSIONS_VERSION;
+        let bufs = res[1..].chunks_mut(SIZE_SESSION);
+        for ((pk, slot, exported), buf) in keypairs.iter().zip(bufs) {
+            let keypair = &exported.keypair;
+            buf[0..32].copy_from_slice(pk.as_bytes());
+            buf[32] = *slot;
+            buf[33..65].copy_from_slice(&keypair.send.key[..]);
+            LittleEndian::write_u32(&mut buf[65..69], keypair.send.id);
+            buf[69..101].copy_from_slice(&keypair.recv.key[..]);
+            LittleEndian::write_u32(&mut buf[101..105], keypair.recv.id);
+            buf[105] = keypair.initiator as u8;
+            LittleEndian::write_u64(
+                &mut buf[106..114],
+                keypair.birth.elapsed().as_millis() as u64,
+            );
+            LittleEndian::write_u64(&mut buf[114..122], exported.nonce);
+            LittleEndian::write_u64(&mut buf[122..130], exported.counter.unwrap_or(0));
+            buf[130] = keypair.cipher as u8;
+            buf[131] = exported.counter.is_some() as u8;
+        }
+        res
+    }
+
+    /// Import sessions exported by another instance (see serialize_sessions)
+    ///
+    /// Sessions are skipped if the peer does not exist, already has key-pairs,
+    /// a receiver id is already allocated (e.g. a session previously exported by this instance)
+    /// or every key-pair of the session has expired.
+    ///
+    /// # Returns
+    ///
+    /// The number of sessions (peers) imported,
+    /// zero if the format version is not supported.
+    pub fn import_sessions(&self, sessions: &[u8]) -> usize {
+        if sessions.first() != Some(&SESSIONS_VERSION) {
+            log::debug!("{} : import sessions, unsupported version", self);
+            return 0;
+        }
+        if (sessions.len() - 1) % SIZE_SESSION != 0 {
+            log::debug!("{} : import sessions, invalid length", self);
+            return 0;
+        }
+
+        // group the key-pairs by peer (the key-pairs of a peer are consecutive)
+        let mut parsed: Vec<(PublicKey, router::ExportedSession)> = vec![];
+        for buf in sessions[1..].chunks(SIZE_SESSION) {
+            let pk: [u8; 32] = buf[0..32].try_into().unwrap();
+            let pk = PublicKey::from(pk);
+            let age = Duration::from_millis(LittleEndian::read_u64(&buf[106..114]));
+            let birth = match Instant::now().checked_sub(age) {
+                Some(birth) => birth,
+                None => continue,
+            };
+            let cipher = match buf[130] {
+                0 => Cipher::ChaChaPoly,
+                1 => Cipher::AESGCM,
+                _ => continue,
+            };
+            let keypair = router::ExportedKeyPair {
+                keypair: KeyPair {
+                    birth,
+                    initiator: buf[105] != 0,
+                    cipher,
+                    send: Key {
+                        key: buf[33..65].try_into().unwrap(),
+                        id: LittleEndian::read_u32(&buf[65..69]),
+                    },
+                    recv: Key {
+                        key: buf[69..101].try_into().unwrap(),
+                        id: LittleEndian::read_u32(&buf[101..105]),
+                    },
+                },
+                nonce: LittleEndian::read_u64(&buf[114..122]),
+                counter: match buf[131] {
+                    0 => None,
+                    _ => Some(LittleEndian::read_u64(&buf[122..130])),
+                },
+            };
+
+            if parsed
+                .last()
+                .map(|(last, _)| last.as_bytes() != pk.as_bytes())
+                .unwrap_or(true)
+            {
+                parsed.push((pk, Default::default()));
+            }
+            let session = &mut parsed.last_mut().unwrap().1;
+            match buf[32] {
+                SLOT_PREVIOUS => session.previous = Some(keypair),
+                SLOT_CURRENT => session.current = Some(keypair),
+                SLOT_NEXT => session.next = Some(keypair),
+                _ => continue,
+            }
+        }
+
+        let mut imported = 0;
+        for (pk, session) in parsed {
+            let peer = match self.lookup_peer(&pk) {
+                Some(peer) => peer,
+                None => continue,
+            };
+
+            // reserve the receiver ids in the handshake device
+            let peers = self.peers.read();
+            let ids: Vec<u32> = [&session.previous, &session.current, &session.next]
+                .iter()
+                .filter_map(|exported| exported.as_ref())
+                .map(|exported| exported.keypair.local_id())
+                .collect();
+            let mut reserved = Vec::with_capacity(ids.len());
+            for id in ids.iter() {
+                if !peers.reserve(&pk, *id) {
+                    log::debug!("{} : import sessions, id {} already allocated", self, id);
+                    break;
+                }
+                reserved.push(*id);
+            }
+            if reserved.len() < ids.len() {
+                for id in reserved {
+                    peers.release(id);
+                }
+                continue;
+            }
+
+            if peer.router.import_session(session) {
+                peer.timers_session_derived();
+                imported += 1;
+            } else {
+                log::debug!("{} : import sessions, session refused by {}", self, peer);
+                for id in reserved {
+                    peers.release(id);
+                }
+            }
+        }
+        imported
+    }
+
+    /// Derive an rng from the rng of the device,
+    /// without holding the lock on the rng of the device for the lifetime of the derived rng
+    /// (e.g. during handshake processing)
+    pub fn derive_rng(&self) -> StdRng {
+        StdRng::from_rng(&mut *self.rng.lock()).expect("seeding from the device rng cannot fail")
+    }
+
+    /// Replace the rng of the device by a seeded rng,
+    /// making the randomness owned by the device reproducible
+    #[cfg(test)]
+    pub fn seed_rng(&self, seed: u64) {
+        *self.rng.lock() = StdRng::seed_from_u64(seed);
+    }
 
     pub fn add_peer(&self, pk: PublicKey) -> bool {
-        let mut peers = self.peers.write();
+        self.insert_peer(&mut self.peers.write(), pk)
+    }
+
+    // add a peer to the (write-locked) peer map
+    fn insert_peer(&self, peers: &mut handshake::Device<Peer<T, B>>, pk: PublicKey) -> bool {
         if peers.contains_key(&pk) {
             return false;
         }
 
         let state = Arc::new(PeerInner {
-            id: OsRng.gen(),
+            id: self.rng.lock().gen(),
             pk,
             wg: self.clone(),
             walltime_last_handshake: Mutex::new(None),
             last_handshake_sent: Mutex::new(Instant::now() - TIME_HORIZON),
             handshake_queued: AtomicBool::new(false),
+            handshake_initiations: AtomicUsize::new(0),
             rx_bytes: AtomicU64::new(0),
             tx_bytes: AtomicU64::new(0),
+            rx_packets: AtomicU64::new(0),
+            tx_packets: AtomicU64::new(0),
+            history: Mutex::new(ArrayDeque::new()),
             timers: RwLock::new(Timers::dummy(&*self.runner.lock())),
         });
 
@@ -243,6 +777,7 @@ impl<T: Tun, B: UDP> WireGuard<T, B> {
          * TODO: Consider the ease of using atomic pointers instead.
          */
         *peer.timers.write() = Timers::new(&*self.runner.lock(), *enabled, peer.clone());
+        peer.timers_stats_history();
 
         // finally, add the peer to the wireguard device
         peers.add(pk, peer).is_ok()
@@ -286,7 +821,7 @@ impl<T: Tun, B: UDP> WireGuard<T, B> {
         let cpus = num_cpus::get();
 
         // create handshake queue
-        let (tx, mut rxs) = ParallelQueue::new(cpus, 128);
+        let (tx, mut rxs) = ParallelQueue::new(cpus, MAX_QUEUED_INCOMING_HANDSHAKES);
 
         // create arc to state
         let wg = WireGuard {
@@ -299,8 +834,15 @@ impl<T: Tun, B: UDP> WireGuard<T, B> {
                 router: router::Device::new(num_cpus::get(), writer),
                 pending: AtomicUsize::new(0),
                 peers: RwLock::new(handshake::Device::new()),
+                endpoint_filter: AtomicBool::new(false),
+                stats_history: AtomicBool::new(false),
                 runner: Mutex::new(Runner::new(TIMERS_TICK, TIMERS_SLOTS, TIMERS_CAPACITY)),
                 queue: tx,
+                rx_messages: MessageCounters::new(),
+                tx_messages: MessageCounters::new(),
+                invalid_mac1: AtomicU64::new(0),
+                handshake_queue_full: AtomicU64::new(0),
+                rng: Mutex::new(StdRng::from_entropy()),
             }),
         };
 
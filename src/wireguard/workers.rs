@@ -4,7 +4,6 @@ use std::time::Instant;
 use byteorder::{ByteOrder, LittleEndian};
 use crossbeam_channel::Receiver;
 use log::debug;
-use rand::rngs::OsRng;
 use x25519_dalek::PublicKey;
 
 // IO traits
@@ -21,6 +20,7 @@ use super::constants::{
     DURATION_UNDER_LOAD, MAX_QUEUED_INCOMING_HANDSHAKES, MESSAGE_PADDING_MULTIPLE,
     THRESHOLD_UNDER_LOAD,
 };
+use super::handshake::HandshakeError;
 use super::handshake::MAX_HANDSHAKE_MSG_SIZE;
 use super::handshake::{TYPE_COOKIE_REPLY, TYPE_INITIATION, TYPE_RESPONSE};
 use super::router::{CAPACITY_MESSAGE_POSTFIX, SIZE_MESSAGE_PREFIX, TYPE_TRANSPORT};
@@ -126,11 +126,31 @@ pub fn udp_worker<T: Tun, B: UDP>(wg: &WireGuard<T, B>, reader: B::Reader) {
         if msg.len() < std::mem::size_of::<u32>() {
             continue;
         }
-        match LittleEndian::read_u32(&msg[..]) {
+        let ty = LittleEndian::read_u32(&msg[..]);
+        wg.rx_messages.count(ty);
+        match ty {
+            TYPE_INITIATION
+                if wg.endpoint_filter.load(Ordering::Relaxed)
+                    && !wg.is_known_source(&src.into_address().ip()) =>
+            {
+                debug!("{} : reader, initiation from unknown source, dropping", wg);
+            }
             TYPE_COOKIE_REPLY | TYPE_INITIATION | TYPE_RESPONSE => {
                 debug!("{} : reader, received handshake message", wg);
+
+                // drop the message rather than blocking the reader if the queue is full
+                // (the number of pending handshake messages is bounded)
+                if wg.pending.load(Ordering::SeqCst) >= MAX_QUEUED_INCOMING_HANDSHAKES {
+                    wg.handshake_queue_full.fetch_add(1, Ordering::Relaxed);
+                    debug!("{} : reader, handshake queue full, dropping message", wg);
+                    continue;
+                }
                 wg.pending.fetch_add(1, Ordering::SeqCst);
-                wg.queue.send(HandshakeJob::Message(msg, src));
+                if !wg.queue.try_send(HandshakeJob::Message(msg, src)) {
+                    wg.pending.fetch_sub(1, Ordering::SeqCst);
+                    wg.handshake_queue_full.fetch_add(1, Ordering::Relaxed);
+                    debug!("{} : reader, handshake queue full, dropping message", wg);
+                }
             }
             TYPE_TRANSPORT => {
                 debug!("{} : reader, received transport message", wg);
@@ -181,7 +201,7 @@ pub fn handshake_worker<T: Tun, B: UDP>(
                 // process message
                 let device = wg.peers.read();
                 match device.process(
-                    &mut OsRng,
+                    &mut wg.derive_rng(),
                     &msg[..],
                     if under_load {
                         Some(src.into_address())
@@ -195,12 +215,13 @@ pub fn handshake_worker<T: Tun, B: UDP>(
                         if let Some(msg) = resp {
                             resp_len = msg.len() as u64;
                             // TODO: consider a more elegant solution for accessing the bind
-                            let _ = wg.router.send_raw(&msg[..], &mut src).map_err(|e| {
-                                debug!(
+                            match wg.router.send_raw(&msg[..], &mut src) {
+                                Ok(()) => wg.tx_messages.count(LittleEndian::read_u32(&msg[..])),
+                                Err(e) => debug!(
                                     "{} : handshake worker, failed to send response, error = {}",
                                     wg, e
-                                );
-                            });
+                                ),
+                            }
                         }
 
                         // update peer state
@@ -242,7 +263,12 @@ pub fn handshake_worker<T: Tun, B: UDP>(
                             });
                         }
                     }
-                    Err(e) => debug!("{} : handshake worker, error = {:?}", wg, e),
+                    Err(e) => {
+                        if let HandshakeError::InvalidMac1 = e {
+                            wg.invalid_mac1.fetch_add(1, Ordering::Relaxed);
+                        }
+                        debug!("{} : handshake worker, error = {:?}", wg, e)
+                    }
                 }
             }
             HandshakeJob::New(pk) => {
@@ -252,10 +278,11 @@ pub fn handshake_worker<T: Tun, B: UDP>(
                         wg, peer
                     );
                     let device = wg.peers.read();
-                    let _ = device.begin(&mut OsRng, &peer.pk).map(|msg| {
-                        let _ = peer.router.send_raw(&msg[..]).map_err(|e| {
-                            debug!("{} : handshake worker, failed to send handshake initiation, error = {}", wg, e)
-                        });
+                    let _ = device.begin(&mut wg.derive_rng(), &peer.pk).map(|msg| {
+                        match peer.router.send_raw(&msg[..]) {
+                            Ok(()) => wg.tx_messages.count(TYPE_INITIATION),
+                            Err(e) => debug!("{} : handshake worker, failed to send handshake initiation, error = {}", wg, e),
+                        }
                         peer.state.sent_handshake_initiation();
                     });
                     peer.handshake_queued.store(false, Ordering::SeqCst);